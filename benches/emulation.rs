@@ -0,0 +1,44 @@
+// Measures how fast the emulator runs cpu_instrs.gb headless, with and without the trace logger.
+// The CPU, PPU, timer and APU all run and the PPU renders into its frame buffer, the frames
+// are just never displayed. Sample output is off as nothing would play it.
+//
+//   cargo bench --bench emulation > /dev/null
+//
+// Criterion reports to stderr, the traced run writes a line per instruction to stdout and to a
+// log file in the temp directory. Expect on the order of 10^8 clocks per second from a release
+// build on a desktop machine, 15 to 30 times the 4.19 MHz of the hardware. The trace logger
+// formats and writes every instruction and brings that down to around 10^7.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rust_webpack_template::console::Console;
+
+const ROM_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/roms/cpu_instrs.gb");
+const TICKS_PER_FRAME: u32 = 70224;
+
+fn emulation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("emulation");
+    group.throughput(Throughput::Elements(TICKS_PER_FRAME as u64));
+
+    // Throughput is in clocks, an iteration runs one frame of them
+    let mut console = create_console(false);
+    group.bench_function("frame", |b| b.iter(|| console.execute_ticks(TICKS_PER_FRAME)));
+
+    let mut console = create_console(true);
+    console.set_trace_path(&std::env::temp_dir().join(format!("rustyboy-bench-{}.log", std::process::id())));
+    group.sample_size(10);
+    group.bench_function("frame with trace", |b| b.iter(|| console.execute_ticks(TICKS_PER_FRAME)));
+
+    group.finish();
+}
+
+fn create_console(trace: bool) -> Console {
+    let mut console = Console::new();
+    console.load_from_file(ROM_PATH).expect("unable to load the benchmark rom");
+    console.reset();
+    console.set_audio_enabled(false);
+    console.set_trace(trace);
+    return console;
+}
+
+criterion_group!(benches, emulation);
+criterion_main!(benches);
@@ -48,7 +48,7 @@ window.screen = new Screen(canvas);
 window.runRustyBoy = () => {
         setTimeout(function() {
                 if (runningFlag) requestAnimationFrame(window.runRustyBoy);
-                window.gameboy.execute_ticks(27756);
+                window.gameboy.execute_ticks(70224); // one frame of clocks
                 let frame = window.gameboy.get_frame();
                 let buffer = screen.createBuffer();
                 buffer.data.set(frame);
@@ -0,0 +1,22 @@
+// Minimal front-end for the WebConsole export, runs one frame per animation frame and
+// draws it on a 160x144 canvas.
+//
+//   import("../pkg/index.js").then(wasm => startWebConsole(wasm, canvas, new Uint8Array(rom)));
+
+export function startWebConsole(wasm, canvas, rom) {
+        const gameboy = wasm.WebConsole.new();
+        gameboy.load_rom(rom);
+
+        const context = canvas.getContext("2d");
+        const image = context.createImageData(160, 144);
+
+        const step = () => {
+                gameboy.run_frame();
+                image.data.set(gameboy.frame_buffer());
+                context.putImageData(image, 0, 0);
+                requestAnimationFrame(step);
+        };
+        requestAnimationFrame(step);
+
+        return gameboy;
+}
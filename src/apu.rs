@@ -0,0 +1,968 @@
+use crate::console::GameboyType;
+use serde_derive::{Serialize, Deserialize};
+
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+// Output rate of a new APU, front-ends set the rate of their sound device with set_sample_rate
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// The frame sequencer is clocked at 512Hz (4194304 / 512)
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+// https://gbdev.io/pandocs/#ff11-nr11-channel-1-sound-length-wave-pattern-duty-r-w
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+// https://gbdev.io/pandocs/#ff22-nr43-channel-4-polynomial-counter-r-w
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+pub const CHANNELS: [Channel; 4] = [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise];
+
+// Bits that always read back as 1, indexed from 0xFF10 to 0xFF2F
+// https://gbdev.io/pandocs/#sound-controller
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // ---- - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // ---- - NR44
+    0x00, 0x00, 0x70,             // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+
+    fn new() -> Self {
+        return Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn read_byte(&self) -> u8 {
+        return (self.initial_volume << 4) | (if self.increase { 0x08 } else { 0 }) | self.period;
+    }
+
+    fn write_byte(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 == 0x08;
+        self.period = value & 0x07;
+    }
+
+    // The DAC is powered as long as the upper 5 bits of NRx2 are not all zero
+    fn is_dac_enabled(&self) -> bool {
+        return self.initial_volume != 0 || self.increase;
+    }
+
+    // The timer treats a period of 0 as 8, the volume just never changes then
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.reload_timer();
+    }
+
+    fn clock(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer > 0 { return; }
+
+        self.reload_timer();
+        if self.period == 0 { return; }
+
+        if self.increase && self.volume < 0xF {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+// Channel 1 and 2 (channel 2 simply has no sweep unit)
+// https://gbdev.io/pandocs/#sound-channel-1-tone-sweep
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SquareChannel {
+    enabled: bool,
+    has_sweep: bool,
+
+    // NR10
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    sweep_shadow: u16,
+
+    // NR11
+    duty: u8,
+    length_counter: u16,
+
+    // NR12
+    envelope: Envelope,
+
+    // NR13 / NR14
+    frequency: u16,
+    length_enable: bool,
+
+    timer: u32,
+    duty_position: usize,
+}
+
+impl SquareChannel {
+
+    fn new(has_sweep: bool) -> Self {
+        return SquareChannel {
+            enabled: false,
+            has_sweep,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            sweep_shadow: 0,
+            duty: 0,
+            length_counter: 0,
+            envelope: Envelope::new(),
+            frequency: 0,
+            length_enable: false,
+            timer: 0,
+            duty_position: 0,
+        }
+    }
+
+    fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => (self.sweep_period << 4) | (if self.sweep_negate { 0x08 } else { 0 }) | self.sweep_shift,
+            1 => self.duty << 6,
+            2 => self.envelope.read_byte(),
+            3 => 0,
+            4 => if self.length_enable { 0x40 } else { 0 },
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 == 0x08;
+                self.sweep_shift = value & 0x07;
+            },
+            1 => {
+                self.duty = value >> 6;
+                self.length_counter = 64 - (value & 0x3F) as u16;
+            },
+            2 => {
+                self.envelope.write_byte(value);
+                if !self.envelope.is_dac_enabled() { self.enabled = false; }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+                self.length_enable = value & 0x40 == 0x40;
+                if value & 0x80 == 0x80 { self.trigger(); }
+            },
+            _ => {},
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        if self.length_counter == 0 { self.length_counter = 64; }
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.sweep_shadow = self.frequency;
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 { self.calculate_sweep(); }
+        }
+    }
+
+    fn calculate_sweep(&mut self) -> u16 {
+        let delta = self.sweep_shadow >> self.sweep_shift;
+        let frequency = if self.sweep_negate {
+            self.sweep_shadow.wrapping_sub(delta)
+        } else {
+            self.sweep_shadow + delta
+        };
+
+        if frequency > 2047 { self.enabled = false; }
+        return frequency;
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_timer > 0 { self.sweep_timer -= 1; }
+        if self.sweep_timer > 0 { return; }
+
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if !self.sweep_enabled || self.sweep_period == 0 { return; }
+
+        let frequency = self.calculate_sweep();
+        if frequency <= 2047 && self.sweep_shift != 0 {
+            self.sweep_shadow = frequency;
+            self.frequency = frequency;
+            self.calculate_sweep();
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 { self.enabled = false; }
+        }
+    }
+
+    fn execute_tick(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.duty_position = (self.duty_position + 1) & 0x07;
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.is_dac_enabled() { return None; }
+        if !self.enabled { return None; }
+        return Some(DUTY_TABLE[self.duty as usize][self.duty_position] * self.envelope.volume);
+    }
+}
+
+// Channel 3
+// https://gbdev.io/pandocs/#sound-channel-3-wave-output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length_counter: u16,
+    volume_code: u8,
+    frequency: u16,
+    length_enable: bool,
+    timer: u32,
+    position: usize,
+    ticks_since_fetch: u32, // Clocks since the channel last read a byte of the wave RAM
+    wave_ram: [u8; 0x10],
+}
+
+impl WaveChannel {
+
+    fn new() -> Self {
+        return WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            length_counter: 0,
+            volume_code: 0,
+            frequency: 0,
+            length_enable: false,
+            timer: 0,
+            position: 0,
+            ticks_since_fetch: u32::MAX,
+            wave_ram: [0; 0x10],
+        }
+    }
+
+    fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            0 => if self.dac_enabled { 0x80 } else { 0 },
+            1 => 0,
+            2 => self.volume_code << 5,
+            3 => 0,
+            4 => if self.length_enable { 0x40 } else { 0 },
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 == 0x80;
+                if !self.dac_enabled { self.enabled = false; }
+            },
+            1 => self.length_counter = 256 - value as u16,
+            2 => self.volume_code = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((value & 0x07) as u16) << 8);
+                self.length_enable = value & 0x40 == 0x40;
+                if value & 0x80 == 0x80 { self.trigger(); }
+            },
+            _ => {},
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        if self.length_counter == 0 { self.length_counter = 256; }
+        self.timer = (2048 - self.frequency as u32) * 2;
+        self.position = 0;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 { self.enabled = false; }
+        }
+    }
+
+    fn execute_tick(&mut self) {
+        self.ticks_since_fetch = self.ticks_since_fetch.saturating_add(1);
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) & 0x1F;
+            self.ticks_since_fetch = 0;
+        }
+    }
+
+    // https://gbdev.io/pandocs/#ff30-ff3f-wave-pattern-ram
+    // While the channel plays, every address of the wave RAM reaches the byte being played.
+    // The DMG only connects it in the M-cycle the channel fetched that byte, at any other
+    // time reads give 0xFF and writes are lost.
+    fn wave_ram_index(&self, address: u16, model: GameboyType) -> Option<usize> {
+        if !self.enabled { return Some((address - 0xFF30) as usize); }
+        if model == GameboyType::CLASSIC && self.ticks_since_fetch >= 4 { return None; }
+        return Some(self.position / 2);
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.dac_enabled { return None; }
+        if !self.enabled { return None; }
+
+        // Each byte holds two 4 bit samples, upper nibble first
+        let sample = self.wave_ram[self.position / 2];
+        let sample = if self.position & 0x01 == 0 { sample >> 4 } else { sample & 0x0F };
+
+        return Some(match self.volume_code {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        });
+    }
+}
+
+// Channel 4
+// https://gbdev.io/pandocs/#sound-channel-4-noise
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct NoiseChannel {
+    enabled: bool,
+    length_counter: u16,
+    envelope: Envelope,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    length_enable: bool,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+
+    fn new() -> Self {
+        return NoiseChannel {
+            enabled: false,
+            length_counter: 0,
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            length_enable: false,
+            timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    fn read_byte(&self, register: u16) -> u8 {
+        match register {
+            1 => 0,
+            2 => self.envelope.read_byte(),
+            3 => (self.clock_shift << 4) | (if self.width_mode { 0x08 } else { 0 }) | self.divisor_code,
+            4 => if self.length_enable { 0x40 } else { 0 },
+            _ => 0,
+        }
+    }
+
+    fn write_byte(&mut self, register: u16, value: u8) {
+        match register {
+            1 => self.length_counter = 64 - (value & 0x3F) as u16,
+            2 => {
+                self.envelope.write_byte(value);
+                if !self.envelope.is_dac_enabled() { self.enabled = false; }
+            },
+            3 => {
+                self.clock_shift = value >> 4;
+                self.width_mode = value & 0x08 == 0x08;
+                self.divisor_code = value & 0x07;
+            },
+            4 => {
+                self.length_enable = value & 0x40 == 0x40;
+                if value & 0x80 == 0x80 { self.trigger(); }
+            },
+            _ => {},
+        }
+    }
+
+    fn period(&self) -> u32 {
+        return NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift;
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.is_dac_enabled();
+        if self.length_counter == 0 { self.length_counter = 64; }
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn clock_length(&mut self) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 { self.enabled = false; }
+        }
+    }
+
+    fn execute_tick(&mut self) {
+        if self.timer > 0 { self.timer -= 1; }
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            let bit = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.width_mode {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> Option<u8> {
+        if !self.envelope.is_dac_enabled() { return None; }
+        if !self.enabled { return None; }
+        return Some(if self.lfsr & 0x01 == 0 { self.envelope.volume } else { 0 });
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Apu {
+    enabled: bool,
+
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+
+    // 0xFF24 (NR50) and 0xFF25 (NR51)
+    master_volume: u8,
+    panning: u8,
+
+    frame_sequencer_clock: u32,
+    frame_sequencer_step: u8,
+
+    sample_clock: u32,
+    // Sum of the left and right output of every clock since the last sample, averaged into it
+    #[serde(skip)]
+    sample_sum: [f32; 2],
+    #[serde(skip)]
+    sample_ticks: u32,
+    #[serde(skip, default = "default_sample_rate")]
+    sample_rate: u32,
+    #[serde(skip)]
+    samples: Vec<f32>,
+    // Front-ends without audio can turn off mixing altogether
+    #[serde(skip, default = "default_sample_output")]
+    sample_output: bool,
+    // Channels left out of the mix while debugging, they keep running and show up in NR52
+    #[serde(skip, default = "default_channels_enabled")]
+    channels_enabled: [bool; 4],
+    model: GameboyType,
+}
+
+fn default_sample_output() -> bool {
+    return true;
+}
+
+fn default_sample_rate() -> u32 {
+    return SAMPLE_RATE;
+}
+
+fn default_channels_enabled() -> [bool; 4] {
+    return [true; 4];
+}
+
+// https://gbdev.io/pandocs/#sound-controller
+impl Apu {
+
+    pub fn new(sample_rate: u32) -> Self {
+        return Apu {
+            enabled: false,
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            master_volume: 0,
+            panning: 0,
+            frame_sequencer_clock: 0,
+            frame_sequencer_step: 0,
+            sample_clock: 0,
+            sample_sum: [0.0; 2],
+            sample_ticks: 0,
+            sample_rate,
+            samples: Vec::new(),
+            sample_output: true,
+            channels_enabled: default_channels_enabled(),
+            model: GameboyType::CLASSIC,
+        }
+    }
+
+    pub fn reset(&mut self, model: GameboyType) {
+        let wave_ram = self.ch3.wave_ram;
+
+        self.ch1 = SquareChannel::new(true);
+        self.ch2 = SquareChannel::new(false);
+        self.ch3 = WaveChannel::new();
+        self.ch3.wave_ram = wave_ram;
+        self.ch4 = NoiseChannel::new();
+        self.enabled = false;
+        self.master_volume = 0;
+        self.panning = 0;
+        self.frame_sequencer_clock = 0;
+        self.frame_sequencer_step = 0;
+        self.sample_clock = 0;
+        self.samples.clear();
+        self.model = model;
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        let value = match address {
+            0xFF10 ..= 0xFF14 => self.ch1.read_byte(address - 0xFF10),
+            0xFF15 ..= 0xFF19 => self.ch2.read_byte(address - 0xFF15),
+            0xFF1A ..= 0xFF1E => self.ch3.read_byte(address - 0xFF1A),
+            0xFF1F ..= 0xFF23 => self.ch4.read_byte(address - 0xFF1F),
+            0xFF24 => self.master_volume,
+            0xFF25 => self.panning,
+            0xFF26 => {
+                (if self.enabled { 0x80 } else { 0 }) |
+                    (if self.ch4.enabled { 0x08 } else { 0 }) |
+                    (if self.ch3.enabled { 0x04 } else { 0 }) |
+                    (if self.ch2.enabled { 0x02 } else { 0 }) |
+                    (if self.ch1.enabled { 0x01 } else { 0 })
+            },
+            0xFF27 ..= 0xFF2F => 0,
+            0xFF30 ..= 0xFF3F => return match self.ch3.wave_ram_index(address, self.model) {
+                Some(index) => self.ch3.wave_ram[index],
+                None => 0xFF,
+            },
+            _ => panic!("{:04X}", address),
+        };
+
+        return value | READ_MASKS[(address - 0xFF10) as usize];
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        // While the APU is powered off only NR52 and the wave RAM are writable, a DMG also
+        // keeps taking the length counters in NRx1 but not the duty of channel 1 and 2
+        if !self.enabled && address < 0xFF26 {
+            if self.model == GameboyType::COLOR { return; }
+            match address {
+                0xFF11 => self.ch1.write_byte(1, value & 0x3F),
+                0xFF16 => self.ch2.write_byte(1, value & 0x3F),
+                0xFF1B => self.ch3.write_byte(1, value),
+                0xFF20 => self.ch4.write_byte(1, value),
+                _ => {},
+            }
+            return;
+        }
+
+        match address {
+            0xFF10 ..= 0xFF14 => self.ch1.write_byte(address - 0xFF10, value),
+            0xFF15 ..= 0xFF19 => self.ch2.write_byte(address - 0xFF15, value),
+            0xFF1A ..= 0xFF1E => self.ch3.write_byte(address - 0xFF1A, value),
+            0xFF1F ..= 0xFF23 => self.ch4.write_byte(address - 0xFF1F, value),
+            0xFF24 => self.master_volume = value,
+            0xFF25 => self.panning = value,
+            0xFF26 => {
+                let enabled = value & 0x80 == 0x80;
+                if self.enabled && !enabled {
+                    // Powering off clears every register except the wave RAM
+                    let samples = std::mem::take(&mut self.samples);
+                    self.reset(self.model);
+                    self.samples = samples;
+                } else if !self.enabled && enabled {
+                    self.frame_sequencer_step = 0;
+                }
+                self.enabled = enabled;
+            },
+            0xFF27 ..= 0xFF2F => {},
+            0xFF30 ..= 0xFF3F => {
+                if let Some(index) = self.ch3.wave_ram_index(address, self.model) {
+                    self.ch3.wave_ram[index] = value;
+                }
+            },
+            _ => panic!("{:04X}", address),
+        }
+    }
+
+    pub fn execute_ticks(&mut self, ticks: u32) {
+        for _i in 0 .. ticks {
+            self.execute_tick();
+        }
+    }
+
+    pub fn execute_tick(&mut self) {
+        if self.enabled {
+            self.frame_sequencer_clock += 1;
+            if self.frame_sequencer_clock >= FRAME_SEQUENCER_PERIOD {
+                self.frame_sequencer_clock = 0;
+                self.clock_frame_sequencer();
+            }
+
+            self.ch1.execute_tick();
+            self.ch2.execute_tick();
+            self.ch3.execute_tick();
+            self.ch4.execute_tick();
+        }
+
+        if !self.sample_output { return; }
+
+        // Every output sample is the average of the clocks since the previous one, which filters
+        // out most of what is too high to be represented at the output rate
+        let (left, right) = self.mix();
+        self.sample_sum[0] += left;
+        self.sample_sum[1] += right;
+        self.sample_ticks += 1;
+
+        // The fraction of an output sample each clock takes is sample_rate / CPU_FREQUENCY
+        self.sample_clock += self.sample_rate;
+        if self.sample_clock >= CPU_FREQUENCY {
+            self.sample_clock -= CPU_FREQUENCY;
+            let ticks = self.sample_ticks as f32;
+            self.samples.push(self.sample_sum[0] / ticks);
+            self.samples.push(self.sample_sum[1] / ticks);
+            self.sample_sum = [0.0; 2];
+            self.sample_ticks = 0;
+        }
+    }
+
+    // https://gbdev.io/pandocs/#frame-sequencer
+    // Step   Length Ctr  Vol Env     Sweep
+    // ---------------------------------------
+    // 0      Clock       -           -
+    // 1      -           -           -
+    // 2      Clock       -           Clock
+    // 3      -           -           -
+    // 4      Clock       -           -
+    // 5      -           -           -
+    // 6      Clock       -           Clock
+    // 7      -           Clock       -
+    fn clock_frame_sequencer(&mut self) {
+        match self.frame_sequencer_step {
+            0 | 4 => {
+                self.clock_lengths();
+            },
+            2 | 6 => {
+                self.clock_lengths();
+                self.ch1.clock_sweep();
+            },
+            7 => {
+                self.ch1.envelope.clock();
+                self.ch2.envelope.clock();
+                self.ch4.envelope.clock();
+            },
+            _ => {},
+        }
+
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) & 0x07;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.ch1.clock_length();
+        self.ch2.clock_length();
+        self.ch3.clock_length();
+        self.ch4.clock_length();
+    }
+
+    // https://gbdev.io/pandocs/#ff25-nr51-selection-of-sound-output-terminal-r-w
+    // NR51 routes each channel to the left and right terminals, NR50 sets their volumes
+    fn mix(&self) -> (f32, f32) {
+        if !self.enabled { return (0.0, 0.0); }
+
+        let outputs = [self.ch1.output(), self.ch2.output(), self.ch3.output(), self.ch4.output()];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (index, output) in outputs.iter().enumerate() {
+            // A disabled channel or DAC outputs nothing, an enabled one maps 0x0 - 0xF to -1.0 - 1.0
+            if !self.channels_enabled[index] { continue; }
+            let analog = match output {
+                Some(digital) => (*digital as f32 / 7.5) - 1.0,
+                None => continue,
+            };
+
+            if self.panning & (0x10 << index) != 0 { left += analog; }
+            if self.panning & (0x01 << index) != 0 { right += analog; }
+        }
+
+        let left_volume = (((self.master_volume >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((self.master_volume & 0x07) + 1) as f32 / 8.0;
+
+        return (left * left_volume / 4.0, right * right_volume / 4.0);
+    }
+
+    pub fn set_sample_output(&mut self, enabled: bool) {
+        self.sample_output = enabled;
+        if !enabled {
+            self.samples.clear();
+            self.sample_sum = [0.0; 2];
+            self.sample_ticks = 0;
+        }
+    }
+
+    // Output sample pairs per second, 44100 or 48000 to match the sound device
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    pub fn sample_output(&self) -> bool {
+        return self.sample_output;
+    }
+
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.channels_enabled[channel as usize] = enabled;
+    }
+
+    pub fn channel_enabled(&self, channel: Channel) -> bool {
+        return self.channels_enabled[channel as usize];
+    }
+
+    // Interleaved left and right samples at sample_rate pairs a second
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        return std::mem::take(&mut self.samples);
+    }
+
+    // The 32 samples of channel 3, two per byte with the upper nibble first
+    pub fn wave_ram(&self) -> &[u8; 0x10] {
+        return &self.ch3.wave_ram;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERN: [u8; 0x10] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xFE, 0xDC, 0xBA, 0x98, 0x76, 0x54, 0x32, 0x10];
+
+    // Plays the pattern at the highest frequency, moving on to the next sample every 2 clocks
+    fn create_playing_apu(model: GameboyType) -> Apu {
+        let mut apu = Apu::new(SAMPLE_RATE);
+        apu.reset(model);
+        for (index, &byte) in PATTERN.iter().enumerate() {
+            apu.write_byte(0xFF30 + index as u16, byte);
+        }
+        apu.write_byte(0xFF26, 0x80);
+        apu.write_byte(0xFF1A, 0x80);
+        apu.write_byte(0xFF1D, 0xFF);
+        apu.write_byte(0xFF1E, 0x87);
+        return apu;
+    }
+
+    #[test]
+    fn wave_ram_reads_back_while_stopped() {
+        let mut apu = Apu::new(SAMPLE_RATE);
+        for (index, &byte) in PATTERN.iter().enumerate() {
+            apu.write_byte(0xFF30 + index as u16, byte);
+        }
+
+        assert_eq!(apu.wave_ram(), &PATTERN);
+        for (index, &byte) in PATTERN.iter().enumerate() {
+            assert_eq!(apu.read_byte(0xFF30 + index as u16), byte);
+        }
+    }
+
+    #[test]
+    fn dmg_reaches_the_played_byte_only_as_it_is_fetched() {
+        let mut apu = create_playing_apu(GameboyType::CLASSIC);
+
+        // Right after a fetch any address gives the byte being played, position 5 is in byte 2
+        apu.execute_ticks(5 * 2);
+        assert_eq!(apu.ch3.position, 5);
+        assert_eq!(apu.read_byte(0xFF30), PATTERN[2]);
+        assert_eq!(apu.read_byte(0xFF3F), PATTERN[2]);
+        apu.write_byte(0xFF3F, 0x99);
+        assert_eq!(apu.wave_ram()[2], 0x99);
+
+        // Between fetches the DMG wave RAM is out of reach
+        apu.ch3.frequency = 0;
+        apu.ch3.timer = 100;
+        apu.execute_ticks(10);
+        assert_eq!(apu.read_byte(0xFF30), 0xFF);
+        apu.write_byte(0xFF30, 0x00);
+        assert_eq!(apu.wave_ram()[0], PATTERN[0]);
+    }
+
+    #[test]
+    fn cgb_always_reaches_the_played_byte() {
+        let mut apu = create_playing_apu(GameboyType::COLOR);
+        apu.ch3.frequency = 0;
+        apu.ch3.position = 6;
+        apu.ch3.timer = 100;
+        apu.execute_ticks(10);
+
+        assert_eq!(apu.read_byte(0xFF30), PATTERN[3]);
+        apu.write_byte(0xFF35, 0x42);
+        assert_eq!(apu.wave_ram()[3], 0x42);
+    }
+
+    #[test]
+    fn muted_channel_keeps_running_silently() {
+        let mut apu = create_playing_apu(GameboyType::CLASSIC);
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF25, 0x44); // Only channel 3, on both sides
+        apu.write_byte(0xFF1B, 0x00); // 256 steps of length
+        apu.write_byte(0xFF1E, 0xC7); // Restart with the length counter on
+
+        apu.execute_ticks(FRAME_SEQUENCER_PERIOD * 2);
+        assert!(apu.drain_samples().iter().any(|&sample| sample != 0.0));
+
+        apu.set_channel_enabled(Channel::Wave, false);
+        let length = apu.ch3.length_counter;
+        apu.execute_ticks(FRAME_SEQUENCER_PERIOD * 4);
+
+        // The first pair still averages in clocks from before
+        let samples = apu.drain_samples();
+        assert!(samples.len() > 2);
+        assert!(samples[2 ..].iter().all(|&sample| sample == 0.0));
+        assert_eq!(apu.ch3.length_counter, length - 2);
+        assert_eq!(apu.read_byte(0xFF26) & 0x04, 0x04);
+
+        apu.set_channel_enabled(Channel::Wave, true);
+        apu.execute_ticks(FRAME_SEQUENCER_PERIOD);
+        assert!(apu.drain_samples().iter().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn nr51_pans_channels() {
+        let mut apu = Apu::new(SAMPLE_RATE);
+        apu.write_byte(0xFF26, 0x80);
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF25, 0x10); // Channel 1 to the left only
+        apu.write_byte(0xFF11, 0x80); // 50% duty
+        apu.write_byte(0xFF12, 0xF0); // Full volume
+        apu.write_byte(0xFF13, 0x00);
+        apu.write_byte(0xFF14, 0x87);
+        apu.execute_ticks(CPU_FREQUENCY / 100);
+
+        let samples = apu.drain_samples();
+        assert_eq!(samples.len() % 2, 0);
+        assert!(samples.chunks(2).any(|pair| pair[0] != 0.0));
+        assert!(samples.chunks(2).all(|pair| pair[1] == 0.0));
+
+        // NR50 scales each side on its own
+        apu.write_byte(0xFF25, 0x11);
+        apu.write_byte(0xFF24, 0x73);
+        apu.execute_ticks(CPU_FREQUENCY / 100);
+        let samples = apu.drain_samples();
+        for pair in samples.chunks(2).skip(1) {
+            assert!((pair[1] - pair[0] / 2.0).abs() < 1e-6, "{:?}", pair);
+        }
+    }
+
+    #[test]
+    fn dmg_takes_length_writes_while_powered_off() {
+        let mut apu = Apu::new(SAMPLE_RATE);
+        apu.reset(GameboyType::CLASSIC);
+        apu.write_byte(0xFF11, 0xFF);
+        apu.write_byte(0xFF1B, 0x10);
+        apu.write_byte(0xFF12, 0xF0);
+
+        assert_eq!(apu.ch1.length_counter, 1);
+        assert_eq!(apu.ch1.duty, 0);
+        assert_eq!(apu.ch3.length_counter, 256 - 0x10);
+        assert_eq!(apu.read_byte(0xFF12), 0x00);
+
+        let mut apu = Apu::new(SAMPLE_RATE);
+        apu.reset(GameboyType::COLOR);
+        apu.write_byte(0xFF11, 0xFF);
+        assert_eq!(apu.ch1.length_counter, 0);
+    }
+
+    #[test]
+    fn envelope_period_0_reloads_the_timer_with_8() {
+        let mut envelope = Envelope::new();
+        envelope.write_byte(0xF0); // Full volume, decreasing, period 0
+        envelope.trigger();
+        assert_eq!(envelope.timer, 8);
+
+        for _ in 0 .. 3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 0xF);
+
+        // A period written later takes effect once the running timer expires
+        envelope.write_byte(0xF1);
+        for _ in 0 .. 4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume, 0xF);
+        envelope.clock();
+        assert_eq!(envelope.volume, 0xE);
+        assert_eq!(envelope.timer, 1);
+    }
+
+    #[test]
+    fn disabled_channel_with_its_dac_on_is_silent() {
+        let mut apu = Apu::new(SAMPLE_RATE);
+        apu.write_byte(0xFF26, 0x80);
+        apu.write_byte(0xFF24, 0x77);
+        apu.write_byte(0xFF25, 0x22); // Channel 2 on both sides
+        apu.write_byte(0xFF17, 0xF0); // DAC on, never triggered
+
+        assert_eq!(apu.mix(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn sample_rate_sets_the_samples_per_frame() {
+        for &rate in [44_100, 48_000].iter() {
+            let mut apu = Apu::new(rate);
+            apu.write_byte(0xFF26, 0x80);
+            apu.write_byte(0xFF24, 0x77);
+            apu.write_byte(0xFF25, 0xFF);
+            apu.write_byte(0xFF12, 0xF0);
+            apu.write_byte(0xFF14, 0x87);
+
+            // 70224 clocks a frame
+            let expected = 70224.0 * rate as f64 / CPU_FREQUENCY as f64;
+            for _ in 0 .. 10 {
+                apu.execute_ticks(70224);
+                let pairs = apu.drain_samples().len() / 2;
+                assert!((pairs as f64 - expected).abs() < 1.0, "{} {}", rate, pairs);
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// Destinations for the stereo samples the APU produces at its sample rate, left and right
+// interleaved. The console hands every frame worth of samples to its sink, see
+// Console::set_audio_sink.
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[f32]);
+
+    // Drops what was pushed but not played yet, the sound stops right away instead of finishing
+    // the queue. Called when fast forward starts.
+    fn discard_queued(&mut self) {}
+}
+
+// Converts an interleaved stereo stream from one sample rate to another with linear interpolation
+#[allow(unused)]
+pub struct Resampler {
+    step: f64, // Input pairs per output pair
+    position: f64, // Position of the next output pair, relative to previous
+    previous: [f32; 2],
+}
+
+#[allow(unused)]
+impl Resampler {
+
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        return Resampler {
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for pair in input.chunks_exact(2) {
+            while self.position < 1.0 {
+                let fraction = self.position as f32;
+                for channel in 0 .. 2 {
+                    output.push(self.previous[channel] + (pair[channel] - self.previous[channel]) * fraction);
+                }
+                self.position += self.step;
+            }
+
+            self.position -= 1.0;
+            self.previous = [pair[0], pair[1]];
+        }
+    }
+}
+
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+pub use self::native::{CpalSink, OUTPUT_RATE};
+
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+mod native {
+    use super::{AudioSink, Resampler};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    // The APU is set to this rate as well, the resampler only steps in if it is not
+    pub const OUTPUT_RATE: u32 = 48_000;
+    const OUTPUT_CHANNELS: u16 = 2;
+    // Samples beyond this are dropped, about 100ms so the sound never lags far behind the picture
+    const MAX_BUFFERED: usize = OUTPUT_RATE as usize * OUTPUT_CHANNELS as usize / 10;
+
+    // Plays through the default output device. The stream callback runs on its own thread and
+    // takes samples from a shared queue, playing silence whenever the queue runs dry.
+    pub struct CpalSink {
+        queue: Arc<Mutex<VecDeque<f32>>>,
+        resampler: Resampler,
+        resampled: Vec<f32>,
+        _stream: cpal::Stream,
+    }
+
+    impl CpalSink {
+
+        pub fn new(input_rate: u32) -> Result<Self, String> {
+            let host = cpal::default_host();
+            let device = host.default_output_device().ok_or("no output device available")?;
+
+            let config = cpal::StreamConfig {
+                channels: OUTPUT_CHANNELS,
+                sample_rate: cpal::SampleRate(OUTPUT_RATE),
+                buffer_size: cpal::BufferSize::Default,
+            };
+
+            let queue = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED)));
+            let stream_queue = Arc::clone(&queue);
+
+            let stream = device.build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let mut queue = stream_queue.lock().unwrap();
+                    for sample in data.iter_mut() {
+                        *sample = queue.pop_front().unwrap_or(0.0);
+                    }
+                },
+                |error| eprintln!("audio stream error: {}", error),
+                None,
+            ).map_err(|error| error.to_string())?;
+            stream.play().map_err(|error| error.to_string())?;
+
+            return Ok(CpalSink {
+                queue,
+                resampler: Resampler::new(input_rate, OUTPUT_RATE),
+                resampled: Vec::new(),
+                _stream: stream,
+            });
+        }
+    }
+
+    impl AudioSink for CpalSink {
+        fn push_samples(&mut self, samples: &[f32]) {
+            self.resampled.clear();
+            self.resampler.process(samples, &mut self.resampled);
+
+            let mut queue = self.queue.lock().unwrap();
+            queue.extend(self.resampled.iter());
+
+            let excess = queue.len().saturating_sub(MAX_BUFFERED);
+            queue.drain(.. excess);
+        }
+
+        fn discard_queued(&mut self) {
+            self.queue.lock().unwrap().clear();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use self::web::WebAudioSink;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use super::AudioSink;
+    use wasm_bindgen::JsValue;
+    use web_sys::AudioContext;
+
+    // Delay before the first buffer plays, also used to recover after an underrun
+    const LATENCY: f64 = 0.05;
+
+    // Queues every batch of samples as a buffer source starting where the previous one ends
+    pub struct WebAudioSink {
+        context: AudioContext,
+        sample_rate: u32, // Of the context, the console has to produce samples at this rate
+        next_start: f64,
+    }
+
+    impl WebAudioSink {
+
+        pub fn new() -> Result<Self, JsValue> {
+            let context = AudioContext::new()?;
+            return Ok(WebAudioSink {
+                sample_rate: context.sample_rate() as u32,
+                context,
+                next_start: 0.0,
+            });
+        }
+
+        pub fn sample_rate(&self) -> u32 {
+            return self.sample_rate;
+        }
+
+        fn schedule(&mut self, samples: &[f32]) -> Result<(), JsValue> {
+            let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+            let right: Vec<f32> = samples.iter().skip(1).step_by(2).copied().collect();
+            let buffer = self.context.create_buffer(2, left.len() as u32, self.sample_rate as f32)?;
+            buffer.copy_to_channel(&left, 0)?;
+            buffer.copy_to_channel(&right, 1)?;
+
+            let source = self.context.create_buffer_source()?;
+            source.set_buffer(Some(&buffer));
+            source.connect_with_audio_node(&self.context.destination())?;
+
+            // Fell behind, there is a gap of silence and playback restarts from now
+            let now = self.context.current_time();
+            if self.next_start < now {
+                self.next_start = now + LATENCY;
+            }
+
+            source.start_with_when(self.next_start)?;
+            self.next_start += left.len() as f64 / self.sample_rate as f64;
+            return Ok(());
+        }
+    }
+
+    impl AudioSink for WebAudioSink {
+        fn push_samples(&mut self, samples: &[f32]) {
+            if samples.is_empty() { return; }
+
+            if let Err(error) = self.schedule(samples) {
+                web_sys::console::error_1(&error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resampler_converts_rate() {
+        let mut resampler = Resampler::new(44_100, 48_000);
+        let mut output = Vec::new();
+
+        for _ in 0 .. 10 {
+            resampler.process(&[0.5; 4410 * 2], &mut output);
+        }
+
+        assert!((output.len() as i64 - 48_000 * 2).abs() <= 2, "{}", output.len());
+        // Only the pairs before the first input ramp up from silence
+        assert!(output[4 ..].iter().all(|&sample| (sample - 0.5).abs() < 1e-6));
+    }
+
+    #[test]
+    fn resampler_interpolates_between_samples() {
+        let mut resampler = Resampler::new(1, 2);
+        let mut output = Vec::new();
+        resampler.process(&[1.0, -1.0, 0.0, 0.0], &mut output);
+
+        assert_eq!(output, vec![0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 0.5, -0.5]);
+    }
+}
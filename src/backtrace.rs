@@ -0,0 +1,88 @@
+use crate::cpu::Registers;
+use std::fmt;
+
+pub const BACKTRACE_LENGTH: usize = 256;
+
+// An instruction about to be executed and the registers before it ran
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub opcode: u8,
+    pub registers: Registers,
+}
+
+impl fmt::Display for TraceEntry {
+    // PC:0150 OP:3E A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = &self.registers;
+        return write!(f, "PC:{:04X} OP:{:02X} A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X}",
+                      r.pc, self.opcode, r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp);
+    }
+}
+
+// The last BACKTRACE_LENGTH instructions, recording one is a copy into a preallocated slot
+pub struct Backtrace {
+    entries: Vec<TraceEntry>,
+    next: usize, // Slot the next entry goes to, the oldest entry once the buffer is full
+}
+
+#[allow(unused)]
+impl Backtrace {
+
+    pub fn new() -> Self {
+        return Backtrace {
+            entries: Vec::with_capacity(BACKTRACE_LENGTH),
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, entry: TraceEntry) {
+        if self.entries.len() < BACKTRACE_LENGTH {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+        }
+        self.next = (self.next + 1) % BACKTRACE_LENGTH;
+    }
+
+    // Oldest first
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        if self.entries.len() < BACKTRACE_LENGTH { return self.entries.clone(); }
+        return [&self.entries[self.next ..], &self.entries[.. self.next]].concat();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn entry(pc: u16) -> TraceEntry {
+        let mut cpu = Cpu::new();
+        cpu.pc = pc;
+        return TraceEntry { opcode: 0x00, registers: cpu.registers() };
+    }
+
+    #[test]
+    fn keeps_the_newest_entries_in_order() {
+        let mut backtrace = Backtrace::new();
+        backtrace.record(entry(1));
+        backtrace.record(entry(2));
+        assert_eq!(backtrace.entries().iter().map(|entry| entry.registers.pc).collect::<Vec<_>>(), vec![1, 2]);
+
+        for pc in 3 ..= 300 {
+            backtrace.record(entry(pc));
+        }
+        let pcs: Vec<u16> = backtrace.entries().iter().map(|entry| entry.registers.pc).collect();
+        assert_eq!(pcs.len(), BACKTRACE_LENGTH);
+        assert_eq!(pcs[0], 300 - BACKTRACE_LENGTH as u16 + 1);
+        assert_eq!(pcs[BACKTRACE_LENGTH - 1], 300);
+
+        backtrace.clear();
+        assert!(backtrace.entries().is_empty());
+    }
+}
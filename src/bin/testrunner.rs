@@ -0,0 +1,82 @@
+// Runs a test rom headless and reports its result. Blargg's test roms end their serial output
+// with "Passed" or "Failed", Mooneye's load a register pattern and execute LD B,B.
+//
+//   cargo run --release --bin testrunner -- roms/cpu_instrs.gb [cycle cap]
+//
+// Built with --features coverage it also lists the opcodes the rom never executed.
+
+use rust_webpack_template::console::{Console, MooneyeResult};
+use std::process;
+
+// One frame worth of clocks between checks of the serial output
+const TICKS_PER_CHECK: u32 = 70224;
+// About 4 minutes of emulated time
+const DEFAULT_CYCLE_CAP: u64 = 1_000_000_000;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <rom> [cycle cap]", args[0]);
+        process::exit(2);
+    }
+
+    let rom_path = &args[1];
+    let cycle_cap = match args.get(2) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(cap) => cap,
+            Err(_) => {
+                eprintln!("error: invalid cycle cap '{}'", value);
+                process::exit(2);
+            },
+        },
+        None => DEFAULT_CYCLE_CAP,
+    };
+
+    let mut console = Console::new();
+    if let Err(error) = console.load_from_file(rom_path) {
+        eprintln!("error: unable to load '{}': {}", rom_path, error);
+        process::exit(2);
+    }
+    console.reset();
+    console.set_audio_enabled(false);
+
+    let mut cycles: u64 = 0;
+    while cycles < cycle_cap {
+        let mooneye_result = console.run_until_mooneye_result(TICKS_PER_CHECK);
+        cycles += TICKS_PER_CHECK as u64;
+
+        match mooneye_result {
+            Some(MooneyeResult::Passed) => { println!("Passed"); finish(&console, 0); },
+            Some(MooneyeResult::Failed) => { println!("Failed"); finish(&console, 1); },
+            None => {},
+        }
+
+        let output = console.serial_output();
+        if output.contains("Passed") {
+            println!("{}", output);
+            finish(&console, 0);
+        }
+        if output.contains("Failed") {
+            println!("{}", output);
+            finish(&console, 1);
+        }
+    }
+
+    println!("{}", console.serial_output());
+    eprintln!("error: no result after {} cycles", cycles);
+    finish(&console, 1);
+}
+
+#[allow(unused_variables)]
+fn finish(console: &Console, code: i32) -> ! {
+    #[cfg(feature = "coverage")]
+    {
+        let opcodes: Vec<String> = console.coverage_report().iter().map(|opcode| format!("{:02X}", opcode)).collect();
+        if opcodes.is_empty() {
+            eprintln!("every opcode was executed");
+        } else {
+            eprintln!("{} opcodes not executed: {}", opcodes.len(), opcodes.join(" "));
+        }
+    }
+    process::exit(code);
+}
@@ -4,10 +4,27 @@ extern crate wasm_bindgen;
 use std::fs;
 use std::convert::TryInto;
 use std::fmt;
+use std::io::{self, Read};
 use crate::console::GameboyType;
 use wasm_bindgen::prelude::*;
+use serde_derive::{Serialize, Deserialize};
 
+pub const HEADER_INDEX_FOR_TITLE: usize = 0x0134;
+pub const HEADER_INDEX_FOR_CGB_FLAG: usize = 0x0143;
+pub const HEADER_INDEX_FOR_SGB_FLAG: usize = 0x0146;
 pub const HEADER_INDEX_FOR_CARTRIDGE_TYPE: usize = 0x0147;
+pub const HEADER_INDEX_FOR_ROM_SIZE: usize = 0x0148;
+pub const HEADER_INDEX_FOR_RAM_SIZE: usize = 0x0149;
+pub const HEADER_INDEX_FOR_DESTINATION_CODE: usize = 0x014A;
+pub const HEADER_INDEX_FOR_VERSION: usize = 0x014C;
+pub const HEADER_INDEX_FOR_CHECKSUM: usize = 0x014D;
+pub const HEADER_INDEX_FOR_GLOBAL_CHECKSUM: usize = 0x014E;
+pub const HEADER_END: usize = 0x0150;
+
+const RTC_TRAILER_SIZE: usize = 48;
+// https://gbdev.io/pandocs/#mbc2
+const MBC2_RAM_SIZE: usize = 0x200;
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
 
 // Logging
 #[wasm_bindgen]
@@ -30,26 +47,359 @@ extern "C" {
 
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CartridgeType {
     None,
     MBC0,
     MBC1,
-    //MBC2,
+    MBC2,
     MBC3,
     //MBC4,
     MBC5,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    FileNotFound,
+    HeaderTooShort(usize),
+    UnsupportedCartridge(u8),
+    InvalidBootRomSize(usize),
+    InvalidArchive,
+    NoRomInArchive,
+    MultipleRomsInArchive(usize),
+    InvalidRomData,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::FileNotFound => write!(f, "rom file not found"),
+            LoadError::HeaderTooShort(size) => write!(f, "rom is only {} bytes, too short to hold a cartridge header", size),
+            LoadError::UnsupportedCartridge(cartridge_type) => write!(f, "cartridge type {:#04x} is not supported", cartridge_type),
+            LoadError::InvalidBootRomSize(size) => write!(f, "boot rom is {} bytes, expected 256", size),
+            LoadError::InvalidArchive => write!(f, "zip archive is damaged"),
+            LoadError::NoRomInArchive => write!(f, "zip archive holds no .gb or .gbc file"),
+            LoadError::MultipleRomsInArchive(count) => write!(f, "zip archive holds {} .gb or .gbc files, expected one", count),
+            LoadError::InvalidRomData => write!(f, "rom data is not an array of bytes"),
+        }
+    }
+}
+
+impl From<LoadError> for JsValue {
+    fn from(error: LoadError) -> Self {
+        return JsValue::from_str(&error.to_string());
+    }
+}
+
+// https://gbdev.io/pandocs/#the-cartridge-header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cartridge_type: u8,
+    pub rom_size: usize, // bytes
+    pub ram_size: usize, // bytes
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub destination_code: u8, // 0x00 for Japan
+    pub version: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    // Only a few emulators and no real hardware check this one
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+
+    pub fn parse(rom: &[u8]) -> Self {
+        if rom.len() < HEADER_END { return CartridgeHeader::default(); }
+
+        // Newer games use the last title bytes for the CGB flag and manufacturer code, the
+        // title then ends at the first 0 byte
+        let cgb_flag = rom[HEADER_INDEX_FOR_CGB_FLAG];
+        let title_end = if cgb_flag & 0x80 != 0 { HEADER_INDEX_FOR_CGB_FLAG } else { HEADER_INDEX_FOR_CGB_FLAG + 1 };
+        let title: String = rom[HEADER_INDEX_FOR_TITLE .. title_end].iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect();
+
+        // https://gbdev.io/pandocs/#_014d-header-checksum
+        let checksum = rom[HEADER_INDEX_FOR_TITLE .. HEADER_INDEX_FOR_CHECKSUM].iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+
+        // https://gbdev.io/pandocs/#_014e-014f-global-checksum
+        let global_checksum = u16::from_be_bytes([rom[HEADER_INDEX_FOR_GLOBAL_CHECKSUM], rom[HEADER_INDEX_FOR_GLOBAL_CHECKSUM + 1]]);
+        let sum = rom.iter().enumerate()
+            .filter(|(index, _)| *index != HEADER_INDEX_FOR_GLOBAL_CHECKSUM && *index != HEADER_INDEX_FOR_GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &byte)| sum.wrapping_add(byte as u16));
+
+        return CartridgeHeader {
+            title: title.trim_end().to_string(),
+            cartridge_type: rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE],
+            rom_size: 0x8000 << (rom[HEADER_INDEX_FOR_ROM_SIZE] & 0x0F),
+            ram_size: Cartridge::get_ram_size(rom),
+            cgb_flag,
+            sgb_flag: rom[HEADER_INDEX_FOR_SGB_FLAG],
+            destination_code: rom[HEADER_INDEX_FOR_DESTINATION_CODE],
+            version: rom[HEADER_INDEX_FOR_VERSION],
+            header_checksum_valid: checksum == rom[HEADER_INDEX_FOR_CHECKSUM],
+            global_checksum,
+            global_checksum_valid: sum == global_checksum,
+        };
+    }
+}
+
+// Title:           CPU_INSTRS
+// Cartridge type:  0x01 MBC1
+// ...
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let validity = |valid: bool| if valid { "valid" } else { "invalid" };
+        let size = |bytes: usize| if bytes == 0 { "none".to_string() } else { format!("{} KiB", bytes / 1024) };
+
+        // https://gbdev.io/pandocs/#_0143-cgb-flag
+        let cgb = match self.cgb_flag {
+            0xC0 => "CGB only",
+            0x80 => "CGB enhanced",
+            _ => "DMG",
+        };
+
+        writeln!(f, "Title:           {}", self.title)?;
+        writeln!(f, "Cartridge type:  {:#04x} {}", self.cartridge_type, cartridge_type_name(self.cartridge_type))?;
+        writeln!(f, "ROM size:        {}", size(self.rom_size))?;
+        writeln!(f, "RAM size:        {}", size(self.ram_size))?;
+        writeln!(f, "CGB flag:        {:#04x} {}", self.cgb_flag, cgb)?;
+        writeln!(f, "SGB flag:        {:#04x} {}", self.sgb_flag, if self.sgb_flag == 0x03 { "SGB functions" } else { "none" })?;
+        writeln!(f, "Region:          {}", if self.destination_code == 0x00 { "Japan" } else { "Overseas" })?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(f, "Header checksum: {}", validity(self.header_checksum_valid))?;
+        writeln!(f, "Global checksum: {:#06x} {}", self.global_checksum, validity(self.global_checksum_valid))?;
+        return Ok(());
+    }
+}
+
+// https://gbdev.io/pandocs/#_0147-cartridge-type
+pub fn cartridge_type_name(cartridge_type: u8) -> &'static str {
+    return match cartridge_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => "unknown",
+    };
+}
+
+// The header of a rom taken out of its archive, also for cartridges the emulator can not run
+pub fn read_header(rom: &[u8]) -> Result<CartridgeHeader, LoadError> {
+    if rom.len() < HEADER_END { return Err(LoadError::HeaderTooShort(rom.len())); }
+    return Ok(CartridgeHeader::parse(rom));
+}
+
+// Roms are often shared zipped, an archive is accepted if it holds exactly one .gb or .gbc file.
+// Anything else is passed through as a raw rom.
+pub fn extract_rom(bytes: Vec<u8>) -> Result<Vec<u8>, LoadError> {
+    if !bytes.starts_with(&ZIP_MAGIC) { return Ok(bytes); }
+
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(bytes)).map_err(|_| LoadError::InvalidArchive)?;
+
+    let roms: Vec<String> = archive.file_names()
+        .filter(|name| {
+            let name = name.to_ascii_lowercase();
+            name.ends_with(".gb") || name.ends_with(".gbc")
+        })
+        .map(String::from)
+        .collect();
+
+    match roms.len() {
+        0 => return Err(LoadError::NoRomInArchive),
+        1 => {},
+        count => return Err(LoadError::MultipleRomsInArchive(count)),
+    }
+
+    let mut file = archive.by_name(&roms[0]).map_err(|_| LoadError::InvalidArchive)?;
+    let mut rom = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut rom).map_err(|_| LoadError::InvalidArchive)?;
+    return Ok(rom);
+}
+
+// https://gbdev.io/pandocs/#the-clock-counter-registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    latch_value: u8,
+    timestamp: u64, // unix time in seconds of the last sync
+}
+
+impl Rtc {
+
+    pub fn new() -> Self {
+        return Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_value: 0xFF,
+            timestamp: Rtc::now(),
+        }
+    }
+
+    // SystemTime is not available on wasm32-unknown-unknown so fall back to the JS clock
+    #[cfg(target_arch = "wasm32")]
+    fn now() -> u64 {
+        return (js_sys::Date::now() / 1000.0) as u64;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now() -> u64 {
+        return std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+    }
+
+    fn sync(&mut self, now: u64) {
+        if !self.halted && now > self.timestamp {
+            self.advance(now - self.timestamp);
+        }
+        self.timestamp = now;
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
+            + seconds;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+
+        let days = total / 86400;
+        if days > 0x1FF { self.day_carry = true; }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    // Writing 0x00 and then 0x01 copies the live counters into the readable registers
+    fn write_latch(&mut self, value: u8, now: u64) {
+        if self.latch_value == 0x00 && value == 0x01 {
+            self.sync(now);
+            self.latched = self.get_registers();
+        }
+        self.latch_value = value;
+    }
+
+    fn read_byte(&self, register: usize) -> u8 {
+        return self.latched[register - 0x08];
+    }
+
+    fn get_registers(&self) -> [u8; 5] {
+        return [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            (self.days & 0xFF) as u8,
+            ((self.days >> 8) as u8 & 0x01) |
+                (if self.halted { 0x40 } else { 0 }) |
+                (if self.day_carry { 0x80 } else { 0 }),
+        ];
+    }
+
+    // The 48 byte trailer used by BGB and VBA: the live and latched registers
+    // as little endian u32s followed by the unix timestamp as a u64.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RTC_TRAILER_SIZE);
+        for value in self.get_registers().iter().chain(self.latched.iter()) {
+            bytes.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        return bytes;
+    }
+
+    fn from_bytes(&mut self, bytes: &[u8]) {
+        let register = |index: usize| bytes[index * 4];
+
+        self.seconds = register(0) % 60;
+        self.minutes = register(1) % 60;
+        self.hours = register(2) % 24;
+        self.days = register(3) as u16 | (((register(4) & 0x01) as u16) << 8);
+        self.halted = register(4) & 0x40 == 0x40;
+        self.day_carry = register(4) & 0x80 == 0x80;
+        for i in 0 .. 5 {
+            self.latched[i] = register(5 + i);
+        }
+
+        // Some emulators only store a 32 bit timestamp
+        let mut timestamp = [0u8; 8];
+        let length = (bytes.len() - 40).min(8);
+        timestamp[.. length].copy_from_slice(&bytes[40 .. 40 + length]);
+        self.timestamp = u64::from_le_bytes(timestamp);
+    }
+
+    fn write_byte(&mut self, register: usize, value: u8, now: u64) {
+        self.sync(now);
+        match register {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((value & 0x01) as u16) << 8);
+                self.halted = value & 0x40 == 0x40;
+                self.day_carry = value & 0x80 == 0x80;
+            },
+            _ => {},
+        }
+        self.latched[register - 0x08] = value;
+    }
+}
+
 #[wasm_bindgen]
+#[derive(Serialize, Deserialize)]
 pub struct Cartridge {
     pub cartridge_type: CartridgeType,
+    #[serde(skip)]
     rom: Vec<u8>,
     rom_bank: usize,
     ram: Vec<u8>,
     ram_on: bool,
     ram_mode: bool,
     ram_bank: usize,
+    rtc: Rtc,
+    #[serde(skip)]
+    header: CartridgeHeader,
 }
 
 
@@ -59,18 +409,37 @@ impl Cartridge {
         Self {
             rom: vec![0; 1],
             rom_bank: 1,
-            ram: vec![0; 0x20000],
+            ram: Vec::new(),
             ram_on: false,
             ram_mode: false,
             ram_bank: 0,
+            rtc: Rtc::new(),
+            header: CartridgeHeader::default(),
             cartridge_type: CartridgeType::None
         }
     }
 
     pub fn set_rom(&mut self, rom: Vec<u8>) {
+        self.ram = vec![0; Cartridge::get_ram_size(&rom)];
+        self.header = CartridgeHeader::parse(&rom);
         self.rom = rom;
     }
 
+    // https://gbdev.io/pandocs/#_0149-ram-size
+    fn get_ram_size(rom: &[u8]) -> usize {
+        // MBC2 carts declare no RAM, the 512 half bytes are built into the chip
+        if let Some(0x05) | Some(0x06) = rom.get(HEADER_INDEX_FOR_CARTRIDGE_TYPE) { return MBC2_RAM_SIZE; }
+
+        match rom.get(HEADER_INDEX_FOR_RAM_SIZE) {
+            Some(0x01) => 0x800,
+            Some(0x02) => 0x2000,
+            Some(0x03) => 0x8000,
+            Some(0x04) => 0x20000,
+            Some(0x05) => 0x10000,
+            _ => 0,
+        }
+    }
+
     pub fn rom_dump(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:x?}", self.rom)
     }
@@ -78,43 +447,117 @@ impl Cartridge {
     pub fn read_byte(&self, addr: u16) -> u8 {
         match self.cartridge_type {
             CartridgeType::None => 0,
-            CartridgeType::MBC0 => { self.rom[addr as usize] },
-            CartridgeType::MBC1 | CartridgeType::MBC5 => {
-                match addr {
-                    0xA000 ..= 0xBFFF => {
-                        if !self.ram_on { return 0 }
-                        let ram_bank = if self.ram_mode { self.ram_bank } else { 0 };
-                        self.ram[(ram_bank * 0x2000) | ((addr & 0x1FFF) as usize)]
-                    }
-                    _ => {
-                        let index = if addr < 0x4000 { addr as usize }
-                        else  { self.rom_bank  * 0x4000 | ((addr as usize) & 0x3FFF) };
-
-                        return *self.rom.get(index).unwrap_or(&0);
-                    }
+            CartridgeType::MBC0 => { *self.rom.get(addr as usize).unwrap_or(&0) },
+            CartridgeType::MBC1 => {
+                // The 2 bit register supplies bits 5-6 of the bank number. In mode 1 it also
+                // switches the 0x0000 - 0x3FFF window to bank 0x20/0x40/0x60.
+                let bank = match addr {
+                    0x0000 ..= 0x3FFF => if self.ram_mode { self.ram_bank << 5 } else { 0 },
+                    _ => (self.ram_bank << 5) | self.rom_bank,
+                };
+
+                return self.rom[self.get_rom_index(bank, addr)];
+            },
+            CartridgeType::MBC2 => {
+                let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+                return self.rom[self.get_rom_index(bank, addr)];
+            },
+            CartridgeType::MBC5 => {
+                // The full 9 bit register is used as is, so bank 0 can also be mapped at 0x4000
+                let bank = if addr < 0x4000 { 0 } else { self.rom_bank };
+                return self.rom[self.get_rom_index(bank, addr)];
+            },
+            CartridgeType::MBC3 => {
+                let index = if addr < 0x4000 { addr as usize }
+                else  { self.rom_bank  * 0x4000 | ((addr as usize) & 0x3FFF) };
+
+                return *self.rom.get(index).unwrap_or(&0);
+            }
+        }
+    }
+
+    // External RAM mapped at 0xA000 - 0xBFFF, reads 0xFF while disabled
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_on { return 0xFF }
+        if self.ram.is_empty() && !self.is_rtc_selected() { return 0xFF }
+
+        match self.cartridge_type {
+            CartridgeType::None | CartridgeType::MBC0 => 0xFF,
+            CartridgeType::MBC1 => {
+                let ram_bank = if self.ram_mode { self.ram_bank } else { 0 };
+                self.ram[self.get_ram_index(ram_bank, addr)]
+            },
+            // Only the low nibble exists, the upper bits float high
+            CartridgeType::MBC2 => { self.ram[(addr as usize) % MBC2_RAM_SIZE] | 0xF0 },
+            CartridgeType::MBC3 => {
+                match self.ram_bank {
+                    0x00 ..= 0x03 => self.ram[self.get_ram_index(self.ram_bank, addr)],
+                    0x08 ..= 0x0C => self.rtc.read_byte(self.ram_bank),
+                    _ => 0xFF,
                 }
             },
+            CartridgeType::MBC5 => { self.ram[self.get_ram_index(self.ram_bank, addr)] },
+        }
+    }
+
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_on { return }
+        if self.ram.is_empty() && !self.is_rtc_selected() { return }
+
+        match self.cartridge_type {
+            CartridgeType::None | CartridgeType::MBC0 => {},
+            CartridgeType::MBC1 => {
+                let ram_bank = if self.ram_mode { self.ram_bank } else { 0 };
+                let index = self.get_ram_index(ram_bank, addr);
+                self.ram[index] = value;
+            },
+            CartridgeType::MBC2 => { self.ram[(addr as usize) % MBC2_RAM_SIZE] = value & 0x0F; },
             CartridgeType::MBC3 => {
-                match addr {
-                    0xA000 ..= 0xBFFF => {
-                        if !self.ram_on { return 0 }
-                        if self.ram_bank <= 3 {
-                            self.ram[self.ram_bank * 0x2000 | ((addr as usize) & 0x1FFF)]
-                        } else {
-                            0
-                        }
+                match self.ram_bank {
+                    0x00 ..= 0x03 => {
+                        let index = self.get_ram_index(self.ram_bank, addr);
+                        self.ram[index] = value;
                     },
-                    _ => { // Rom
-                        let index = if addr < 0x4000 { addr as usize }
-                        else  { self.rom_bank  * 0x4000 | ((addr as usize) & 0x3FFF) };
-
-                        return *self.rom.get(index).unwrap_or(&0);
-                    }
+                    0x08 ..= 0x0C => self.rtc.write_byte(self.ram_bank, value, Rtc::now()),
+                    _ => {},
                 }
-            }
+            },
+            CartridgeType::MBC5 => {
+                let index = self.get_ram_index(self.ram_bank, addr);
+                self.ram[index] = value;
+            },
         }
     }
 
+    fn is_rtc_selected(&self) -> bool {
+        return self.cartridge_type == CartridgeType::MBC3 && self.ram_bank >= 0x08 && self.ram_bank <= 0x0C;
+    }
+
+    // Banks beyond the size of the rom wrap around
+    // The bank of the rom that a read of addr in 0x0000 - 0x7FFF lands in
+    pub fn mapped_rom_bank(&self, addr: u16) -> usize {
+        if self.rom.is_empty() { return 0; }
+
+        let bank = match self.cartridge_type {
+            CartridgeType::None | CartridgeType::MBC0 => return (addr as usize) / 0x4000,
+            CartridgeType::MBC1 => match addr {
+                0x0000 ..= 0x3FFF => if self.ram_mode { self.ram_bank << 5 } else { 0 },
+                _ => (self.ram_bank << 5) | self.rom_bank,
+            },
+            CartridgeType::MBC2 | CartridgeType::MBC3 | CartridgeType::MBC5 => if addr < 0x4000 { 0 } else { self.rom_bank },
+        };
+        return self.get_rom_index(bank, addr) / 0x4000;
+    }
+
+    fn get_rom_index(&self, rom_bank: usize, addr: u16) -> usize {
+        return ((rom_bank * 0x4000) | ((addr as usize) & 0x3FFF)) % self.rom.len();
+    }
+
+    // Banks beyond the size declared in the header wrap around
+    fn get_ram_index(&self, ram_bank: usize, addr: u16) -> usize {
+        return ((ram_bank * 0x2000) | ((addr as usize) & 0x1FFF)) % self.ram.len();
+    }
+
     pub fn write_byte(&mut self, addr: u16, value: u8) {
         match self.cartridge_type {
             CartridgeType::None => {},
@@ -122,22 +565,19 @@ impl Cartridge {
             CartridgeType::MBC1 => {
                 match addr {
                     0x0000 ..= 0x1FFF => { self.ram_on = value == 0x0A; },
-                    0x2000 ..= 0x3FFF => {
-                        self.rom_bank = (self.rom_bank & 0x60) | match (value as usize) & 0x1F { 0 => 1, n => n }
-                    },
-                    0x4000 ..= 0x5FFF => {
-                        if !self.ram_mode {
-                            self.rom_bank = self.rom_bank & 0x1F | (((value as usize) & 0x03) << 5)
-                        } else {
-                            self.rom_bank = (value as usize) & 0x03;
-                        }
-                    },
+                    // Only the 5 bit register is checked for zero, so banks 0x20/0x40/0x60 map to 0x21/0x41/0x61
+                    0x2000 ..= 0x3FFF => self.rom_bank = match (value as usize) & 0x1F { 0 => 1, n => n },
+                    0x4000 ..= 0x5FFF => self.ram_bank = (value as usize) & 0x03,
                     0x6000 ..= 0x7FFF => { self.ram_mode = (value & 0x01) == 0x01; },
-                    0xA000 ..= 0xBFFF => {
-                        if !self.ram_on { return }
-                        let ram_bank = if self.ram_mode { self.ram_bank } else { 0 };
-                        self.ram[(ram_bank * 0x2000) | ((addr & 0x1FFF) as usize)] = value;
-                    }
+                    _ => panic!("error"),
+                }
+            },
+            CartridgeType::MBC2 => {
+                match addr {
+                    // Bit 8 of the address selects the register, the rest of the range mirrors it
+                    0x0000 ..= 0x3FFF if addr & 0x0100 == 0 => { self.ram_on = (value & 0x0F) == 0x0A; },
+                    0x0000 ..= 0x3FFF => self.rom_bank = match (value as usize) & 0x0F { 0 => 1, n => n },
+                    0x4000 ..= 0x7FFF => {},
                     _ => panic!("error"),
                 }
             },
@@ -148,10 +588,6 @@ impl Cartridge {
                     0x3000 ..= 0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | (((value & 0x1) as usize) << 8),
                     0x4000 ..= 0x5FFF => self.ram_bank = (value & 0x0F) as usize,
                     0x6000 ..= 0x7FFF => {}
-                    0xA000 ..= 0xBFFF => {
-                        if self.ram_on == false { return }
-                        self.ram[self.ram_bank * 0x2000 | ((addr as usize) & 0x1FFF)] = value;
-                    }
                     _ => panic!("error"),
                 }
             },
@@ -160,19 +596,454 @@ impl Cartridge {
                     0x0000 ..= 0x1FFF => { self.ram_on = value == 0x0A; },
                     0x2000 ..= 0x3FFF => self.rom_bank = match value & 0x7F { 0 => 1, n => n as usize },
                     0x4000 ..= 0x5FFF => self.ram_bank = value as usize,
-                    0x6000 ..= 0x7FFF => { },
-                    0xA000 ..= 0xBFFF => {
-                        if self.ram_on == false { return }
-                        self.ram[self.ram_bank * 0x2000 | ((addr as usize) & 0x1FFF)] = value;
-                    }
+                    0x6000 ..= 0x7FFF => self.rtc.write_latch(value, Rtc::now()),
                     _ => panic!("error"),
                 }
             }
         }
     }
 
+    // Restores the banking registers and RAM of a save state, keeping the loaded rom
+    pub fn load_state(&mut self, state: Cartridge) {
+        let rom = std::mem::take(&mut self.rom);
+        let header = std::mem::take(&mut self.header);
+        *self = state;
+        self.rom = rom;
+        self.header = header;
+    }
+
+    // https://gbdev.io/pandocs/#_0147-cartridge-type
+    pub fn has_battery(&self) -> bool {
+        match self.rom.get(HEADER_INDEX_FOR_CARTRIDGE_TYPE) {
+            Some(0x03) | Some(0x06) | Some(0x09) | Some(0x0D) | Some(0x0F) | Some(0x10) |
+            Some(0x13) | Some(0x1B) | Some(0x1E) | Some(0x22) | Some(0xFF) => true,
+            _ => false,
+        }
+    }
+
+    fn has_rtc(&self) -> bool {
+        match self.rom.get(HEADER_INDEX_FOR_CARTRIDGE_TYPE) {
+            Some(0x0F) | Some(0x10) => true,
+            _ => false,
+        }
+    }
+
+    // Raw RAM as written to .sav files, followed by the RTC trailer on MBC3 timer carts
+    pub fn ram_snapshot(&self) -> Vec<u8> {
+        let mut snapshot = self.ram.clone();
+        if self.has_rtc() {
+            snapshot.extend(self.rtc.to_bytes());
+        }
+        return snapshot;
+    }
+
+    pub fn load_ram(&mut self, snapshot: &[u8]) {
+        let length = snapshot.len().min(self.ram.len());
+        self.ram[.. length].copy_from_slice(&snapshot[.. length]);
+
+        let trailer = &snapshot[length ..];
+        if self.has_rtc() && trailer.len() >= RTC_TRAILER_SIZE - 4 {
+            self.rtc.from_bytes(trailer);
+        }
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        return &self.header;
+    }
+
+    pub fn get_rtc(&self) -> Rtc {
+        return self.rtc;
+    }
+
+    pub fn set_rtc(&mut self, rtc: Rtc) {
+        self.rtc = rtc;
+    }
+
     pub fn get_gameboy_type(&self) -> GameboyType {
         let mode_byte = self.rom[0x143];
         return if mode_byte == 0x80 || mode_byte == 0xc0 { GameboyType::COLOR } else { GameboyType::CLASSIC };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_cartridge(cartridge_type: CartridgeType, header_type: u8, ram_size: u8) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE] = header_type;
+        rom[HEADER_INDEX_FOR_RAM_SIZE] = ram_size;
+
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = cartridge_type;
+        cartridge.set_rom(rom);
+        return cartridge;
+    }
+
+    #[test]
+    fn ram_survives_disable_and_enable() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC1, 0x03, 0x03);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        for addr in 0xA000 ..= 0xBFFFu16 {
+            cartridge.write_ram(addr, (addr ^ (addr >> 8)) as u8);
+        }
+
+        cartridge.write_byte(0x0000, 0x00);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+        assert_eq!(cartridge.read_ram(0xBFFF), 0xFF);
+
+        // Writes while disabled are dropped
+        cartridge.write_ram(0xA000, 0x00);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        for addr in 0xA000 ..= 0xBFFFu16 {
+            assert_eq!(cartridge.read_ram(addr), (addr ^ (addr >> 8)) as u8);
+        }
+    }
+
+    #[test]
+    fn ram_is_sized_from_header() {
+        assert_eq!(create_cartridge(CartridgeType::MBC1, 0x01, 0x00).ram.len(), 0);
+        assert_eq!(create_cartridge(CartridgeType::MBC1, 0x03, 0x02).ram.len(), 0x2000);
+        assert_eq!(create_cartridge(CartridgeType::MBC5, 0x1B, 0x04).ram.len(), 0x20000);
+
+        let mut cartridge = create_cartridge(CartridgeType::MBC1, 0x01, 0x00);
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+    }
+
+    fn create_banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * 0x4000];
+        for bank in 0 .. banks {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 0x3FFF] = bank as u8;
+        }
+        rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE] = 0x01;
+        return rom;
+    }
+
+    #[test]
+    fn mbc1_switches_rom_banks() {
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = CartridgeType::MBC1;
+        cartridge.set_rom(create_banked_rom(128));
+
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        cartridge.write_byte(0x2000, 0x05);
+        assert_eq!(cartridge.read_byte(0x4000), 5);
+        assert_eq!(cartridge.read_byte(0x7FFF), 5);
+
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        cartridge.write_byte(0x4000, 0x02);
+        cartridge.write_byte(0x2000, 0x13);
+        assert_eq!(cartridge.read_byte(0x4000), 0x53);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn mbc1_remaps_bank_zero_quirk() {
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = CartridgeType::MBC1;
+        cartridge.set_rom(create_banked_rom(128));
+
+        for &upper in [0x01u8, 0x02, 0x03].iter() {
+            cartridge.write_byte(0x4000, upper);
+            cartridge.write_byte(0x2000, 0x00);
+            assert_eq!(cartridge.read_byte(0x4000), (upper << 5) | 1);
+        }
+    }
+
+    #[test]
+    fn mbc1_mode_one_banks_lower_window() {
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = CartridgeType::MBC1;
+        cartridge.set_rom(create_banked_rom(128));
+
+        cartridge.write_byte(0x4000, 0x01);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+
+        cartridge.write_byte(0x6000, 0x01);
+        assert_eq!(cartridge.read_byte(0x0000), 0x20);
+        assert_eq!(cartridge.read_byte(0x3FFF), 0x20);
+
+        // Small roms ignore the upper bits
+        cartridge.set_rom(create_banked_rom(8));
+        cartridge.write_byte(0x2000, 0x09);
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn mbc2_address_bit_8_selects_register() {
+        let mut rom = create_banked_rom(16);
+        rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE] = 0x06;
+
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = CartridgeType::MBC2;
+        cartridge.set_rom(rom);
+        assert_eq!(cartridge.ram.len(), 0x200);
+
+        // Bit 8 set: ROM bank, RAM stays disabled
+        cartridge.write_byte(0x2100, 0x0A);
+        assert_eq!(cartridge.read_byte(0x4000), 0x0A);
+        cartridge.write_ram(0xA000, 0x05);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+
+        // Bit 8 clear: RAM enable, ROM bank unchanged
+        cartridge.write_byte(0x0000, 0x0A);
+        assert_eq!(cartridge.read_byte(0x4000), 0x0A);
+        cartridge.write_ram(0xA000, 0x05);
+        assert_eq!(cartridge.read_ram(0xA000), 0xF5);
+
+        cartridge.write_byte(0x3E00, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0x0A);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+
+        cartridge.write_byte(0x3FFF, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0x01);
+    }
+
+    #[test]
+    fn mbc2_ram_holds_low_nibbles() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC2, 0x05, 0x00);
+        cartridge.write_byte(0x0000, 0x0A);
+
+        cartridge.write_ram(0xA000, 0xAB);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFB);
+
+        // The 512 half bytes repeat across 0xA000 - 0xBFFF
+        cartridge.write_ram(0xA1FF, 0x03);
+        assert_eq!(cartridge.read_ram(0xA3FF), 0xF3);
+        assert_eq!(cartridge.read_ram(0xBE00), 0xFB);
+    }
+
+    #[test]
+    fn mbc5_selects_bank_zero_and_high_banks() {
+        let mut rom = create_banked_rom(512);
+        for bank in 0 .. 512 {
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+
+        let mut cartridge = Cartridge::new();
+        cartridge.cartridge_type = CartridgeType::MBC5;
+        cartridge.set_rom(rom);
+        assert_eq!(cartridge.read_byte(0x4000), 1);
+
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0);
+        assert_eq!(cartridge.read_byte(0x4001), 0);
+
+        // Bit 8 comes from 0x3000 - 0x3FFF and is kept when the low byte changes
+        cartridge.write_byte(0x3000, 0x01);
+        cartridge.write_byte(0x2000, 0x01);
+        assert_eq!(cartridge.read_byte(0x4000), 0x01);
+        assert_eq!(cartridge.read_byte(0x4001), 0x01);
+        assert_eq!(cartridge.read_byte(0x7FFF), 0x01);
+
+        cartridge.write_byte(0x2FFF, 0xFF);
+        assert_eq!((cartridge.read_byte(0x4001), cartridge.read_byte(0x4000)), (0x01, 0xFF));
+
+        cartridge.write_byte(0x3FFF, 0x00);
+        assert_eq!((cartridge.read_byte(0x4001), cartridge.read_byte(0x4000)), (0x00, 0xFF));
+        assert_eq!(cartridge.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn mbc5_switches_ram_banks() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC5, 0x1B, 0x04);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        for bank in 0 .. 16u8 {
+            cartridge.write_byte(0x4000, bank);
+            cartridge.write_ram(0xA000, bank + 0x10);
+        }
+
+        cartridge.write_byte(0x4000, 0x03);
+        assert_eq!(cartridge.read_ram(0xA000), 0x13);
+        cartridge.write_byte(0x5FFF, 0x0F);
+        assert_eq!(cartridge.read_ram(0xA000), 0x1F);
+
+        cartridge.write_byte(0x1FFF, 0x00);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc3_rtc_latches_advanced_time() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC3, 0x10, 0x03);
+        cartridge.rtc.timestamp = 1000;
+        cartridge.write_byte(0x0000, 0x0A);
+
+        cartridge.rtc.write_latch(0x00, 1000);
+        cartridge.rtc.write_latch(0x01, 1000);
+        cartridge.write_byte(0x4000, 0x08);
+        assert_eq!(cartridge.read_ram(0xA000), 0);
+
+        // Latched values stay put until the next latch sequence
+        cartridge.rtc.sync(1125);
+        assert_eq!(cartridge.read_ram(0xA000), 0);
+
+        cartridge.rtc.write_latch(0x00, 1125);
+        cartridge.rtc.write_latch(0x01, 1125);
+        assert_eq!(cartridge.read_ram(0xA000), 5);
+        cartridge.write_byte(0x4000, 0x09);
+        assert_eq!(cartridge.read_ram(0xA000), 2);
+    }
+
+    #[test]
+    fn mbc3_rtc_halt_and_day_carry() {
+        let mut rtc = Rtc::new();
+        rtc.timestamp = 0;
+
+        rtc.write_byte(0x0C, 0x41, 0); // halt, day bit 8
+        rtc.sync(3600);
+        rtc.write_latch(0x00, 3600);
+        rtc.write_latch(0x01, 3600);
+        assert_eq!(rtc.read_byte(0x08), 0);
+        assert_eq!(rtc.read_byte(0x0C), 0x41);
+
+        rtc.write_byte(0x0B, 0xFF, 3600);
+        rtc.write_byte(0x0C, 0x01, 3600);
+        rtc.sync(3600 + 86400);
+        rtc.write_latch(0x00, 3600 + 86400);
+        rtc.write_latch(0x01, 3600 + 86400);
+        assert_eq!(rtc.read_byte(0x0B), 0x00);
+        assert_eq!(rtc.read_byte(0x0C), 0x80);
+    }
+
+    #[test]
+    fn mbc3_rom_and_ram_banks() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC3, 0x13, 0x03);
+        cartridge.set_rom({
+            let mut rom = create_banked_rom(128);
+            rom[HEADER_INDEX_FOR_RAM_SIZE] = 0x03;
+            rom
+        });
+
+        cartridge.write_byte(0x2000, 0x7F);
+        assert_eq!(cartridge.read_byte(0x4000), 0x7F);
+        cartridge.write_byte(0x2000, 0x00);
+        assert_eq!(cartridge.read_byte(0x4000), 0x01);
+
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0x4000, 0x02);
+        cartridge.write_ram(0xA010, 0x42);
+        cartridge.write_byte(0x4000, 0x00);
+        assert_eq!(cartridge.read_ram(0xA010), 0x00);
+        cartridge.write_byte(0x4000, 0x02);
+        assert_eq!(cartridge.read_ram(0xA010), 0x42);
+        assert_eq!(cartridge.ram_snapshot()[0x4010], 0x42);
+    }
+
+    #[test]
+    fn battery_snapshot_round_trip() {
+        assert!(!create_cartridge(CartridgeType::MBC1, 0x02, 0x02).has_battery());
+        assert!(create_cartridge(CartridgeType::MBC1, 0x03, 0x02).has_battery());
+
+        let mut cartridge = create_cartridge(CartridgeType::MBC1, 0x03, 0x02);
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_ram(0xA123, 0x5A);
+        let snapshot = cartridge.ram_snapshot();
+        assert_eq!(snapshot.len(), 0x2000);
+
+        let mut loaded = create_cartridge(CartridgeType::MBC1, 0x03, 0x02);
+        loaded.load_ram(&snapshot);
+        loaded.write_byte(0x0000, 0x0A);
+        assert_eq!(loaded.read_ram(0xA123), 0x5A);
+    }
+
+    #[test]
+    fn battery_snapshot_appends_rtc_trailer() {
+        let mut cartridge = create_cartridge(CartridgeType::MBC3, 0x10, 0x03);
+        cartridge.rtc.timestamp = 1000;
+        cartridge.rtc.write_byte(0x09, 42, 1000);
+        cartridge.rtc.write_latch(0x00, 1000);
+        cartridge.rtc.write_latch(0x01, 1000);
+
+        let snapshot = cartridge.ram_snapshot();
+        assert_eq!(snapshot.len(), 0x8000 + RTC_TRAILER_SIZE);
+        assert_eq!(snapshot[0x8000 + 4], 42);
+
+        let mut loaded = create_cartridge(CartridgeType::MBC3, 0x10, 0x03);
+        loaded.load_ram(&snapshot);
+        assert_eq!(loaded.rtc.get_registers(), cartridge.rtc.get_registers());
+        assert_eq!(loaded.rtc.latched, cartridge.rtc.latched);
+        assert_eq!(loaded.rtc.timestamp, 1000);
+
+        // Without a timer the trailer is neither written nor read
+        let cartridge = create_cartridge(CartridgeType::MBC3, 0x13, 0x03);
+        assert_eq!(cartridge.ram_snapshot().len(), 0x8000);
+    }
+
+    #[test]
+    fn header_of_cpu_instrs() {
+        let mut cartridge = Cartridge::new();
+        cartridge.set_rom(include_bytes!("../roms/cpu_instrs.gb").to_vec());
+
+        assert_eq!(cartridge.header(), &CartridgeHeader {
+            title: "CPU_INSTRS".to_string(),
+            cartridge_type: 0x01,
+            rom_size: 0x10000,
+            ram_size: 0,
+            cgb_flag: 0x80,
+            sgb_flag: 0x00,
+            destination_code: 0x00,
+            version: 0x00,
+            header_checksum_valid: true,
+            // Blargg's roms are not patched with the real sum
+            global_checksum: 0xF530,
+            global_checksum_valid: false,
+        });
+    }
+
+    #[test]
+    fn info_of_cpu_instrs() {
+        let info = read_header(include_bytes!("../roms/cpu_instrs.gb")).unwrap().to_string();
+        assert!(info.contains("Title:           CPU_INSTRS\n"), "{}", info);
+        assert!(info.contains("Cartridge type:  0x01 MBC1\n"), "{}", info);
+        assert!(info.contains("ROM size:        64 KiB\n"), "{}", info);
+        assert!(info.contains("Global checksum: 0xf530 invalid\n"), "{}", info);
+
+        // Unsupported cartridges are described too
+        let mut rom = vec![0; 0x8000];
+        rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE] = 0xFC;
+        assert!(read_header(&rom).unwrap().to_string().contains("0xfc POCKET CAMERA"));
+        assert_eq!(read_header(&[0; 16]), Err(LoadError::HeaderTooShort(16)));
+    }
+
+    #[test]
+    fn header_title_and_checksum() {
+        let mut rom = include_bytes!("../roms/opus5.gb").to_vec();
+        assert_eq!(CartridgeHeader::parse(&rom).title, "Opus Test");
+        assert!(CartridgeHeader::parse(&rom).header_checksum_valid);
+
+        rom[HEADER_INDEX_FOR_CHECKSUM] ^= 0xFF;
+        assert!(!CartridgeHeader::parse(&rom).header_checksum_valid);
+
+        // Without the CGB flag all 16 bytes belong to the title
+        rom[HEADER_INDEX_FOR_TITLE .. HEADER_INDEX_FOR_TITLE + 16].copy_from_slice(b"SIXTEEN BYTES OK");
+        assert_eq!(CartridgeHeader::parse(&rom).title, "SIXTEEN BYTES OK");
+    }
+
+    #[test]
+    fn global_checksum_covers_whole_rom() {
+        let mut rom = include_bytes!("../roms/dmg-acid2.gb").to_vec();
+        assert!(CartridgeHeader::parse(&rom).global_checksum_valid);
+
+        rom[0x7000] ^= 0x01;
+        let header = CartridgeHeader::parse(&rom);
+        assert!(!header.global_checksum_valid);
+        assert!(header.header_checksum_valid);
+
+        // Storing the new sum fixes it, the checksum bytes themselves are not summed
+        let sum = rom.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+            .wrapping_sub(rom[HEADER_INDEX_FOR_GLOBAL_CHECKSUM] as u16)
+            .wrapping_sub(rom[HEADER_INDEX_FOR_GLOBAL_CHECKSUM + 1] as u16);
+        rom[HEADER_INDEX_FOR_GLOBAL_CHECKSUM .. HEADER_INDEX_FOR_GLOBAL_CHECKSUM + 2].copy_from_slice(&sum.to_be_bytes());
+        assert!(CartridgeHeader::parse(&rom).global_checksum_valid);
+    }
+}
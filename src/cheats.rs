@@ -0,0 +1,165 @@
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatError {
+    InvalidLength(usize),
+    InvalidDigit(char),
+    NotRomAddress(u16),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidLength(length) => write!(f, "cheat has {} digits, expected 6 or 9 for Game Genie or 8 for GameShark", length),
+            CheatError::InvalidDigit(digit) => write!(f, "'{}' is not a hex digit", digit),
+            CheatError::NotRomAddress(address) => write!(f, "Game Genie code patches {:#06x}, outside of the rom", address),
+        }
+    }
+}
+
+impl From<CheatError> for JsValue {
+    fn from(error: CheatError) -> Self {
+        return JsValue::from_str(&error.to_string());
+    }
+}
+
+// Replaces a rom byte as the CPU reads it, only when the rom holds compare if one is given
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameGenieCode {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+// Writes a byte to RAM after every frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameSharkCode {
+    pub bank: u8,
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cheat {
+    GameGenie(GameGenieCode),
+    GameShark(GameSharkCode),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+    game_genie: Vec<GameGenieCode>,
+    game_shark: Vec<GameSharkCode>,
+}
+
+impl Cheats {
+
+    pub fn new() -> Self {
+        return Cheats {
+            game_genie: Vec::new(),
+            game_shark: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, code: &str) -> Result<(), CheatError> {
+        match parse(code)? {
+            Cheat::GameGenie(code) => self.game_genie.push(code),
+            Cheat::GameShark(code) => self.game_shark.push(code),
+        }
+        return Ok(());
+    }
+
+    pub fn clear(&mut self) {
+        self.game_genie.clear();
+        self.game_shark.clear();
+    }
+
+    pub fn patch_rom_read(&self, address: u16, value: u8) -> u8 {
+        for code in self.game_genie.iter() {
+            if code.address == address && code.compare.is_none_or(|compare| compare == value) {
+                return code.value;
+            }
+        }
+        return value;
+    }
+
+    pub fn game_shark_codes(&self) -> &[GameSharkCode] {
+        return &self.game_shark;
+    }
+}
+
+// Dashes and spaces are ignored, the number of digits tells the formats apart
+pub fn parse(code: &str) -> Result<Cheat, CheatError> {
+    let digits = code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u16).ok_or(CheatError::InvalidDigit(c)))
+        .collect::<Result<Vec<u16>, CheatError>>()?;
+
+    return match digits.len() {
+        6 | 9 => parse_game_genie(&digits).map(Cheat::GameGenie),
+        8 => Ok(Cheat::GameShark(parse_game_shark(&digits))),
+        length => Err(CheatError::InvalidLength(length)),
+    };
+}
+
+// ABC-DEF-GHI: AB is the new value, the address is FCDE with F inverted and GI is the
+// compare value rotated right by two and XORed with 0xBA. H is not used.
+fn parse_game_genie(digits: &[u16]) -> Result<GameGenieCode, CheatError> {
+    let address = ((digits[5] ^ 0xF) << 12) | (digits[2] << 8) | (digits[3] << 4) | digits[4];
+    if address >= 0x8000 { return Err(CheatError::NotRomAddress(address)); }
+
+    let compare = if digits.len() == 9 {
+        let scrambled = ((digits[6] << 4) | digits[8]) as u8;
+        Some(scrambled.rotate_right(2) ^ 0xBA)
+    } else { None };
+
+    return Ok(GameGenieCode {
+        address,
+        value: ((digits[0] << 4) | digits[1]) as u8,
+        compare,
+    });
+}
+
+// ABCDEFGH: AB is the RAM bank, CD the value and GHEF the address
+fn parse_game_shark(digits: &[u16]) -> GameSharkCode {
+    let byte = |index: usize| ((digits[index] << 4) | digits[index + 1]) as u8;
+
+    return GameSharkCode {
+        bank: byte(0),
+        value: byte(2),
+        address: ((byte(6) as u16) << 8) | byte(4) as u16,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_game_genie_codes() {
+        assert_eq!(parse("C3A-BDF-4A1"), Ok(Cheat::GameGenie(GameGenieCode { address: 0x0ABD, value: 0xC3, compare: Some(0xEA) })));
+        assert_eq!(parse("01A 3CE"), Ok(Cheat::GameGenie(GameGenieCode { address: 0x1A3C, value: 0x01, compare: None })));
+        assert_eq!(parse("000-000-000"), Err(CheatError::NotRomAddress(0xF000)));
+    }
+
+    #[test]
+    fn decodes_game_shark_codes() {
+        assert_eq!(parse("0163A4C6"), Ok(Cheat::GameShark(GameSharkCode { bank: 0x01, value: 0x63, address: 0xC6A4 })));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert_eq!(parse("0163A4C"), Err(CheatError::InvalidLength(7)));
+        assert_eq!(parse("0163A4CG"), Err(CheatError::InvalidDigit('G')));
+    }
+
+    #[test]
+    fn game_genie_patch_checks_compare_value() {
+        let mut cheats = Cheats::new();
+        cheats.add("C3A-BDF-4A1").unwrap();
+
+        assert_eq!(cheats.patch_rom_read(0x0ABD, 0xEA), 0xC3);
+        assert_eq!(cheats.patch_rom_read(0x0ABD, 0x00), 0x00);
+        assert_eq!(cheats.patch_rom_read(0x0ABE, 0xEA), 0xEA);
+    }
+}
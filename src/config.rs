@@ -0,0 +1,191 @@
+use crate::joypad::Button;
+use minifb::Key;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+pub const CONFIG_FILE_NAME: &str = "rustyboy.toml";
+
+// Settings read from rustyboy.toml, command line options take precedence. Every field is
+// optional, for example:
+//
+//   scale = 3
+//   palette = "pocket"
+//   audio = false
+//
+//   [keys]
+//   a = "K"
+//   b = "J"
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scale: Option<usize>,
+    pub palette: Option<String>,
+    pub audio: Option<bool>,
+    pub keys: HashMap<String, String>, // Button name to key name
+}
+
+impl Config {
+
+    pub fn parse(text: &str) -> Result<Config, String> {
+        return toml::from_str(text).map_err(|error| error.to_string());
+    }
+
+    // The first config file found, the defaults when there is none or it can not be read
+    pub fn load() -> Config {
+        for path in search_paths() {
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(_) => continue,
+            };
+
+            return match Config::parse(&text) {
+                Ok(config) => config,
+                Err(error) => {
+                    eprintln!("warning: ignoring {}: {}", path.display(), error);
+                    Config::default()
+                },
+            };
+        }
+        return Config::default();
+    }
+
+    // The default layout with the bindings of the [keys] table applied. Bad entries are
+    // reported and leave the default binding of their button in place.
+    pub fn key_bindings(&self) -> HashMap<Key, Button> {
+        let mut bindings: HashMap<Key, Button> = DEFAULT_KEY_BINDINGS.iter().cloned().collect();
+
+        let mut names: Vec<_> = self.keys.iter().collect();
+        names.sort();
+        for (button_name, key_name) in names {
+            let button = match parse_button(button_name) {
+                Some(button) => button,
+                None => { eprintln!("warning: unknown button '{}' in config", button_name); continue; },
+            };
+            let key = match parse_key(key_name) {
+                Some(key) => key,
+                None => { eprintln!("warning: unknown key '{}' in config", key_name); continue; },
+            };
+
+            bindings.retain(|_, bound| *bound != button);
+            bindings.insert(key, button);
+        }
+
+        return bindings;
+    }
+}
+
+// ./rustyboy.toml, then $XDG_CONFIG_HOME/rustyboy/rustyboy.toml with ~/.config standing in
+// for an unset XDG_CONFIG_HOME
+fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE_NAME)];
+
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    };
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join("rustyboy").join(CONFIG_FILE_NAME));
+    }
+
+    return paths;
+}
+
+pub const DEFAULT_KEY_BINDINGS: [(Key, Button); 8] = [
+    (Key::Right, Button::RIGHT),
+    (Key::Left, Button::LEFT),
+    (Key::Up, Button::UP),
+    (Key::Down, Button::DOWN),
+    (Key::X, Button::A),
+    (Key::Z, Button::B),
+    (Key::Backspace, Button::SELECT),
+    (Key::Enter, Button::START),
+];
+
+fn parse_button(name: &str) -> Option<Button> {
+    return match name.to_ascii_lowercase().as_str() {
+        "right" => Some(Button::RIGHT),
+        "left" => Some(Button::LEFT),
+        "up" => Some(Button::UP),
+        "down" => Some(Button::DOWN),
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "select" => Some(Button::SELECT),
+        "start" => Some(Button::START),
+        _ => None,
+    };
+}
+
+// Key names as minifb spells them, ignoring case
+fn parse_key(name: &str) -> Option<Key> {
+    return KEYS.iter().cloned().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name));
+}
+
+const KEYS: [Key; 106] = [
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11,
+    Key::F12, Key::F13, Key::F14, Key::F15,
+    Key::Down, Key::Left, Key::Right, Key::Up,
+    Key::Apostrophe, Key::Backquote, Key::Backslash, Key::Comma, Key::Equal, Key::LeftBracket, Key::Minus,
+    Key::Period, Key::RightBracket, Key::Semicolon, Key::Slash,
+    Key::Backspace, Key::Delete, Key::End, Key::Enter, Key::Escape, Key::Home, Key::Insert, Key::Menu,
+    Key::PageDown, Key::PageUp, Key::Pause, Key::Space, Key::Tab, Key::NumLock, Key::CapsLock, Key::ScrollLock,
+    Key::LeftShift, Key::RightShift, Key::LeftCtrl, Key::RightCtrl,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4, Key::NumPad5, Key::NumPad6,
+    Key::NumPad7, Key::NumPad8, Key::NumPad9, Key::NumPadDot, Key::NumPadSlash, Key::NumPadAsterisk,
+    Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter,
+    Key::LeftAlt, Key::RightAlt, Key::LeftSuper, Key::RightSuper,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_config_rebinds_keys() {
+        let config = Config::parse(r#"
+            scale = 3
+            palette = "pocket"
+            audio = false
+
+            [keys]
+            a = "K"
+            b = "j"
+            start = "Space"
+        "#).unwrap();
+
+        assert_eq!(config.scale, Some(3));
+        assert_eq!(config.palette.as_deref(), Some("pocket"));
+        assert_eq!(config.audio, Some(false));
+
+        let bindings = config.key_bindings();
+        assert_eq!(bindings.len(), 8);
+        assert_eq!(bindings.get(&Key::K), Some(&Button::A));
+        assert_eq!(bindings.get(&Key::J), Some(&Button::B));
+        assert_eq!(bindings.get(&Key::Space), Some(&Button::START));
+        assert_eq!(bindings.get(&Key::Backspace), Some(&Button::SELECT));
+        assert_eq!(bindings.get(&Key::X), None);
+        assert_eq!(bindings.get(&Key::Enter), None);
+    }
+
+    #[test]
+    fn missing_fields_fall_back_to_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.key_bindings(), DEFAULT_KEY_BINDINGS.iter().cloned().collect());
+    }
+
+    #[test]
+    fn bad_entries_are_rejected() {
+        assert!(Config::parse("scale = \"big\"").is_err());
+        assert!(Config::parse("volume = 3").is_err());
+
+        // Unknown names keep the default binding
+        let config = Config::parse("[keys]\na = \"NoSuchKey\"\njump = \"Space\"").unwrap();
+        assert_eq!(config.key_bindings(), DEFAULT_KEY_BINDINGS.iter().cloned().collect());
+    }
+}
@@ -1,22 +1,154 @@
-use crate::cpu::Cpu;
-use crate::mmu::Mmu;
+use crate::cpu::{Cpu, Registers};
+use crate::mmu::{Mmu, InitPattern, InterruptWrite};
+use crate::operations;
+use crate::logger::{Logger, debug, warn, set_log_path};
+use crate::rewind::RewindBuffer;
+use crate::audio::AudioSink;
+use crate::apu::Channel;
+use crate::ppu::{SCREEN_W, SCREEN_H, VBlankCallback};
+use image::{ImageOutputFormat, RgbaImage};
 use web_sys::CanvasRenderingContext2d;
 use wasm_bindgen::prelude::*;
 use console_error_panic_hook;
 use js_sys;
-use crate::joypad::{Joypad, Button};
+use crate::joypad::{Joypad, Button, InputState};
+use crate::cartridge::{LoadError, CartridgeHeader};
+use crate::cheats::CheatError;
+use crate::input_log::{InputLog, InputPlayback};
+use crate::symbols::{SymbolTable, SymbolError};
+use crate::backtrace::{Backtrace, TraceEntry};
+use crate::sgb::SgbPacket;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::{fmt, fs, io};
+use std::hash::Hasher;
+use serde_derive::{Serialize, Deserialize};
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GameboyType {
     COLOR,
     CLASSIC
 }
 
+// Shown instead of running the CPU through an empty cartridge slot
+const NO_ROM_MESSAGE: [&str; 1] = ["NO ROM LOADED"];
+
+// Instructions shown before a line that differs from a reference log
+const REFERENCE_BACKTRACE_LENGTH: usize = 8;
+
+// 154 scanlines of 456 clocks each
+pub const TICKS_PER_FRAME: u32 = 70224;
+
+const SAVE_STATE_MAGIC: [u8; 4] = *b"RBSS";
+const SAVE_STATE_VERSION: u32 = 16;
+
+// Leads every save state so states from another format can be rejected before decoding the rest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct SaveStateHeader {
+    magic: [u8; 4],
+    version: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    InvalidData,
+    UnsupportedVersion(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReferenceError {
+    FileNotFound,
+    Mismatch(TraceMismatch),
+}
+
+// The first instruction where the emulator and a reference log part ways
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceMismatch {
+    pub line: usize, // 1 based
+    pub expected: String,
+    pub actual: String,
+    pub backtrace: Vec<TraceEntry>, // The instructions leading up to it, oldest first
+}
+
+impl fmt::Display for TraceMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "reference log differs at line {}", self.line)?;
+        writeln!(f, "expected: {}", self.expected)?;
+        writeln!(f, "actual:   {}", self.actual)?;
+        for entry in self.backtrace.iter() {
+            writeln!(f, "  {}", entry)?;
+        }
+        return Ok(());
+    }
+}
+
+// 64 bit FNV-1a, unlike the std hashers its output is fixed across builds and Rust versions
+// http://www.isthe.com/chongo/tech/comp/fnv/index.html
+struct StateHasher(u64);
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001B3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        return self.0;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepResult {
+    Stepped(u32), // 4 MHz clocks spent, see Console::execute_tick
+    BreakpointHit(u16),
+    WatchpointHit(u16),
+    NotReturned(u32), // step_over gave up on a subroutine after this many clocks
+}
+
+// https://github.com/Gekkio/mooneye-test-suite#passfail-reporting
+// Mooneye's test roms finish with LD B,B as a software breakpoint. The Fibonacci numbers
+// 3 5 8 13 21 34 in B C D E H L mean the test passed, 0x42 in all of them that it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MooneyeResult {
+    Passed,
+    Failed,
+}
+
+impl MooneyeResult {
+
+    // None for registers in neither pattern, an LD B,B of any other rom
+    pub fn from_registers(registers: &Registers) -> Option<MooneyeResult> {
+        let r = registers;
+        return match [r.b, r.c, r.d, r.e, r.h, r.l] {
+            [3, 5, 8, 13, 21, 34] => Some(MooneyeResult::Passed),
+            [0x42, 0x42, 0x42, 0x42, 0x42, 0x42] => Some(MooneyeResult::Failed),
+            _ => None,
+        };
+    }
+}
+
 #[wasm_bindgen]
 pub struct Console {
     cpu: Cpu,
     mmu: Mmu,
+    save_path: Option<PathBuf>,
+    breakpoints: HashSet<u16>,
+    symbols: SymbolTable,
+    trace: bool,
+    backtrace: Option<Backtrace>, // The last instructions while record_backtrace is on
+    overshoot: u32, // Clocks the last execute_ticks ran past its budget
+    turbo: u32, // Frames emulated per execute_frame
+    audio_enabled: bool,
+    paused: bool,
+    init_pattern: InitPattern, // Applied again whenever a cartridge is inserted
+    rewind_buffer: Option<RewindBuffer>,
+    audio_sink: Option<Box<dyn AudioSink>>,
+    input_frame: u64, // Frames run by execute_frame since recording or playback started
+    recording: Option<InputLog>,
+    playback: Option<InputPlayback>,
+    interrupt_trace: Vec<InterruptWrite>, // Collected while trace_interrupts is on
 }
 
 #[wasm_bindgen]
@@ -25,14 +157,42 @@ impl Console {
     pub fn new() -> Self {
         console_error_panic_hook::set_once();
 
-        return Console {
+        let mut console = Console {
             mmu: Mmu::new(),
-            cpu: Cpu::new()
-        }
+            cpu: Cpu::new(),
+            save_path: None,
+            breakpoints: HashSet::new(),
+            symbols: SymbolTable::new(),
+            trace: false,
+            backtrace: None,
+            overshoot: 0,
+            turbo: 1,
+            audio_enabled: true,
+            paused: false,
+            init_pattern: InitPattern::Zero,
+            rewind_buffer: None,
+            audio_sink: None,
+            input_frame: 0,
+            recording: None,
+            playback: None,
+            interrupt_trace: Vec::new(),
+        };
+        console.mmu.ppu.show_text(&NO_ROM_MESSAGE);
+        return console;
+    }
+
+    pub fn load(&mut self, result: &JsValue) -> Result<(), LoadError> {
+        let bytes: Vec<u8> = result.into_serde().map_err(|_| LoadError::InvalidRomData)?;
+        return self.insert_cartridge(bytes);
     }
 
-    pub fn load(&mut self, result: &JsValue) {
-        self.mmu.load_cartridge_from_js_value(result);
+    // Takes Game Genie (ABC-DEF-GHI or ABC-DEF) and GameShark (ABCDEFGH) codes
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        return self.mmu.cheats.add(code);
+    }
+
+    pub fn clear_cheats(&mut self) {
+        self.mmu.cheats.clear();
     }
 
     pub fn press_button(&mut self, button: Button) {
@@ -45,30 +205,1490 @@ impl Console {
 
     pub fn reset(&mut self) {
         let model = self.mmu.model;
-        self.cpu.reset(model.clone());
-        self.mmu.reset(model.clone());
-        self.mmu.timer.reset(model.clone());
-        self.mmu.ppu.reset(model.clone());
-        self.mmu.dma.reset(model);
+        self.cpu.reset(model);
+        self.mmu.reset(model);
+        self.overshoot = 0;
+        if let Some(buffer) = &mut self.rewind_buffer { buffer.clear(); }
+        if let Some(backtrace) = &mut self.backtrace { backtrace.clear(); }
+
+        if self.mmu.has_boot_rom() {
+            // The boot rom sets up the registers itself and hands over at 0x0100
+            self.cpu = Cpu::new();
+            self.mmu.map_boot_rom();
+        }
     }
 
-    pub fn execute_ticks(&mut self, ticks: u32) -> () {
-        for _i in 0 .. ticks {
-            self.execute_tick();
+    // Runs whole instructions for a budget of clocks. The last instruction usually runs past the
+    // budget, that overshoot is taken off the next call so repeated calls never drift.
+    // Returns the clocks actually executed.
+    pub fn execute_ticks(&mut self, ticks: u32) -> u32 {
+        if self.paused || !self.mmu.has_cartridge() { return 0; }
+        if self.overshoot >= ticks {
+            self.overshoot -= ticks;
+            return 0;
+        }
+
+        let budget = ticks - self.overshoot;
+        let mut executed = 0;
+        while executed < budget {
+            executed += self.execute_tick();
         }
+
+        self.overshoot = executed - budget;
+        return executed;
     }
 
-    pub fn execute_tick(&mut self) {
-        let cpu_ticks = self.cpu.execute_tick(&mut self.mmu) * 4;
-        self.mmu.execute_ticks(cpu_ticks);
+    // Runs one frame of clocks, or several while in turbo mode
+    pub fn execute_frame(&mut self) -> u32 {
+        if self.paused { return 0; }
+
+        self.mmu.ppu.take_frame_ready();
+        let mut executed = 0;
+        for _ in 0 .. self.turbo {
+            self.update_input_log();
+            executed += self.execute_ticks(TICKS_PER_FRAME);
+        }
+        self.mmu.apply_cheats();
+
+        if let Some(sink) = &mut self.audio_sink {
+            let samples = self.mmu.apu.drain_samples();
+            if !samples.is_empty() { sink.push_samples(&samples); }
+        }
+
+        if let Some(buffer) = &mut self.rewind_buffer {
+            let mut snapshot_due = false;
+            for _ in 0 .. self.turbo {
+                snapshot_due |= buffer.frame_elapsed();
+            }
+
+            if snapshot_due {
+                let state = self.save_state();
+                self.rewind_buffer.as_mut().unwrap().push(state);
+            }
+        }
+
+        return executed;
+    }
+
+    // Runs whole instructions until the PPU enters V-Blank, when a frame has just been completed.
+    // Follows the PPU rather than counting clocks, so it stays in step when the LCD is switched
+    // off and on mid frame. Returns the clocks executed.
+    pub fn run_until_vblank(&mut self) -> u32 {
+        if self.paused || !self.mmu.has_cartridge() { return 0; }
+
+        let mut executed = 0;
+        let mut in_v_blank = self.mmu.ppu.v_blank;
+
+        loop {
+            executed += self.execute_tick();
+
+            // With the LCD off there is no V-Blank, a frame worth of clocks stands in for it
+            if !self.mmu.ppu.lcd_enabled() && executed >= TICKS_PER_FRAME { return executed; }
+
+            let v_blank = self.mmu.ppu.v_blank;
+            if v_blank && !in_v_blank { return executed; }
+            in_v_blank = v_blank;
+        }
+    }
+
+    // Snapshots the console every interval frames run by execute_frame, keeping the last capacity
+    // snapshots. A capacity of 0 turns rewinding off.
+    pub fn set_rewind(&mut self, interval: usize, capacity: usize) {
+        self.rewind_buffer = if capacity == 0 { None } else { Some(RewindBuffer::new(interval, capacity)) };
+    }
+
+    // Goes back to the newest snapshot at least frames frames old, false if there is none
+    pub fn rewind(&mut self, frames: usize) -> bool {
+        let state = match self.rewind_buffer.as_mut().and_then(|buffer| buffer.rewind(frames)) {
+            Some(state) => state,
+            None => return false,
+        };
+
+        self.load_state(&state).expect("rewind snapshots are always valid");
+        return true;
+    }
+
+    // Draws only every (frames + 1)th frame, the frame buffer keeps the last drawn one meanwhile.
+    // Emulation is unaffected, the PPU just leaves out the pixel work on the skipped frames.
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.mmu.ppu.set_frame_skip(frames);
+    }
+
+    // While paused nothing runs, the frame buffer keeps the last frame and no sound is generated,
+    // so sinks play silence. The time spent paused does not count towards rewind or input logs.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.paused;
+    }
+
+    // Fast forward by running factor frames for every displayed one. Sound is muted
+    // meanwhile, it would otherwise pile up faster than it can be played. The APU keeps
+    // running so NR52 and the channels are where they should be once turbo ends.
+    pub fn set_turbo(&mut self, factor: u32) {
+        let starting = self.turbo == 1 && factor > 1;
+        self.turbo = factor.max(1);
+        self.mmu.apu.set_sample_output(self.audio_enabled && self.turbo == 1);
+
+        if starting {
+            if let Some(sink) = &mut self.audio_sink { sink.discard_queued(); }
+        }
+    }
+
+    pub fn execute_tick(&mut self) -> u32 {
+        if self.trace && !self.cpu.halted && !self.cpu.stopped {
+            Logger::trace_cpu(&self.cpu, &self.mmu);
+        }
+
+        if let Some(backtrace) = &mut self.backtrace {
+            if !self.cpu.halted && !self.cpu.stopped && !self.cpu.locked {
+                backtrace.record(TraceEntry { opcode: self.mmu.read_byte(self.cpu.pc), registers: self.cpu.registers() });
+            }
+        }
+
+        let pc = self.cpu.pc;
+        let locked = self.cpu.locked;
+        // Counted in clocks of the PPU so a frame is TICKS_PER_FRAME in double speed mode too,
+        // and a HDMA stall counts
+        self.cpu.execute_tick(&mut self.mmu);
+        let ticks = self.mmu.take_clocks();
+        if self.mmu.interrupt_writes.as_ref().is_some_and(|writes| !writes.is_empty()) {
+            self.collect_interrupt_writes(pc);
+        }
+        if self.cpu.locked && !locked {
+            self.report_lock_up();
+        }
+        return ticks;
     }
 
     pub fn get_frame(&self) -> js_sys::Array {
         return self.mmu.ppu.get_frame();
     }
 
-    pub fn get_sound(&self) -> js_sys::Int8Array {
-        return js_sys::Int8Array::new_with_length(0);
+    // The last completed frame as a PNG file. The PPU only publishes whole frames, so this
+    // never shows a frame that is still being drawn.
+    pub fn screenshot(&self) -> Vec<u8> {
+        let image = RgbaImage::from_raw(SCREEN_W as u32, SCREEN_H as u32, self.mmu.ppu.frame_rgba().to_vec())
+            .expect("frame has the screen dimensions");
+
+        let mut png = io::Cursor::new(Vec::new());
+        image.write_to(&mut png, ImageOutputFormat::Png).expect("unable to encode screenshot");
+        return png.into_inner();
+    }
+
+    pub fn serial_output(&self) -> String {
+        return self.mmu.serial.get_output().to_string();
+    }
+
+    pub fn get_sound(&mut self) -> js_sys::Float32Array {
+        let samples = self.mmu.apu.drain_samples();
+        return js_sys::Float32Array::from(&samples[..]);
+    }
+
+    // Logs every instruction in the Gameboy Doctor format, see Logger::format_cpu_trace
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    // Logs every write the program makes to IE and IF with the PC of the instruction and the
+    // interrupts it turned on or off, the entries are also kept for take_interrupt_trace
+    pub fn trace_interrupts(&mut self, enabled: bool) {
+        self.mmu.interrupt_writes = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // Keeps the last instructions executed for dump_backtrace, cheap enough to leave on
+    pub fn record_backtrace(&mut self, enabled: bool) {
+        self.backtrace = if enabled { Some(Backtrace::new()) } else { None };
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn add_watchpoint(&mut self, address: u16) {
+        self.mmu.watchpoints.insert(address);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u16) {
+        self.mmu.watchpoints.remove(&address);
+    }
+
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        return Console::new();
+    }
+}
+
+impl Console {
+
+    // Oldest first, empty unless record_backtrace is on
+    pub fn dump_backtrace(&self) -> Vec<TraceEntry> {
+        return self.backtrace.as_ref().map_or(Vec::new(), Backtrace::entries);
+    }
+
+    // Where set_trace writes its log file, cpu.log in the working directory by default
+    pub fn set_trace_path(&mut self, path: &Path) {
+        set_log_path(path);
+    }
+
+    // https://robertheaton.com/gameboy-doctor/
+    // Steps through a Gameboy Doctor log, which has a line for the state before every
+    // instruction in the format of Logger::format_cpu_trace. Stops at the first line that does
+    // not match, prints it and returns it. Returns the lines checked when the whole log matches.
+    pub fn run_with_reference(&mut self, reference: &Path) -> Result<usize, ReferenceError> {
+        let text = fs::read_to_string(reference).map_err(|_| ReferenceError::FileNotFound)?;
+
+        let recording = self.backtrace.is_some();
+        if !recording { self.record_backtrace(true); }
+
+        let mut checked = 0;
+        let mut result = Ok(());
+        for (index, line) in text.lines().enumerate() {
+            let expected = line.trim();
+            if expected.is_empty() { continue; }
+
+            // The log has no lines for the time spent halted
+            let mut waited = 0;
+            while (self.cpu.halted || self.cpu.stopped) && waited < TICKS_PER_FRAME {
+                waited += self.execute_tick();
+            }
+
+            let actual = Logger::format_cpu_trace(&self.cpu, &self.mmu);
+            if actual != expected {
+                let backtrace = self.dump_backtrace();
+                let start = backtrace.len().saturating_sub(REFERENCE_BACKTRACE_LENGTH);
+                result = Err(TraceMismatch { line: index + 1, expected: expected.to_string(), actual, backtrace: backtrace[start ..].to_vec() });
+                break;
+            }
+
+            self.execute_tick();
+            checked += 1;
+        }
+
+        if !recording { self.record_backtrace(false); }
+        return match result {
+            Ok(()) => Ok(checked),
+            Err(mismatch) => {
+                debug(&mismatch.to_string());
+                Err(ReferenceError::Mismatch(mismatch))
+            },
+        };
+    }
+
+    fn report_lock_up(&self) {
+        let entries = self.dump_backtrace();
+        if entries.is_empty() { return; }
+
+        debug(&format!("last {} instructions before the lock up:", entries.len()));
+        for entry in entries.iter() {
+            debug(&entry.to_string());
+        }
+    }
+
+    #[cfg(feature = "coverage")]
+    pub fn coverage_report(&self) -> Vec<u16> {
+        return self.cpu.coverage_report();
+    }
+
+    // The IE and IF writes logged since the last call
+    pub fn take_interrupt_trace(&mut self) -> Vec<InterruptWrite> {
+        return std::mem::take(&mut self.interrupt_trace);
+    }
+
+    // The Super Game Boy commands the game sent since the last call, none of them are acted on
+    pub fn take_sgb_commands(&mut self) -> Vec<SgbPacket> {
+        return self.mmu.sgb.take_commands();
+    }
+
+    // A hash of the CPU registers and the memory and IO registers of the machine. Two builds
+    // running the same rom for the same clocks agree on it until their emulation differs.
+    pub fn hash_state(&self) -> u64 {
+        let mut hasher = StateHasher(0xCBF29CE484222325);
+        let r = self.cpu.registers();
+        hasher.write(&[r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l]);
+        hasher.write(&r.sp.to_le_bytes());
+        hasher.write(&r.pc.to_le_bytes());
+        hasher.write(&[r.interrupt_master_enable as u8, r.halted as u8]);
+        self.mmu.hash_state(&mut hasher);
+        return hasher.finish();
+    }
+
+    fn collect_interrupt_writes(&mut self, pc: u16) {
+        let writes = match &mut self.mmu.interrupt_writes {
+            Some(writes) => std::mem::take(writes),
+            None => return,
+        };
+
+        for mut write in writes {
+            write.pc = pc;
+            debug(&write.to_string());
+            self.interrupt_trace.push(write);
+        }
+    }
+
+    // Captures the joypad state at the start of every frame run by execute_frame
+    pub fn start_recording(&mut self) {
+        self.recording = Some(InputLog::new());
+        self.input_frame = 0;
+    }
+
+    pub fn stop_recording(&mut self) -> InputLog {
+        return self.recording.take().unwrap_or_default();
+    }
+
+    // Replays a recording from the next frame on, replacing the buttons pressed meanwhile.
+    // Started from the state the recording started from the run plays out the same.
+    pub fn play_input_log(&mut self, log: InputLog) {
+        self.playback = Some(InputPlayback::new(log));
+        self.input_frame = 0;
+    }
+
+    fn update_input_log(&mut self) {
+        if let Some(playback) = &mut self.playback {
+            if let Some(buttons) = playback.buttons_at(self.input_frame) {
+                self.mmu.joypad.apply_state(InputState(buttons));
+            }
+            if playback.is_finished() { self.playback = None; }
+        }
+
+        if let Some(log) = &mut self.recording {
+            log.record(self.input_frame, self.mmu.joypad.current_state().0);
+        }
+        self.input_frame += 1;
+    }
+
+    // Console::new starts from zeroed memory, this one fills WRAM, HRAM, VRAM and OAM with pattern
+    pub fn new_with_init_pattern(pattern: InitPattern) -> Self {
+        let mut console = Console::new();
+        console.mmu.init_memory(pattern);
+        console.init_pattern = pattern;
+        return console;
+    }
+
+    pub fn load_from_file(&mut self, file_path: &str) -> Result<(), LoadError> {
+        let bytes = fs::read(file_path).map_err(|_| LoadError::FileNotFound)?;
+        self.insert_cartridge(bytes)?;
+
+        if !self.mmu.cartridge.has_battery() { return Ok(()); }
+
+        // Battery backed RAM is kept in a .sav next to the rom
+        let save_path = Path::new(file_path).with_extension("sav");
+        if let Ok(snapshot) = fs::read(&save_path) {
+            self.mmu.cartridge.load_ram(&snapshot);
+        }
+        self.save_path = Some(save_path);
+        return Ok(());
+    }
+
+    pub fn save_battery_ram(&self) -> io::Result<()> {
+        return match &self.save_path {
+            Some(save_path) => fs::write(save_path, self.mmu.cartridge.ram_snapshot()),
+            None => Ok(()),
+        };
+    }
+
+    // Takes effect on the next reset
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        return self.mmu.load_boot_rom(data);
+    }
+
+    pub fn load_from_bytes(&mut self, bytes: Vec<u8>) -> Result<(), LoadError> {
+        return self.insert_cartridge(bytes);
+    }
+
+    // Swapping cartridges is a power cycle, nothing of the previous game carries over besides
+    // the battery RAM it writes out first. The current cartridge is left running when the rom
+    // is rejected.
+    fn insert_cartridge(&mut self, bytes: Vec<u8>) -> Result<(), LoadError> {
+        let mut mmu = Mmu::new();
+        mmu.load_cartridge(bytes)?;
+        mmu.init_memory(self.init_pattern);
+
+        if let Err(error) = self.save_battery_ram() {
+            warn(&format!("unable to write the save file: {}", error));
+        }
+        self.save_path = None;
+
+        self.mmu.replace(mmu);
+        self.mmu.cheats.clear();
+        self.symbols = SymbolTable::new();
+        self.cpu = Cpu::new();
+        self.recording = None;
+        self.playback = None;
+        self.input_frame = 0;
+        self.interrupt_trace.clear();
+        self.reset();
+        return Ok(());
+    }
+
+    // The cartridge rom and rendered frames are not included
+    pub fn save_state(&self) -> Vec<u8> {
+        let header = SaveStateHeader {
+            magic: SAVE_STATE_MAGIC,
+            version: SAVE_STATE_VERSION,
+        };
+
+        return bincode::serialize(&(header, &self.cpu, &self.mmu, self.overshoot)).expect("unable to serialize state");
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let header: SaveStateHeader = bincode::deserialize(data).map_err(|_| SaveStateError::InvalidData)?;
+        if header.magic != SAVE_STATE_MAGIC { return Err(SaveStateError::InvalidData); }
+        if header.version != SAVE_STATE_VERSION { return Err(SaveStateError::UnsupportedVersion(header.version)); }
+
+        let (_, cpu, mmu, overshoot): (SaveStateHeader, Cpu, Mmu, u32) = bincode::deserialize(data).map_err(|_| SaveStateError::InvalidData)?;
+        self.cpu = cpu;
+        self.mmu.load_state(mmu);
+        self.overshoot = overshoot;
+        return Ok(());
+    }
+
+    // See Ppu::frame_buffer for the pixel format
+    pub fn frame_buffer(&self) -> &[u32] {
+        return self.mmu.ppu.frame_buffer();
+    }
+
+    pub fn cartridge_header(&self) -> &CartridgeHeader {
+        return self.mmu.cartridge.header();
+    }
+
+    pub fn set_dmg_palette(&mut self, colors: [u32; 4]) {
+        self.mmu.ppu.set_dmg_palette(colors);
+    }
+
+    // Sets all eight buttons at once, see Joypad::apply_state
+    pub fn set_input_state(&mut self, state: InputState) {
+        self.mmu.joypad.apply_state(state);
+    }
+
+    // See Ppu::set_scanline_callback
+    pub fn set_scanline_callback(&mut self, callback: Box<dyn FnMut(u8)>) {
+        self.mmu.ppu.set_scanline_callback(callback);
+    }
+
+    // See Ppu::set_vblank_callback
+    pub fn set_vblank_callback(&mut self, callback: VBlankCallback) {
+        self.mmu.ppu.set_vblank_callback(callback);
+    }
+
+    pub fn frame_rgba(&self) -> &[u8] {
+        return self.mmu.ppu.frame_rgba();
+    }
+
+    pub fn frame_ready(&self) -> bool {
+        return self.mmu.ppu.frame_ready();
+    }
+
+    // Runs exactly one instruction. A breakpoint is reported when the next instruction
+    // to execute sits on it, so stepping again always moves past it.
+    pub fn step(&mut self) -> StepResult {
+        let ticks = self.execute_tick();
+
+        if let Some(address) = self.mmu.watchpoint_hit.take() {
+            return StepResult::WatchpointHit(address);
+        }
+        if self.breakpoints.contains(&self.cpu.pc) {
+            return StepResult::BreakpointHit(self.cpu.pc);
+        }
+        return StepResult::Stepped(ticks);
+    }
+
+    // Runs at least ticks clocks of whole instructions, stopping early after an LD B,B that
+    // reports a Mooneye test result
+    pub fn run_until_mooneye_result(&mut self, ticks: u32) -> Option<MooneyeResult> {
+        if self.paused || !self.mmu.has_cartridge() { return None; }
+
+        let mut executed = 0;
+        while executed < ticks {
+            let breakpoint = !self.cpu.halted && self.mmu.read_byte(self.cpu.pc) == 0x40;
+            executed += self.execute_tick();
+
+            if breakpoint {
+                let result = MooneyeResult::from_registers(&self.cpu.registers());
+                if result.is_some() { return result; }
+            }
+        }
+        return None;
+    }
+
+    // Like step, except that a CALL or RST runs until the subroutine returns to the next instruction.
+    // A subroutine still running after a frame is left where it is, it may never return.
+    pub fn step_over(&mut self) -> StepResult {
+        let pc = self.cpu.pc;
+        let sp = self.cpu.sp;
+        let opcode = self.mmu.read_bus(pc);
+
+        // CALL, CALL cc and RST
+        let is_call = opcode == 0xCD || opcode & 0xE7 == 0xC4 || opcode & 0xC7 == 0xC7;
+        if !is_call { return self.step(); }
+
+        let (_, length) = operations::disassemble(&self.mmu, pc);
+        let return_address = pc.wrapping_add(length as u16);
+
+        let mut elapsed = 0;
+        loop {
+            match self.step() {
+                StepResult::Stepped(ticks) => elapsed += ticks,
+                result => return result,
+            }
+
+            // A recursive call can pass the same address deeper in the stack
+            if self.cpu.pc == return_address && self.cpu.sp >= sp {
+                return StepResult::Stepped(elapsed);
+            }
+            if elapsed >= TICKS_PER_FRAME {
+                return StepResult::NotReturned(elapsed);
+            }
+        }
+    }
+
+    // Mnemonic and length of the instruction at address, for a debugger's instruction view.
+    // With symbols loaded a labeled instruction reads "Main: CALL Update" instead of
+    // "CALL $4123".
+    pub fn disassemble(&self, address: u16) -> (String, u8) {
+        let (mut text, length) = operations::disassemble(&self.mmu, address);
+        if self.symbols.is_empty() { return (text, length); }
+
+        // Addresses are the only 4 digit operands
+        if let Some(start) = text.find('$') {
+            let digits = &text[start + 1 ..];
+            let end = digits.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(digits.len());
+            if end == 4 {
+                let target = u16::from_str_radix(&digits[.. 4], 16).unwrap();
+                if let Some(label) = self.label(target) {
+                    text.replace_range(start .. start + 5, label);
+                }
+            }
+        }
+
+        return match self.label(address) {
+            Some(label) => (format!("{}: {}", label, text), length),
+            None => (text, length),
+        };
+    }
+
+    // Reads a .sym file, replacing the symbols loaded before
+    pub fn load_symbols(&mut self, path: &str) -> Result<(), SymbolError> {
+        let text = fs::read_to_string(path).map_err(|_| SymbolError::FileNotFound)?;
+        self.symbols = SymbolTable::parse(&text)?;
+        return Ok(());
+    }
+
+    // Breaks at a label of the symbol file, whichever bank is mapped when it is reached
+    pub fn add_breakpoint_named(&mut self, name: &str) -> Result<u16, SymbolError> {
+        let (_, address) = self.symbols.address(name).ok_or_else(|| SymbolError::UnknownSymbol(name.to_string()))?;
+        self.add_breakpoint(address);
+        return Ok(address);
     }
 
+    // The label at address in the bank that is mapped there right now
+    pub fn label(&self, address: u16) -> Option<&str> {
+        return self.symbols.label(self.mapped_bank(address), address);
+    }
+
+    fn mapped_bank(&self, address: u16) -> u16 {
+        return match address {
+            0x4000 ..= 0x7FFF => self.mmu.cartridge.mapped_rom_bank(address) as u16,
+            0xD000 ..= 0xDFFF => self.mmu.wram_bank() as u16,
+            _ => 0,
+        };
+    }
+
+    // Steps until at least `ticks` T-cycles have run or a breakpoint or watchpoint is hit
+    pub fn run(&mut self, ticks: u32) -> StepResult {
+        let mut elapsed = 0;
+        while elapsed < ticks {
+            match self.step() {
+                StepResult::Stepped(step_ticks) => elapsed += step_ticks,
+                result => return result,
+            }
+        }
+        return StepResult::Stepped(elapsed);
+    }
+
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        self.audio_enabled = enabled;
+        self.mmu.apu.set_sample_output(enabled && self.turbo == 1);
+    }
+
+    // Mutes or unmutes a channel in the sound output, the game sees no difference
+    pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+        self.mmu.apu.set_channel_enabled(channel, enabled);
+    }
+
+    pub fn is_channel_enabled(&self, channel: Channel) -> bool {
+        return self.mmu.apu.channel_enabled(channel);
+    }
+
+    // Receives the sound of every frame run by execute_frame
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio_sink = Some(sink);
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        return self.mmu.apu.drain_samples();
+    }
+
+    // Sample pairs per second handed to the audio sink and drain_samples, see Apu::set_sample_rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mmu.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        return self.mmu.apu.sample_rate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::ppu::DMG_PALETTE_GREEN;
+    use crate::mmu::Speed;
+
+    // Fills 0xC000 - 0xDFFF with an incrementing counter forever
+    fn create_test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x21, 0x00, 0xC0, // LD HL, 0xC000
+            0x04,             // INC B
+            0x70,             // LD (HL), B
+            0x23,             // INC HL
+            0x7C,             // LD A, H
+            0xE6, 0x1F,       // AND 0x1F
+            0xF6, 0xC0,       // OR 0xC0
+            0x67,             // LD H, A
+            0x18, 0xF5,       // JR -11
+        ];
+        rom[0x100 .. 0x100 + program.len()].copy_from_slice(&program);
+        return rom;
+    }
+
+    fn create_console() -> Console {
+        let mut console = Console::new();
+        console.load_from_bytes(create_test_rom()).unwrap();
+        console.reset();
+        return console;
+    }
+
+    #[test]
+    fn run_until_vblank_advances_one_frame() {
+        let mut console = create_console();
+        console.run_until_vblank();
+        assert_eq!(console.mmu.read_byte(0xFF44), 144);
+
+        for _ in 0 .. 2 {
+            let executed = console.run_until_vblank();
+            // The last instruction can run a few clocks into V-Blank
+            assert!(executed.abs_diff(TICKS_PER_FRAME) < 24, "{}", executed);
+            assert_eq!(console.mmu.read_byte(0xFF44), 144);
+        }
+    }
+
+    #[test]
+    fn paused_console_does_not_run() {
+        let mut console = create_console();
+        console.execute_frame();
+        let pc = console.cpu.registers().pc;
+        let frame = console.frame_buffer().to_vec();
+        console.drain_samples();
+
+        console.set_paused(true);
+        for _ in 0 .. 100 {
+            assert_eq!(console.execute_ticks(TICKS_PER_FRAME), 0);
+        }
+        assert_eq!(console.run_until_vblank(), 0);
+        assert_eq!(console.execute_frame(), 0);
+        assert_eq!(console.cpu.registers().pc, pc);
+        assert_eq!(console.frame_buffer(), &frame[..]);
+        assert!(console.drain_samples().is_empty());
+
+        console.set_paused(false);
+        assert!(console.execute_frame() > 0);
+    }
+
+    #[test]
+    fn cheats_patch_rom_and_poke_ram() {
+        let mut console = create_console();
+        // INC B at 0x0103 becomes INC C, compare value 0x04
+        console.add_cheat("0C1-03F-F0A").unwrap();
+        console.add_cheat("01A5FFC1").unwrap();
+        assert_eq!(console.mmu.read_byte(0x0103), 0x0C);
+        assert_eq!(console.mmu.dump_range(0x0103, 1), vec![0x04]);
+
+        console.execute_frame();
+        assert_eq!(console.mmu.read_byte(0xC1FF), 0xA5);
+        assert_eq!(console.cpu.registers().b, 0);
+
+        console.clear_cheats();
+        assert_eq!(console.mmu.read_byte(0x0103), 0x04);
+        assert_eq!(console.add_cheat("0C1-03F"), Ok(()));
+        assert_eq!(console.add_cheat("XYZ"), Err(CheatError::InvalidDigit('X')));
+    }
+
+    #[test]
+    fn console_without_rom_shows_message() {
+        let mut console = Console::new();
+        let light = DMG_PALETTE_GREEN[0] & 0xFFFFFF;
+        let dark = DMG_PALETTE_GREEN[3] & 0xFFFFFF;
+
+        let text_pixels = |console: &Console| console.frame_buffer().iter().filter(|&&pixel| pixel == dark).count();
+        let pixels = text_pixels(&console);
+        assert!(pixels > 100, "{}", pixels);
+        assert!(console.frame_buffer().iter().all(|&pixel| pixel == light || pixel == dark));
+
+        // Text sits on the middle rows only
+        assert!(console.frame_buffer()[.. SCREEN_W * 60].iter().all(|&pixel| pixel == light));
+        assert!(console.frame_buffer()[SCREEN_W * 84 ..].iter().all(|&pixel| pixel == light));
+
+        // Nothing runs until a rom is loaded
+        assert_eq!(console.execute_frame(), 0);
+        assert_eq!(console.run_until_vblank(), 0);
+        assert_eq!(text_pixels(&console), pixels);
+    }
+
+    // Copies the action buttons into WRAM over and over
+    fn create_joypad_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x21, 0x00, 0xC0, // LD HL, 0xC000
+            0x3E, 0x10,       // LD A, 0x10
+            0xE0, 0x00,       // LDH (0x00), A
+            0xF0, 0x00,       // LDH A, (0x00)
+            0x22,             // LD (HL+), A
+            0x7C,             // LD A, H
+            0xE6, 0x1F,       // AND 0x1F
+            0xF6, 0xC0,       // OR 0xC0
+            0x67,             // LD H, A
+            0x18, 0xF1,       // JR -15
+        ];
+        rom[0x100 .. 0x100 + program.len()].copy_from_slice(&program);
+        return rom;
+    }
+
+    #[test]
+    fn input_log_replays_identically() {
+        let mut console = Console::new();
+        console.load_from_bytes(create_joypad_rom()).unwrap();
+        console.reset();
+        let start = console.save_state();
+
+        console.start_recording();
+        for frame in 0 .. 12 {
+            match frame {
+                2 => console.press_button(Button::A),
+                5 => console.release_button(Button::A),
+                7 => console.press_button(Button::START),
+                _ => {},
+            }
+            console.execute_frame();
+        }
+        let log = console.stop_recording();
+        let recorded = console.mmu.dump_range(0xC000, 0x2000);
+        assert_eq!(log.events().len(), 4);
+
+        console.load_state(&start).unwrap();
+        for _ in 0 .. 12 { console.execute_frame(); }
+        assert_ne!(console.mmu.dump_range(0xC000, 0x2000), recorded);
+
+        console.load_state(&start).unwrap();
+        console.play_input_log(log);
+        for _ in 0 .. 12 { console.execute_frame(); }
+        assert_eq!(console.mmu.dump_range(0xC000, 0x2000), recorded);
+    }
+
+    fn dump_memory(console: &Console) -> Vec<u8> {
+        let mut dump = console.mmu.dump_range(0x8000, 0x2000);
+        dump.extend(console.mmu.dump_range(0xC000, 0x2000));
+        dump.extend(console.mmu.dump_range(0xFE00, 0xA0));
+        dump.extend(console.mmu.dump_range(0xFF80, 0x7F));
+        return dump;
+    }
+
+    #[test]
+    fn init_pattern_seeds_memory() {
+        let first = dump_memory(&Console::new_with_init_pattern(InitPattern::Seeded(42)));
+        let second = dump_memory(&Console::new_with_init_pattern(InitPattern::Seeded(42)));
+        assert_eq!(first, second);
+        assert_ne!(first, dump_memory(&Console::new_with_init_pattern(InitPattern::Seeded(43))));
+        assert!(first.iter().any(|&byte| byte != 0x00) && first.iter().any(|&byte| byte != 0xFF));
+
+        let ones = dump_memory(&Console::new_with_init_pattern(InitPattern::Ones));
+        assert!(ones.iter().all(|&byte| byte == 0xFF));
+        assert_eq!(dump_memory(&Console::new_with_init_pattern(InitPattern::Zero)), dump_memory(&Console::new()));
+    }
+
+    #[test]
+    fn save_state_round_trip_is_deterministic() {
+        let mut console = create_console();
+        console.execute_ticks(5000);
+        let state = console.save_state();
+
+        console.execute_ticks(5000);
+        let expected = console.save_state();
+
+        console.load_state(&state).unwrap();
+        console.execute_ticks(5000);
+        assert_eq!(console.save_state(), expected);
+    }
+
+    #[test]
+    fn load_state_rejects_other_formats() {
+        let mut console = create_console();
+        let mut state = console.save_state();
+
+        assert_eq!(console.load_state(&state[.. 3]), Err(SaveStateError::InvalidData));
+        assert_eq!(console.load_state(&state[.. 100]), Err(SaveStateError::InvalidData));
+
+        state[4] = 0xFF;
+        assert_eq!(console.load_state(&state), Err(SaveStateError::UnsupportedVersion(0xFF)));
+
+        state[0] = b'X';
+        assert_eq!(console.load_state(&state), Err(SaveStateError::InvalidData));
+    }
+
+    #[test]
+    fn load_rejects_truncated_rom() {
+        let mut console = Console::new();
+        assert_eq!(console.load_from_bytes(vec![0; 16]), Err(LoadError::HeaderTooShort(16)));
+    }
+
+    #[test]
+    fn load_rejects_unsupported_cartridge() {
+        let mut console = Console::new();
+        let mut rom = create_test_rom();
+        rom[0x147] = 0xFC; // Pocket Camera
+        assert_eq!(console.load_from_bytes(rom), Err(LoadError::UnsupportedCartridge(0xFC)));
+    }
+
+    #[test]
+    fn load_reports_missing_file() {
+        let mut console = Console::new();
+        assert_eq!(console.load_from_file("./roms/does_not_exist.gb"), Err(LoadError::FileNotFound));
+    }
+
+    #[test]
+    fn run_stops_at_breakpoint() {
+        let mut console = create_console();
+        console.add_breakpoint(0x0105);
+
+        assert_eq!(console.run(1_000_000), StepResult::BreakpointHit(0x0105));
+        assert_eq!(console.cpu.pc, 0x0105);
+        assert_eq!(console.mmu.read_byte(0xC000), 0x01);
+
+        // Stepping moves past the breakpoint and the loop hits it again on the next pass
+        assert!(matches!(console.step(), StepResult::Stepped(_)));
+        assert_eq!(console.cpu.pc, 0x0106);
+        assert_eq!(console.run(1_000_000), StepResult::BreakpointHit(0x0105));
+        assert_eq!(console.mmu.read_byte(0xC001), 0x02);
+
+        console.remove_breakpoint(0x0105);
+        assert_eq!(console.run(1000), StepResult::Stepped(1000));
+    }
+
+    #[test]
+    fn run_stops_on_watched_write() {
+        let mut console = create_console();
+        console.add_watchpoint(0xC002);
+
+        assert_eq!(console.run(1_000_000), StepResult::WatchpointHit(0xC002));
+        assert_eq!(console.cpu.pc, 0x0105);
+        assert_eq!(console.mmu.read_byte(0xC002), 0x03);
+        assert_eq!(console.mmu.read_byte(0xC003), 0x00);
+    }
+
+    #[test]
+    fn execute_ticks_carries_overshoot() {
+        let mut console = create_console();
+
+        let mut requested = 0;
+        let mut executed = 0;
+        for _ in 0 .. 1000 {
+            requested += 7;
+            executed += console.execute_ticks(7);
+            assert!(executed >= requested);
+            assert!(executed - requested < 24);
+        }
+    }
+
+    #[test]
+    fn boot_rom_is_unmapped_by_ff50() {
+        let mut rom = create_test_rom();
+        rom[0x0000] = 0xAA;
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[.. 4].copy_from_slice(&[
+            0x3E, 0x01, // LD A, 0x01
+            0xE0, 0x50, // LDH (0x50), A
+        ]);
+
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        assert_eq!(console.load_boot_rom(&boot_rom[.. 0x80]), Err(LoadError::InvalidBootRomSize(0x80)));
+        console.load_boot_rom(&boot_rom).unwrap();
+        console.reset();
+
+        assert_eq!(console.cpu.pc, 0x0000);
+        assert_eq!(console.mmu.read_byte(0x0000), 0x3E);
+
+        console.step();
+        assert_eq!(console.mmu.read_byte(0x0000), 0x3E);
+        console.step();
+        assert_eq!(console.cpu.pc, 0x0004);
+        assert_eq!(console.mmu.read_byte(0x0000), 0xAA);
+        assert_eq!(console.mmu.read_byte(0x0100), 0x21);
+    }
+
+    #[test]
+    fn reset_sets_post_boot_registers() {
+        let console = create_console();
+        let cpu = &console.cpu;
+        assert_eq!((cpu.get_af(), cpu.get_bc(), cpu.get_de(), cpu.get_hl()), (0x01B0, 0x0013, 0x00D8, 0x014D));
+        assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+
+        let mut rom = create_test_rom();
+        rom[0x143] = 0x80;
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+        let cpu = &console.cpu;
+        assert_eq!((cpu.get_af(), cpu.get_bc(), cpu.get_de(), cpu.get_hl()), (0x1180, 0x0000, 0xFF56, 0x000D));
+        assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+    }
+
+    #[test]
+    fn loading_another_rom_starts_from_a_clean_console() {
+        let save_path = std::env::temp_dir().join(format!("rustyboy-load-test-{}.sav", std::process::id()));
+        let mut first_rom = create_test_rom();
+        first_rom[0x143] = 0x80; // Color
+        first_rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+        first_rom[0x149] = 0x02; // 8 KiB RAM
+
+        let mut console = Console::new();
+        console.load_from_bytes(first_rom).unwrap();
+        console.reset();
+        console.save_path = Some(save_path.clone());
+        console.add_cheat("00C0FFC0").unwrap();
+        console.execute_frame();
+        console.mmu.write_byte(0x0000, 0x0A);
+        console.mmu.write_byte(0xA000, 0x42);
+        console.mmu.write_byte(0x2000, 0x03);
+        assert_ne!(console.mmu.read_byte(0xC000), 0x00);
+
+        let mut fresh = Console::new();
+        fresh.load_from_bytes(create_test_rom()).unwrap();
+        fresh.reset();
+        console.load_from_bytes(create_test_rom()).unwrap();
+        console.reset();
+
+        let saved = fs::read(&save_path).unwrap();
+        fs::remove_file(&save_path).unwrap();
+        assert_eq!(saved[0], 0x42);
+        assert_eq!(console.save_path, None);
+        assert!(console.mmu.cheats.game_shark_codes().is_empty());
+        assert_eq!(console.mmu.read_byte(0xC000), 0x00);
+        assert_eq!(console.save_state(), fresh.save_state());
+    }
+
+    #[test]
+    fn rejected_rom_keeps_the_current_cartridge() {
+        let mut console = create_console();
+        console.execute_frame();
+        let state = console.save_state();
+
+        assert_eq!(console.load_from_bytes(vec![0; 16]), Err(LoadError::HeaderTooShort(16)));
+        assert_eq!(console.save_state(), state);
+    }
+
+    fn create_call_console() -> Console {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 .. 0x104].copy_from_slice(&[
+            0xCD, 0x00, 0x02, // CALL 0x0200
+            0x00,             // NOP
+        ]);
+        rom[0x200 .. 0x203].copy_from_slice(&[
+            0x3C,             // INC A
+            0x3C,             // INC A
+            0xC9,             // RET
+        ]);
+
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+        return console;
+    }
+
+    #[test]
+    fn symbols_label_disassembly_and_breakpoints() {
+        let path = std::env::temp_dir().join(format!("rustyboy-symbols-test-{}.sym", std::process::id()));
+        fs::write(&path, "; test symbols\n00:0100 Main\n00:0200 AddTwo\n01:4000 BankOne\n02:4000 BankTwo\n").unwrap();
+
+        let mut console = create_call_console();
+        let result = console.load_symbols(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(console.disassemble(0x0100), ("Main: CALL AddTwo".to_string(), 3));
+        assert_eq!(console.disassemble(0x0103), ("NOP".to_string(), 1));
+
+        assert_eq!(console.add_breakpoint_named("AddTwo"), Ok(0x0200));
+        assert_eq!(console.add_breakpoint_named("Missing"), Err(SymbolError::UnknownSymbol("Missing".to_string())));
+        assert_eq!(console.run(1000), StepResult::BreakpointHit(0x0200));
+
+        assert_eq!(console.load_symbols("missing.sym"), Err(SymbolError::FileNotFound));
+    }
+
+    #[test]
+    fn banked_symbols_follow_the_mapped_bank() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01; // MBC1
+        rom[0x148] = 0x01; // 4 banks
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+        console.symbols = SymbolTable::parse("01:4000 BankOne\n02:4000 BankTwo\n").unwrap();
+
+        assert_eq!(console.label(0x4000), Some("BankOne"));
+        console.mmu.write_byte(0x2000, 0x02);
+        assert_eq!(console.label(0x4000), Some("BankTwo"));
+        console.mmu.write_byte(0x2000, 0x03);
+        assert_eq!(console.label(0x4000), None);
+    }
+
+    #[test]
+    fn backtrace_holds_the_last_instructions() {
+        let mut console = create_console();
+        assert!(console.dump_backtrace().is_empty());
+
+        console.record_backtrace(true);
+        for _ in 0 .. 10 {
+            console.step();
+        }
+
+        // The loop of create_test_rom starts at 0x0103 and jumps back from 0x010C
+        let pcs: Vec<u16> = console.dump_backtrace().iter().map(|entry| entry.registers.pc).collect();
+        assert_eq!(pcs, vec![0x0100, 0x0103, 0x0104, 0x0105, 0x0106, 0x0107, 0x0109, 0x010B, 0x010C, 0x0103]);
+        assert_eq!(console.dump_backtrace()[0].opcode, 0x21);
+
+        // An illegal opcode stops the recording, the lock up is the last entry
+        console.mmu.write_byte(0xC000, 0xDD);
+        console.cpu.pc = 0xC000;
+        console.execute_ticks(100);
+        let backtrace = console.dump_backtrace();
+        assert_eq!(backtrace.last().unwrap().registers.pc, 0xC000);
+        assert_eq!(backtrace.last().unwrap().opcode, 0xDD);
+        assert_eq!(backtrace.len(), 11);
+    }
+
+    #[test]
+    fn reference_log_mismatch_is_reported_at_its_line() {
+        let mut reference = create_console();
+        let mut lines = Vec::new();
+        for _ in 0 .. 20 {
+            lines.push(Logger::format_cpu_trace(&reference.cpu, &reference.mmu));
+            reference.step();
+        }
+        let path = std::env::temp_dir().join(format!("rustyboy-reference-test-{}.log", std::process::id()));
+
+        fs::write(&path, lines.join("\n")).unwrap();
+        assert_eq!(create_console().run_with_reference(&path), Ok(20));
+
+        // B is off by one from the 6th instruction on
+        let actual = lines[5].clone();
+        lines[5] = lines[5].replace("B:01", "B:02");
+        fs::write(&path, lines.join("\n")).unwrap();
+        let result = create_console().run_with_reference(&path);
+        fs::remove_file(&path).unwrap();
+
+        let mismatch = match result {
+            Err(ReferenceError::Mismatch(mismatch)) => mismatch,
+            other => panic!("expected a mismatch, got {:?}", other),
+        };
+        assert_eq!(mismatch.line, 6);
+        assert_eq!(mismatch.expected, lines[5]);
+        assert_eq!(mismatch.actual, actual);
+        assert_eq!(mismatch.backtrace.len(), 5);
+        assert_eq!(mismatch.backtrace.last().unwrap().registers.pc, 0x0106);
+
+        assert_eq!(create_console().run_with_reference(Path::new("missing.log")), Err(ReferenceError::FileNotFound));
+    }
+
+    #[test]
+    fn step_over_runs_whole_subroutine() {
+        let mut console = create_call_console();
+        let a = console.cpu.a;
+
+        assert!(matches!(console.step_over(), StepResult::Stepped(_)));
+        assert_eq!(console.cpu.pc, 0x0103);
+        assert_eq!(console.cpu.sp, 0xFFFE);
+        assert_eq!(console.cpu.a, a + 2);
+    }
+
+    #[test]
+    fn step_enters_subroutine() {
+        let mut console = create_call_console();
+
+        console.step();
+        assert_eq!(console.cpu.pc, 0x0200);
+
+        // Not a call, so step_over behaves like step
+        console.step_over();
+        assert_eq!(console.cpu.pc, 0x0201);
+    }
+
+    #[test]
+    fn step_over_stops_at_breakpoint_inside_subroutine() {
+        let mut console = create_call_console();
+        console.add_breakpoint(0x0202);
+
+        assert_eq!(console.step_over(), StepResult::BreakpointHit(0x0202));
+    }
+
+    #[test]
+    fn step_over_gives_up_on_a_subroutine_that_never_returns() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 .. 0x103].copy_from_slice(&[0xCD, 0x00, 0x02]); // CALL 0x0200
+        rom[0x200 .. 0x202].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+
+        match console.step_over() {
+            StepResult::NotReturned(elapsed) => assert!(elapsed >= TICKS_PER_FRAME && elapsed < TICKS_PER_FRAME + 24),
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(console.cpu.pc, 0x0200);
+    }
+
+    #[test]
+    fn frame_ready_after_execute_frame() {
+        let mut console = create_console();
+        assert!(!console.frame_ready());
+
+        console.execute_frame();
+        assert!(console.frame_ready());
+        console.execute_ticks(4);
+        assert!(console.frame_ready());
+    }
+
+    #[test]
+    fn turbo_runs_several_frames_without_sound() {
+        let mut console = create_console();
+
+        let executed = console.execute_frame();
+        assert!(executed >= TICKS_PER_FRAME && executed < TICKS_PER_FRAME + 24);
+        assert!(console.mmu.apu.sample_output());
+
+        console.set_turbo(4);
+        let executed = console.execute_frame();
+        assert!(executed >= 4 * TICKS_PER_FRAME - 24 && executed < 4 * TICKS_PER_FRAME + 24);
+        assert!(!console.mmu.apu.sample_output());
+
+        console.set_turbo(1);
+        assert!(console.mmu.apu.sample_output());
+
+        // Leaving turbo mode does not turn sound back on if it was disabled
+        console.set_audio_enabled(false);
+        console.set_turbo(4);
+        console.set_turbo(1);
+        assert!(!console.mmu.apu.sample_output());
+    }
+
+    #[test]
+    fn rewind_restores_earlier_frame() {
+        let mut console = create_console();
+        console.set_rewind(1, 60);
+
+        let mut history = Vec::new();
+        for _ in 0 .. 20 {
+            console.execute_frame();
+            history.push((console.cpu.pc, console.save_state()));
+        }
+
+        let state_size = history[0].1.len();
+        let rewind_size = console.rewind_buffer.as_ref().unwrap().memory_size();
+        assert!(rewind_size < state_size * 4, "{} bytes for 20 snapshots of {}", rewind_size, state_size);
+
+        assert!(console.rewind(5));
+        assert_eq!(console.cpu.pc, history[14].0);
+        assert_eq!(console.save_state(), history[14].1);
+
+        // Running forward again reproduces the same frames
+        console.execute_frame();
+        assert_eq!(console.save_state(), history[15].1);
+    }
+
+    #[test]
+    fn rewind_without_snapshots_does_nothing() {
+        let mut console = create_console();
+        assert!(!console.rewind(1));
+
+        console.set_rewind(1, 10);
+        assert!(!console.rewind(1));
+    }
+
+    struct CountingSink {
+        counts: Rc<RefCell<Vec<usize>>>,
+        discards: Rc<RefCell<u32>>,
+    }
+
+    impl AudioSink for CountingSink {
+        fn push_samples(&mut self, samples: &[f32]) {
+            self.counts.borrow_mut().push(samples.len());
+        }
+
+        fn discard_queued(&mut self) {
+            *self.discards.borrow_mut() += 1;
+        }
+    }
+
+    #[test]
+    fn audio_sink_receives_one_frame_of_samples_per_frame() {
+        let mut console = create_console();
+        let counts = Rc::new(RefCell::new(Vec::new()));
+        console.set_audio_sink(Box::new(CountingSink { counts: counts.clone(), discards: Rc::new(RefCell::new(0)) }));
+
+        for _ in 0 .. 3 {
+            console.execute_frame();
+        }
+
+        // 44100 stereo pairs a second at about 59.73 frames a second
+        let counts = counts.borrow();
+        assert_eq!(counts.len(), 3);
+        assert!(counts.iter().all(|&count| count >= 737 * 2 && count <= 740 * 2 && count % 2 == 0), "{:?}", counts);
+    }
+
+    #[test]
+    fn audio_sink_gets_nothing_during_turbo() {
+        let mut console = create_console();
+        let counts = Rc::new(RefCell::new(Vec::new()));
+        let discards = Rc::new(RefCell::new(0));
+        console.set_audio_sink(Box::new(CountingSink { counts: counts.clone(), discards: discards.clone() }));
+        console.mmu.write_byte(0xFF12, 0xF0);
+        console.mmu.write_byte(0xFF14, 0x87); // A tone on channel 1
+
+        console.execute_frame();
+        console.set_turbo(4);
+        assert_eq!(*discards.borrow(), 1);
+        for _ in 0 .. 3 {
+            console.execute_frame();
+        }
+
+        // The APU kept running, channel 1 is still on
+        assert_eq!(counts.borrow().len(), 1);
+        assert_eq!(console.mmu.read_byte(0xFF26) & 0x01, 0x01);
+
+        console.set_turbo(4);
+        console.set_turbo(1);
+        console.execute_frame();
+        assert_eq!(*discards.borrow(), 1);
+        assert_eq!(counts.borrow().len(), 2);
+    }
+
+    #[test]
+    fn screenshot_encodes_last_frame_as_png() {
+        let mut console = create_console();
+
+        // Shade 1 of the first tile row maps to the darkest color through BGP 0xFC
+        console.mmu.write_byte(0x8000, 0x55);
+        console.mmu.write_byte(0x8001, 0x33);
+        console.execute_frame();
+        console.execute_frame();
+
+        let png = console.screenshot();
+        assert_eq!(&png[1 .. 4], b"PNG");
+
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (SCREEN_W as u32, SCREEN_H as u32));
+        assert_eq!(image.get_pixel(0, 0).0, [0x9B, 0xBC, 0x0F, 0xFF]);
+        assert_eq!(image.get_pixel(1, 0).0, [0x0F, 0x38, 0x0F, 0xFF]);
+    }
+
+    // Slow in debug builds, run with cargo test --release --features coverage
+    #[cfg(feature = "coverage")]
+    #[test]
+    fn cpu_instrs_executes_nearly_every_opcode() {
+        let mut console = Console::new();
+        console.load_from_bytes(include_bytes!("../roms/cpu_instrs.gb").to_vec()).unwrap();
+        console.reset();
+        console.set_audio_enabled(false);
+
+        // Two minutes of emulated time, the rom finishes within one
+        for _ in 0 .. 120 * 60 {
+            console.execute_frame();
+            if console.serial_output().contains("Passed") { break; }
+        }
+        assert!(console.serial_output().contains("Passed"), "{}", console.serial_output());
+
+        let untouched = console.coverage_report();
+        assert!(untouched.len() <= 10, "not executed: {:X?}", untouched);
+    }
+
+    #[test]
+    fn interrupt_trace_records_pc_and_changed_bits() {
+        let mut console = create_console();
+        console.trace_interrupts(true);
+        console.cpu.pc = 0xC000;
+        console.cpu.a = 0x05;
+        console.mmu.interrupt_enable = 0x01;
+        console.mmu.write_byte(0xC000, 0xE0); // LDH (0xFF),A
+        console.mmu.write_byte(0xC001, 0xFF);
+
+        console.execute_tick();
+
+        let trace = console.take_interrupt_trace();
+        assert_eq!(trace, vec![InterruptWrite { pc: 0xC000, address: 0xFFFF, old: 0x01, new: 0x05 }]);
+        assert_eq!(trace[0].to_string(), "PC:C000 IE 01 -> 05 (+Timer)");
+        assert_eq!(InterruptWrite { pc: 0x0150, address: 0xFF0F, old: 0x03, new: 0x10 }.to_string(),
+                   "PC:0150 IF 03 -> 10 (-V-Blank -STAT +Joypad)");
+
+        console.trace_interrupts(false);
+        console.cpu.pc = 0xC000;
+        console.execute_tick();
+        assert!(console.take_interrupt_trace().is_empty());
+    }
+
+    #[test]
+    fn state_hash_follows_the_emulation() {
+        // Keeps storing the action buttons read from the joypad at 0xC000
+        let mut rom = vec![0; 0x8000];
+        let program = [
+            0x3E, 0x10, // LD A,10
+            0xE0, 0x00, // LDH (00),A
+            0xF0, 0x00, // LDH A,(00)
+            0xEA, 0x00, 0xC0, // LD (C000),A
+            0x18, 0xF5, // JR -11
+        ];
+        rom[0x100 .. 0x100 + program.len()].copy_from_slice(&program);
+        let create_console = || {
+            let mut console = Console::new();
+            console.load_from_bytes(rom.clone()).unwrap();
+            console.reset();
+            console.set_audio_enabled(false);
+            return console;
+        };
+
+        let mut first = create_console();
+        let mut second = create_console();
+        assert_eq!(first.hash_state(), second.hash_state());
+
+        first.execute_ticks(1000);
+        second.execute_ticks(1000);
+        assert_eq!(first.hash_state(), second.hash_state());
+
+        first.press_button(Button::A);
+        second.press_button(Button::B);
+        first.execute_ticks(1000);
+        second.execute_ticks(1000);
+        assert_ne!(first.hash_state(), second.hash_state());
+    }
+
+    #[test]
+    fn reset_returns_to_the_post_boot_state() {
+        let mut console = create_console();
+        console.execute_ticks(TICKS_PER_FRAME * 3 / 2);
+        console.cpu.halted = true;
+        console.cpu.interrupt_master_enable = true;
+        console.cpu.sp = 0xC100;
+        console.mmu.write_byte(0xFF40, 0x83);
+        console.mmu.write_byte(0xFF47, 0x1B);
+        console.mmu.write_byte(0xFF05, 0x42);
+        console.mmu.write_byte(0xFFFF, 0x1F);
+
+        console.reset();
+
+        let registers = console.cpu.registers();
+        assert_eq!((registers.pc, registers.sp), (0x0100, 0xFFFE));
+        assert_eq!((registers.a, registers.f), (0x01, 0xB0));
+        assert!(!registers.halted && !registers.interrupt_master_enable);
+
+        // https://gbdev.io/pandocs/#power-up-sequence
+        let expected = [
+            (0xFF05, 0x00), (0xFF06, 0x00), (0xFF10, 0x80), (0xFF24, 0x77), (0xFF25, 0xF3),
+            (0xFF26, 0xF1), (0xFF40, 0x91), (0xFF42, 0x00), (0xFF43, 0x00), (0xFF44, 0x00),
+            (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF4A, 0x00), (0xFF4B, 0x00), (0xFFFF, 0x00),
+        ];
+        for &(address, value) in expected.iter() {
+            assert_eq!(console.mmu.read_byte(address), value, "{:04X}", address);
+        }
+    }
+
+    #[test]
+    fn double_speed_keeps_the_frame_length() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80; // CGB
+        let program = [
+            0x3E, 0x01, // LD A,1
+            0xE0, 0x4D, // LDH (4D),A
+            0x10, 0x00, // STOP
+            0x18, 0xFE, // JR -2
+        ];
+        rom[0x100 .. 0x100 + program.len()].copy_from_slice(&program);
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+
+        console.run_until_vblank();
+        assert_eq!(console.mmu.speed, Speed::FAST);
+
+        // Twice the CPU clocks go by, the PPU draws one frame
+        let executed = console.run_until_vblank();
+        assert!(executed.abs_diff(TICKS_PER_FRAME) < 24, "{}", executed);
+        console.execute_frame();
+        assert_eq!(console.mmu.read_byte(0xFF44), 144);
+    }
+
+    #[test]
+    fn ld_b_b_reports_the_mooneye_result() {
+        let run = |registers: [u8; 6]| {
+            let mut rom = vec![0; 0x8000];
+            let mut program = Vec::new();
+            for (index, &value) in registers.iter().enumerate() {
+                program.extend_from_slice(&[0x06 + index as u8 * 8, value]); // LD B,d8 ... LD L,d8
+            }
+            program.extend_from_slice(&[0x40, 0x18, 0xFE]); // LD B,B; JR -2
+            rom[0x100 .. 0x100 + program.len()].copy_from_slice(&program);
+
+            let mut console = Console::new();
+            console.load_from_bytes(rom).unwrap();
+            console.reset();
+            return console.run_until_mooneye_result(TICKS_PER_FRAME);
+        };
+
+        assert_eq!(run([3, 5, 8, 13, 21, 34]), Some(MooneyeResult::Passed));
+        assert_eq!(run([0x42; 6]), Some(MooneyeResult::Failed));
+        assert_eq!(run([1, 2, 3, 4, 5, 6]), None);
+    }
+
+    #[test]
+    fn vblank_callback_fires_once_per_frame() {
+        let mut console = create_console();
+        console.run_until_vblank();
+
+        let frames = Rc::new(RefCell::new(0));
+        let counter = frames.clone();
+        console.set_vblank_callback(Box::new(move |frame| {
+            assert_eq!(frame.len(), SCREEN_W * SCREEN_H);
+            *counter.borrow_mut() += 1;
+        }));
+
+        for _ in 0 .. 5 {
+            console.execute_frame();
+        }
+        assert_eq!(*frames.borrow(), 5);
+
+        // Kept across save states like the other front-end settings
+        let state = console.save_state();
+        console.load_state(&state).unwrap();
+        console.execute_frame();
+        assert_eq!(*frames.borrow(), 6);
+    }
 }
@@ -1,12 +1,14 @@
 use crate::mmu::Mmu;
 use crate::operations::execute_operation;
-use crate::logger::log;
+use crate::logger::warn;
 use crate::console::GameboyType;
 use wasm_bindgen::prelude::*;
+use serde_derive::{Serialize, Deserialize};
+use std::fmt;
 
 #[wasm_bindgen]
 #[allow(unused)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cpu {
     pub a: u8,
     pub b: u8,
@@ -20,13 +22,87 @@ pub struct Cpu {
     pub pc: u16,
 
     pub halted: bool,
+    pub stopped: bool, // Set by STOP, only a button press resumes execution
+    pub halt_bug: bool, // The next opcode fetch does not increment PC
+    pub locked: bool, // Set by an illegal opcode, nothing but a reset gets the CPU going again
     pub interrupt_master_enable: bool,
-    pub disable_interrupt_counter: u8, // Schedules interrupt handling to be enabled after the next machine cycle
-    pub enable_interrupt_counter: u8,  // Schedules interrupt handling to be enabled after the next machine cycle
+    pub enable_interrupts_pending: bool, // Set by EI, IME turns on once the next instruction has run
 
     pub cycles: u32,
     pub ticks: u32,
     pub opcode: u16,
+
+    #[cfg(feature = "coverage")]
+    #[serde(skip)]
+    coverage: OpcodeCoverage,
+}
+
+// Copy of the register file for front-ends that display it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub interrupt_master_enable: bool,
+    pub halted: bool,
+}
+
+// https://gbdev.io/pandocs/#the-flags-register-lower-8-bits-of-af-register
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub zero: bool,
+    pub negative: bool,
+    pub half_carry: bool,
+    pub carry: bool,
+}
+
+// Which of the 256 opcodes and 256 CB prefixed opcodes have run, for finding instructions
+// no test exercises. Only built with the coverage feature, marking costs a store per instruction.
+#[cfg(feature = "coverage")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct OpcodeCoverage {
+    executed: [bool; 512],
+}
+
+// https://gbdev.io/pandocs/#cpu-instruction-set
+// Opcodes the hardware does not have, executing one locks up the CPU
+#[cfg(any(test, feature = "coverage"))]
+pub const ILLEGAL_OPCODES: [u16; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+#[cfg(feature = "coverage")]
+impl Default for OpcodeCoverage {
+    fn default() -> Self {
+        return OpcodeCoverage { executed: [false; 512] };
+    }
+}
+
+#[cfg(feature = "coverage")]
+impl OpcodeCoverage {
+
+    // opcode as left in Cpu::opcode, CB prefixed ones carry the prefix in the high byte
+    fn mark(&mut self, opcode: u16) {
+        if opcode >> 8 == 0xCB {
+            self.executed[0xCB] = true;
+            self.executed[0x100 | (opcode & 0xFF) as usize] = true;
+        } else {
+            self.executed[(opcode & 0xFF) as usize] = true;
+        }
+    }
+
+    fn untouched(&self) -> Vec<u16> {
+        return (0 .. 512u16)
+            .filter(|&index| !self.executed[index as usize])
+            .map(|index| if index >= 0x100 { 0xCB00 | (index & 0xFF) } else { index })
+            .filter(|opcode| !ILLEGAL_OPCODES.contains(opcode))
+            .collect();
+    }
 }
 
 #[allow(unused)]
@@ -45,37 +121,48 @@ impl Cpu {
             pc: 0x0000,
             sp: 0x0000,
             halted: false,
+            stopped: false,
+            halt_bug: false,
+            locked: false,
             interrupt_master_enable: true,
-            disable_interrupt_counter: 0,
-            enable_interrupt_counter: 0,
+            enable_interrupts_pending: false,
             cycles: 0,
             opcode: 0,
             ticks: 0,
+            #[cfg(feature = "coverage")]
+            coverage: OpcodeCoverage::default(),
         }
     }
 
+    // https://gbdev.io/pandocs/#cpu-registers
+    // Register values the boot rom leaves behind when it hands over to the cartridge
     pub fn reset(&mut self, model: GameboyType) {
-        self.a = 0x11;
-        self.b = 0x00;
-        self.c = 0x13;
-        self.d = 0x00;
-        self.e = 0xD8;
-        self.f = 0xB0;
-        self.h = 0x01;
-        self.l = 0x4D;
+        match model {
+            GameboyType::CLASSIC => {
+                self.set_af(0x01B0);
+                self.set_bc(0x0013);
+                self.set_de(0x00D8);
+                self.set_hl(0x014D);
+            },
+            GameboyType::COLOR => {
+                self.set_af(0x1180);
+                self.set_bc(0x0000);
+                self.set_de(0xFF56);
+                self.set_hl(0x000D);
+            },
+        }
         self.pc = 0x0100;
         self.sp = 0xFFFE;
         self.halted = false;
-        self.interrupt_master_enable = true;
-        self.disable_interrupt_counter = 0;
-        self.enable_interrupt_counter = 0;
+        self.stopped = false;
+        self.halt_bug = false;
+        self.locked = false;
+        // The boot rom never enables interrupts
+        self.interrupt_master_enable = false;
+        self.enable_interrupts_pending = false;
         self.cycles = 0;
         self.opcode = 0;
         self.ticks = 0;
-
-        if model == GameboyType::CLASSIC {
-            self.a = 0x01;
-        }
     }
 
     pub fn execute_ticks(&mut self, mmu: &mut Mmu, ticks: u32) -> u32 {
@@ -86,11 +173,26 @@ impl Cpu {
         return total;
     }
 
+    // Runs one instruction, or services an interrupt, and clocks the rest of the system through
+    // it. Returns the M-cycles taken.
     pub fn execute_tick(&mut self, mmu: &mut Mmu) -> u32 {
+        let cycles = self.execute_instruction(mmu);
+        mmu.complete_instruction(cycles);
+        return cycles;
+    }
+
+    fn execute_instruction(&mut self, mmu: &mut Mmu) -> u32 {
         let cycles = self.cycles;
         let pc = self.pc;
 
-        self.update_interrupt_master_flag();
+        // Interrupts are not serviced either, the clocks keep running for the rest of the system
+        if self.locked { return 1; }
+
+        if self.stopped {
+            if !mmu.joypad.is_any_pressed() { return 1; }
+            self.stopped = false;
+        }
+
         if self.handle_interrupt(mmu) {
             return (self.cycles - cycles) as u32;
         }
@@ -99,30 +201,30 @@ impl Cpu {
             return 1;
         }
 
-        self.opcode = mmu.read_byte(pc) as u8 as u16;
+        // https://gbdev.io/pandocs/#ime-interrupt-master-enable-flag-write-only
+        // An EI before this instruction takes effect after it, unless it is a DI
+        let enable_interrupts = self.enable_interrupts_pending;
+
+        self.opcode = mmu.cpu_read_byte(pc) as u8 as u16;
+        if self.halt_bug {
+            // Operands are read from pc + 1, so stepping back makes the opcode byte be read again
+            self.halt_bug = false;
+            self.pc = pc.wrapping_sub(1);
+        }
         //log(self.to_string());
         execute_operation(self.opcode as u8, self, mmu);
+        #[cfg(feature = "coverage")]
+        self.coverage.mark(self.opcode);
+
+        if enable_interrupts && self.enable_interrupts_pending {
+            self.interrupt_master_enable = true;
+            self.enable_interrupts_pending = false;
+        }
 
         self.ticks += 1;
         return (self.cycles - cycles) as u32;
     }
 
-    pub fn update_interrupt_master_flag(&mut self) {
-        // There are some operations where interrupts are triggered after the next machine cycle
-        // So to mimic it we will use a counter that will tick down.
-
-        self.disable_interrupt_counter = match self.disable_interrupt_counter {
-            2 => 1,
-            1 => { self.interrupt_master_enable = false; 0},
-            _ => 0,
-        };
-        self.enable_interrupt_counter = match self.enable_interrupt_counter {
-            2 => 1,
-            1 => { self.interrupt_master_enable = true; 0},
-            _ => 0,
-        };
-    }
-
     pub fn handle_interrupt(&mut self, mmu: &mut Mmu) -> bool {
         if self.interrupt_master_enable == false &&
             self.halted == false {
@@ -136,6 +238,7 @@ impl Cpu {
         self.halted = false;
         if self.interrupt_master_enable == false { return false; };
         self.interrupt_master_enable = false;
+        self.enable_interrupts_pending = false;
 
         // http://bgb.bircd.org/pandocs.htm#interrupts
         // Bit 0: V-Blank  Interrupt Enable  (INT 40h)  (1=Enable)
@@ -150,7 +253,11 @@ impl Cpu {
         // clear flag
         mmu.interrupt_flags &= !(1 << interrupt_type);
 
-        self.push_word(mmu, self.pc);
+        // EI right before a bugged HALT returns to the HALT itself
+        let return_address = if self.halt_bug { self.pc.wrapping_sub(1) } else { self.pc };
+        self.halt_bug = false;
+        mmu.cpu_idle_cycle();
+        self.push_word(mmu, return_address);
 
         // go to the vector
         self.pc = match interrupt_type {
@@ -162,33 +269,68 @@ impl Cpu {
             _ => { panic!("error") }
         };
 
-        self.cycles += 12;
+        // Two wait states, two pushes and the jump (20 clocks)
+        self.cycles += 5;
 
         return true;
     }
 
-    pub fn to_string(&mut self) -> String {
-        return format!("PC: {:#06X} OPCODE: {:#04X} A: {:#04X} B: {:#04X} C: {:#04X} D: {:#04X} E: {:#04X} F: {:#04X} H: {:#04X} L: {:#04X} SP: {:#06X}",
-                       self.pc,
-                       self.opcode,
-                       self.a,
-                       self.b,
-                       self.c,
-                       self.d,
-                       self.e,
-                       self.f,
-                       self.h,
-                       self.l,
-                       self.sp
-        );
+    pub fn registers(&self) -> Registers {
+        return Registers {
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            interrupt_master_enable: self.interrupt_master_enable,
+            halted: self.halted,
+        };
+    }
+
+    pub fn flags(&self) -> Flags {
+        return Flags {
+            zero: self.get_f_zero(),
+            negative: self.get_f_substract(),
+            half_carry: self.get_f_half_carry(),
+            carry: self.get_f_carry(),
+        };
+    }
+
+    // The low nibble of F does not exist on hardware and always reads 0
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.f = 0;
+        self.set_f_zero(flags.zero);
+        self.set_f_negative(flags.negative);
+        self.set_f_half_carry(flags.half_carry);
+        self.set_f_carry(flags.carry);
     }
 
-    pub fn print(&mut self) {
-        println!("{}", self.to_string());
+    pub fn print(&self) {
+        println!("{}", self);
     }
 
     // register helpers
 
+    // Opcodes never executed since the CPU was created, CB prefixed ones as 0xCBXX. The
+    // opcodes the hardware does not have are left out.
+    #[cfg(feature = "coverage")]
+    pub fn coverage_report(&self) -> Vec<u16> {
+        return self.coverage.untouched();
+    }
+
+    pub fn lock_up(&mut self) {
+        if !self.locked {
+            warn(&format!("illegal opcode {:02X} at {:04X}, the CPU locked up", self.opcode, self.pc));
+        }
+        self.locked = true;
+        self.cycles += 1; // Fetching the opcode
+    }
+
     pub fn get_af(&self) -> u16 {
         return (self.a as u16) << 8
             | (self.f & 0xF0) as u16;
@@ -342,7 +484,7 @@ impl Cpu {
         let result = a.wrapping_sub(b).wrapping_sub(carry);
 
         self.set_f_zero(result == 0);
-        self.set_f_half_carry((a & 0x0F) < (b & 0x0F) + carry);
+        self.set_f_half_carry(((a & 0x0F) as i16) - ((b & 0x0F) as i16) - (carry as i16) < 0);
         self.set_f_negative(true);
         self.set_f_carry((a as i16 - b as i16 - carry as i16) < 0);
 
@@ -359,6 +501,8 @@ impl Cpu {
         return result;
     }
 
+    // ADD SP,r8 and LD HL,SP+r8. H and C come from adding the offset to the low byte of SP as
+    // unsigned bytes, whatever its sign.
     pub fn apply_add_i16_with_flags(&mut self, a:  i16, b: i16) -> u16 {
         let result: u16 = a.wrapping_add(b) as u16;
 
@@ -446,19 +590,21 @@ impl Cpu {
 
     pub fn push_byte(&mut self, mmu: &mut Mmu, value: u8) {
         self.sp = self.sp.wrapping_sub(1);
-        mmu.write_byte(self.sp, value);
+        mmu.cpu_write_byte(self.sp, value);
     }
 
+    // PUSH, CALL, RST and interrupts all decrement SP in an internal cycle before the writes
     pub fn push_word(&mut self, mmu: &mut Mmu, value: u16) {
         let low = (value & 0xFF) as u8;
         let high = (value >> 8) as u8;
 
+        mmu.cpu_idle_cycle();
         self.push_byte(mmu, high);
         self.push_byte(mmu, low);
     }
 
     pub fn pop_byte(&mut self, mmu: &mut Mmu) -> u8 {
-        let value = mmu.read_byte(self.sp);
+        let value = mmu.cpu_read_byte(self.sp);
         self.sp = self.sp.wrapping_add(1);
         return value;
     }
@@ -470,3 +616,617 @@ impl Cpu {
     }
 }
 
+impl fmt::Display for Cpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PC: {:#06X} OPCODE: {:#04X} A: {:#04X} B: {:#04X} C: {:#04X} D: {:#04X} E: {:#04X} F: {:#04X} H: {:#04X} L: {:#04X} SP: {:#06X}",
+               self.pc,
+               self.opcode,
+               self.a,
+               self.b,
+               self.c,
+               self.d,
+               self.e,
+               self.f,
+               self.h,
+               self.l,
+               self.sp
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_round_trip_through_f() {
+        let mut cpu = Cpu::new();
+        cpu.f = 0x0F;
+
+        let flags = Flags { zero: true, negative: false, half_carry: true, carry: true };
+        cpu.set_flags(flags);
+        assert_eq!(cpu.f, 0xB0);
+        assert_eq!(cpu.flags(), flags);
+
+        cpu.set_flags(Flags::default());
+        assert_eq!(cpu.f, 0x00);
+        assert_eq!(cpu.flags(), Flags { zero: false, negative: false, half_carry: false, carry: false });
+    }
+
+    #[test]
+    fn push_then_pop_word_restores_sp() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFE;
+
+        cpu.push_word(&mut mmu, 0xBEEF);
+        assert_eq!(cpu.sp, 0xFFFC);
+
+        assert_eq!(cpu.pop_word(&mut mmu), 0xBEEF);
+        assert_eq!(cpu.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn push_bc_pop_de_round_trip() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0xC000;
+        cpu.set_bc(0x1234);
+
+        mmu.write_byte(0xC000, 0xC5); // PUSH BC
+        mmu.write_byte(0xC001, 0xD1); // POP DE
+        execute_operation(mmu.read_byte(cpu.pc), &mut cpu, &mut mmu);
+        execute_operation(mmu.read_byte(cpu.pc), &mut cpu, &mut mmu);
+
+        assert_eq!(cpu.get_de(), 0x1234);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+
+    #[test]
+    fn pop_af_clears_low_nibble_of_f() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0xC000;
+        cpu.a = 0x12;
+        cpu.f = 0xFF;
+
+        mmu.write_byte(0xC000, 0xF5); // PUSH AF
+        mmu.write_byte(0xC001, 0xF1); // POP AF
+        execute_operation(mmu.read_byte(cpu.pc), &mut cpu, &mut mmu);
+        assert_eq!(mmu.read_byte(cpu.sp), 0xF0);
+
+        // Even a stray low nibble on the stack does not reach F
+        mmu.write_byte(cpu.sp, 0xFF);
+        execute_operation(mmu.read_byte(cpu.pc), &mut cpu, &mut mmu);
+
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.f & 0x0F, 0);
+        assert_eq!(cpu.f, 0xF0);
+        assert_eq!(cpu.get_af(), 0x12F0);
+    }
+
+    #[test]
+    fn rst_28_pushes_return_address() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xC000, 0xEF); // RST 0x28
+
+        let cycles = cpu.cycles;
+        execute_operation(0xEF, &mut cpu, &mut mmu);
+
+        assert_eq!(cpu.pc, 0x0028);
+        assert_eq!(cpu.cycles - cycles, 4); // Machine cycles, 16 clocks
+        assert_eq!(cpu.sp, 0xFFFC);
+        // High byte pushed first, so the word sits little endian at SP
+        assert_eq!((mmu.read_byte(0xFFFC), mmu.read_byte(0xFFFD)), (0x01, 0xC0));
+        assert_eq!(cpu.pop_word(&mut mmu), 0xC001);
+    }
+
+    #[test]
+    fn rst_jumps_to_all_eight_vectors() {
+        for vector in 0 .. 8u16 {
+            let mut cpu = Cpu::new();
+            let mut mmu = Mmu::new();
+            cpu.sp = 0xFFFE;
+            cpu.pc = 0xC100;
+
+            let opcode = 0xC7 | (vector as u8) << 3;
+            execute_operation(opcode, &mut cpu, &mut mmu);
+
+            assert_eq!(cpu.pc, vector * 8, "opcode {:02X}", opcode);
+            assert_eq!(cpu.pop_word(&mut mmu), 0xC101);
+        }
+    }
+
+    // Runs opcode at 0xC000 with the zero and carry flags set as given, returns the machine
+    // cycles spent and the next pc
+    fn execute_branch(opcode: u8, zero: bool, carry: bool) -> (u32, u16) {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFC;
+        cpu.pc = 0xC000;
+        cpu.set_f_zero(zero);
+        cpu.set_f_carry(carry);
+        mmu.write_byte(0xC000, opcode);
+        mmu.write_byte(0xC001, 0x10);
+        mmu.write_byte(0xC002, 0xD0);
+        // Return address for RET
+        mmu.write_byte(0xFFFC, 0x00);
+        mmu.write_byte(0xFFFD, 0xD1);
+
+        let cycles = cpu.cycles;
+        execute_operation(opcode, &mut cpu, &mut mmu);
+        return (cpu.cycles - cycles, cpu.pc);
+    }
+
+    #[test]
+    fn conditional_branches_cost_extra_when_taken() {
+        // (opcode, zero and carry flags that take the branch, taken target, taken cycles, fall through pc, not taken cycles)
+        let branches: [(u8, bool, bool, u16, u32, u16, u32); 16] = [
+            (0x20, false, false, 0xC012, 3, 0xC002, 2), // JR NZ
+            (0x28, true, false, 0xC012, 3, 0xC002, 2),  // JR Z
+            (0x30, false, false, 0xC012, 3, 0xC002, 2), // JR NC
+            (0x38, false, true, 0xC012, 3, 0xC002, 2),  // JR C
+            (0xC2, false, false, 0xD010, 4, 0xC003, 3), // JP NZ
+            (0xCA, true, false, 0xD010, 4, 0xC003, 3),  // JP Z
+            (0xD2, false, false, 0xD010, 4, 0xC003, 3), // JP NC
+            (0xDA, false, true, 0xD010, 4, 0xC003, 3),  // JP C
+            (0xC4, false, false, 0xD010, 6, 0xC003, 3), // CALL NZ
+            (0xCC, true, false, 0xD010, 6, 0xC003, 3),  // CALL Z
+            (0xD4, false, false, 0xD010, 6, 0xC003, 3), // CALL NC
+            (0xDC, false, true, 0xD010, 6, 0xC003, 3),  // CALL C
+            (0xC0, false, false, 0xD100, 5, 0xC001, 2), // RET NZ
+            (0xC8, true, false, 0xD100, 5, 0xC001, 2),  // RET Z
+            (0xD0, false, false, 0xD100, 5, 0xC001, 2), // RET NC
+            (0xD8, false, true, 0xD100, 5, 0xC001, 2),  // RET C
+        ];
+
+        for &(opcode, zero, carry, target, taken, next, not_taken) in branches.iter() {
+            assert_eq!(execute_branch(opcode, zero, carry), (taken, target), "{:02X} taken", opcode);
+            assert_eq!(execute_branch(opcode, !zero, !carry), (not_taken, next), "{:02X} not taken", opcode);
+        }
+    }
+
+    fn execute_cb(cpu: &mut Cpu, mmu: &mut Mmu, opcode: u8) {
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xC000, 0xCB);
+        mmu.write_byte(0xC001, opcode);
+        execute_operation(0xCB, cpu, mmu);
+    }
+
+    #[test]
+    fn swap_exchanges_nibbles_and_clears_carry() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.a = 0xF1;
+        cpu.set_f_carry(true);
+
+        execute_cb(&mut cpu, &mut mmu, 0x37); // SWAP A
+
+        assert_eq!(cpu.a, 0x1F);
+        assert!(!cpu.get_f_carry());
+        assert!(!cpu.get_f_zero());
+    }
+
+    #[test]
+    fn sra_preserves_sign_bit() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.c = 0x81;
+
+        execute_cb(&mut cpu, &mut mmu, 0x29); // SRA C
+        assert_eq!(cpu.c, 0xC0);
+        assert!(cpu.get_f_carry());
+
+        execute_cb(&mut cpu, &mut mmu, 0x39); // SRL C
+        assert_eq!(cpu.c, 0x60);
+        assert!(!cpu.get_f_carry());
+    }
+
+    #[test]
+    fn bit_7_of_hl_sets_zero_flag() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.set_hl(0xC100);
+        mmu.write_byte(0xC100, 0x7F);
+
+        let cycles = cpu.cycles;
+        execute_cb(&mut cpu, &mut mmu, 0x7E); // BIT 7, (HL)
+        assert!(cpu.get_f_zero());
+        assert_eq!(cpu.cycles - cycles, 3);
+
+        mmu.write_byte(0xC100, 0x80);
+        execute_cb(&mut cpu, &mut mmu, 0x7E);
+        assert!(!cpu.get_f_zero());
+        assert_eq!(mmu.read_byte(0xC100), 0x80);
+    }
+
+    // Result and carry of the CB operation selected by bits 3 - 7 of opcode
+    fn reference_cb(opcode: u8, value: u8, carry: bool) -> (u8, bool) {
+        let bit = (opcode >> 3) & 0x07;
+        return match opcode >> 3 {
+            0 => (value.rotate_left(1), value & 0x80 != 0),             // RLC
+            1 => (value.rotate_right(1), value & 0x01 != 0),            // RRC
+            2 => (value << 1 | carry as u8, value & 0x80 != 0),         // RL
+            3 => (value >> 1 | (carry as u8) << 7, value & 0x01 != 0),  // RR
+            4 => (value << 1, value & 0x80 != 0),                       // SLA
+            5 => (value >> 1 | (value & 0x80), value & 0x01 != 0),      // SRA
+            6 => (value.rotate_left(4), false),                         // SWAP
+            7 => (value >> 1, value & 0x01 != 0),                       // SRL
+            8 ..= 15 => (value, carry),                                 // BIT
+            16 ..= 23 => (value & !(1 << bit), carry),                  // RES
+            _ => (value | (1 << bit), carry),                           // SET
+        };
+    }
+
+    #[test]
+    fn cb_table_matches_reference() {
+        for opcode in 0 ..= 0xFFu8 {
+            for &value in [0x00u8, 0x01, 0x80, 0x8F, 0xF0, 0x55].iter() {
+                for &carry in [false, true].iter() {
+                    let mut cpu = Cpu::new();
+                    let mut mmu = Mmu::new();
+                    cpu.set_hl(0xC100);
+                    cpu.set_f_carry(carry);
+
+                    let target = opcode & 0x07;
+                    match target {
+                        0 => cpu.b = value, 1 => cpu.c = value, 2 => cpu.d = value, 3 => cpu.e = value,
+                        4 => cpu.h = value, 5 => cpu.l = value, 6 => mmu.write_byte(0xC100, value), _ => cpu.a = value,
+                    }
+
+                    let cycles = cpu.cycles;
+                    execute_cb(&mut cpu, &mut mmu, opcode);
+
+                    let result = match target {
+                        0 => cpu.b, 1 => cpu.c, 2 => cpu.d, 3 => cpu.e,
+                        4 => cpu.h, 5 => cpu.l, 6 => mmu.read_byte(0xC100), _ => cpu.a,
+                    };
+                    let (expected, expected_carry) = reference_cb(opcode, value, carry);
+                    let context = format!("CB {:02X} on {:02X}, carry {}", opcode, value, carry);
+
+                    assert_eq!(result, expected, "{}", context);
+                    assert_eq!(cpu.get_f_carry(), expected_carry, "{}", context);
+                    if opcode < 0x80 {
+                        let zero = if opcode < 0x40 { expected == 0 } else { value & (1 << ((opcode >> 3) & 0x07)) == 0 };
+                        assert_eq!(cpu.get_f_zero(), zero, "{}", context);
+                    }
+                    assert_eq!(cpu.pc, 0xC002, "{}", context);
+
+                    let expected_cycles = match (target, opcode) {
+                        (6, 0x40 ..= 0x7F) => 3,
+                        (6, _) => 4,
+                        _ => 2,
+                    };
+                    assert_eq!(cpu.cycles - cycles, expected_cycles, "{}", context);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rlc_b_rotates_b_not_a() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.a = 0x01;
+        cpu.b = 0x85;
+
+        execute_cb(&mut cpu, &mut mmu, 0x00); // RLC B
+
+        assert_eq!(cpu.b, 0x0B);
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.get_f_carry());
+        assert!(!cpu.get_f_zero());
+    }
+
+    #[test]
+    fn rl_b_without_incoming_carry() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.b = 0x80;
+        cpu.set_f_carry(false);
+
+        execute_cb(&mut cpu, &mut mmu, 0x10); // RL B
+
+        assert_eq!(cpu.b, 0x00);
+        assert!(cpu.get_f_carry());
+        assert!(cpu.get_f_zero());
+    }
+
+    #[test]
+    fn rl_b_with_incoming_carry() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.b = 0x11;
+        cpu.set_f_carry(true);
+
+        execute_cb(&mut cpu, &mut mmu, 0x10); // RL B
+
+        assert_eq!(cpu.b, 0x23);
+        assert!(!cpu.get_f_carry());
+        assert!(!cpu.get_f_zero());
+    }
+
+    #[test]
+    fn interrupt_dispatch_jumps_to_highest_priority_vector() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0xC123;
+        mmu.interrupt_enable = 0x1F;
+        mmu.interrupt_flags = 0x05; // V-Blank and Timer
+
+        let cycles = cpu.execute_tick(&mut mmu);
+
+        assert_eq!(cycles, 5);
+        assert_eq!(cpu.pc, 0x40);
+        assert_eq!(mmu.interrupt_flags, 0x04);
+        assert_eq!(cpu.pop_word(&mut mmu), 0xC123);
+        assert!(!cpu.interrupt_master_enable);
+    }
+
+    #[test]
+    fn halt_wakes_on_pending_interrupt_without_ime() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.halted = true;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x04;
+        mmu.interrupt_flags = 0x04;
+
+        assert!(!cpu.handle_interrupt(&mut mmu));
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0xC000);
+        assert_eq!(mmu.interrupt_flags, 0x04);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x04;
+        mmu.interrupt_flags = 0x04;
+        mmu.write_byte(0xC000, 0x76); // HALT
+        mmu.write_byte(0xC001, 0x3C); // INC A
+        mmu.write_byte(0xC002, 0x00); // NOP
+
+        cpu.execute_tick(&mut mmu);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0xC001);
+
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0xC001);
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.a, 2);
+    }
+
+    #[test]
+    fn halt_bug_repeats_opcode_byte_as_operand() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x01;
+        mmu.interrupt_flags = 0x01;
+        mmu.write_byte(0xC000, 0x76); // HALT
+        mmu.write_byte(0xC001, 0x3E); // LD A, 0x14
+        mmu.write_byte(0xC002, 0x14);
+
+        cpu.execute_tick(&mut mmu);
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.a, 0x3E);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+
+    #[test]
+    fn halt_bug_after_ei_returns_to_halt() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = 0xFFFE;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x04;
+        mmu.interrupt_flags = 0x04;
+        mmu.write_byte(0xC000, 0xFB); // EI
+        mmu.write_byte(0xC001, 0x76); // HALT
+
+        cpu.execute_tick(&mut mmu);
+        cpu.execute_tick(&mut mmu);
+        assert!(cpu.halt_bug);
+
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(mmu.read_word(cpu.sp), 0xC001);
+        assert!(!cpu.halt_bug);
+    }
+
+    #[test]
+    fn ei_enables_interrupts_after_next_instruction() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = 0xFFFE;
+        cpu.a = 0;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x01;
+        mmu.interrupt_flags = 0x01;
+        mmu.write_byte(0xC000, 0xFB); // EI
+        mmu.write_byte(0xC001, 0x3C); // INC A
+        mmu.write_byte(0xC002, 0x00); // NOP
+
+        cpu.execute_tick(&mut mmu);
+        assert!(!cpu.interrupt_master_enable);
+        assert_eq!(cpu.pc, 0xC001);
+
+        // The instruction after EI still runs before the interrupt is taken
+        cpu.execute_tick(&mut mmu);
+        assert!(cpu.interrupt_master_enable);
+        assert_eq!(cpu.pc, 0xC002);
+        assert_eq!(cpu.a, 0x01);
+
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0x40);
+        assert_eq!(mmu.read_word(cpu.sp), 0xC002);
+        assert!(!cpu.interrupt_master_enable);
+    }
+
+    #[test]
+    fn di_right_after_ei_keeps_interrupts_disabled() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.interrupt_master_enable = false;
+        mmu.interrupt_enable = 0x01;
+        mmu.interrupt_flags = 0x01;
+        mmu.write_byte(0xC000, 0xFB); // EI
+        mmu.write_byte(0xC001, 0xF3); // DI
+        mmu.write_byte(0xC002, 0x00); // NOP
+
+        for _ in 0 .. 3 {
+            cpu.execute_tick(&mut mmu);
+        }
+        assert!(!cpu.interrupt_master_enable);
+        assert_eq!(cpu.pc, 0xC003);
+    }
+
+    #[test]
+    fn di_takes_effect_immediately() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.interrupt_master_enable = true;
+        mmu.write_byte(0xC000, 0xF3); // DI
+        mmu.write_byte(0xC001, 0x00); // NOP
+
+        cpu.execute_tick(&mut mmu);
+        assert!(!cpu.interrupt_master_enable);
+
+        mmu.interrupt_enable = 0x01;
+        mmu.interrupt_flags = 0x01;
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0xC002);
+    }
+
+    #[test]
+    fn reti_returns_and_enables_interrupts_immediately() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = 0xFFFE;
+        cpu.interrupt_master_enable = false;
+        cpu.sp = 0xFFFC;
+        mmu.write_word(0xFFFC, 0xC123);
+        mmu.write_byte(0xC000, 0xD9); // RETI
+
+        assert_eq!(cpu.execute_tick(&mut mmu), 4);
+        assert_eq!(cpu.pc, 0xC123);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert!(cpu.interrupt_master_enable);
+        assert!(!cpu.enable_interrupts_pending);
+
+        // No EI delay, a pending interrupt is taken before the next instruction
+        mmu.interrupt_enable = 0x04;
+        mmu.interrupt_flags = 0x04;
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(mmu.read_word(cpu.sp), 0xC123);
+    }
+
+    // Reads TIMA with TAC 0x05, which increments it every 16 clocks starting from a reset DIV
+    fn read_tima_after_nop(read: &[u8]) -> u8 {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xFF04, 0x00);
+        mmu.write_byte(0xFF05, 0x00);
+        mmu.write_byte(0xFF07, 0x05);
+        mmu.write_byte(0xC000, 0x00); // NOP
+        for (offset, &byte) in read.iter().enumerate() {
+            mmu.write_byte(0xC001 + offset as u16, byte);
+        }
+
+        cpu.execute_tick(&mut mmu);
+        cpu.execute_tick(&mut mmu);
+        return cpu.a;
+    }
+
+    #[test]
+    fn timer_read_lands_on_its_m_cycle() {
+        // LD A, (0xFF05) reads in its 4th M-cycle, 16 clocks after the NOP started
+        assert_eq!(read_tima_after_nop(&[0xFA, 0x05, 0xFF]), 1);
+        // LDH A, (0x05) reads in its 3rd, one M-cycle before the increment
+        assert_eq!(read_tima_after_nop(&[0xF0, 0x05]), 0);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up_cpu() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.interrupt_master_enable = true;
+        mmu.write_byte(0xC000, 0xD3);
+
+        cpu.execute_tick(&mut mmu);
+        assert!(cpu.locked);
+        assert_eq!(cpu.pc, 0xC000);
+
+        // Stays put, even with an interrupt to service
+        mmu.interrupt_enable = 0x01;
+        mmu.interrupt_flags = 0x01;
+        for _ in 0 .. 10 {
+            assert_eq!(cpu.execute_tick(&mut mmu), 1);
+        }
+        assert_eq!(cpu.pc, 0xC000);
+        assert_eq!(mmu.interrupt_flags, 0x01);
+
+        cpu.reset(GameboyType::CLASSIC);
+        assert!(!cpu.locked);
+    }
+
+    #[test]
+    fn every_illegal_opcode_locks_up_cpu() {
+        for &opcode in ILLEGAL_OPCODES.iter() {
+            let mut cpu = Cpu::new();
+            let mut mmu = Mmu::new();
+            cpu.pc = 0xC000;
+            mmu.write_byte(0xC000, opcode as u8);
+
+            cpu.execute_tick(&mut mmu);
+            assert!(cpu.locked, "{:02X}", opcode);
+        }
+    }
+
+    #[cfg(feature = "coverage")]
+    #[test]
+    fn coverage_report_lists_opcodes_not_executed() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        assert_eq!(cpu.coverage_report().len(), 512 - ILLEGAL_OPCODES.len());
+
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xC000, 0x00); // NOP
+        mmu.write_byte(0xC001, 0xCB); // SWAP A
+        mmu.write_byte(0xC002, 0x37);
+        cpu.execute_tick(&mut mmu);
+        cpu.execute_tick(&mut mmu);
+
+        let untouched = cpu.coverage_report();
+        assert_eq!(untouched.len(), 512 - ILLEGAL_OPCODES.len() - 3);
+        assert!(!untouched.contains(&0x00));
+        assert!(!untouched.contains(&0xCB));
+        assert!(!untouched.contains(&0xCB37));
+        assert!(untouched.contains(&0x01));
+        assert!(untouched.contains(&0xCB36));
+        assert!(!untouched.contains(&0xD3));
+    }
+}
@@ -2,8 +2,13 @@ use crate::mmu::Mmu;
 use wasm_bindgen::prelude::*;
 use crate::console::GameboyType;
 use crate::ppu::Ppu;
+use serde_derive::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+const OAM_DMA_LENGTH: u16 = 0xA0;
+// Every 0x10 byte block stalls the CPU for 8 µs, 32 dots in either speed mode
+const HDMA_BLOCK_DOTS: u32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum DMAType {
     NONE,
     GDMA,
@@ -11,13 +16,20 @@ enum DMAType {
 }
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Dma {
     dma: [u8; 4],
     dma_status: DMAType,
     dma_source: u16,
     dma_destination: u16,
     dma_length: u8,
+    // Set once the H-Blank DMA copied its block for the current H-Blank
+    h_blank_done: bool,
+
+    // OAM DMA (0xFF46)
+    oam_active: bool,
+    oam_source: u16,
+    oam_index: u16,
 }
 
 impl Dma {
@@ -29,6 +41,10 @@ impl Dma {
             dma_source: 0,
             dma_destination: 0,
             dma_length: 0,
+            h_blank_done: false,
+            oam_active: false,
+            oam_source: 0,
+            oam_index: 0,
         }
     }
 
@@ -37,11 +53,20 @@ impl Dma {
         self.dma_destination = 0;
         self.dma_length = 0;
         self.dma_status = DMAType::NONE;
+        self.h_blank_done = false;
+        self.oam_active = false;
+        self.oam_source = 0;
+        self.oam_index = 0;
+    }
+
+    pub fn is_oam_transfer_active(&self) -> bool {
+        return self.oam_active;
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
         match address {
             0xFF51 ..= 0xFF54 => { self.dma[(address - 0xFF51) as usize] },
+            // Remaining blocks minus one, bit 7 is set once the transfer finished or was cancelled
             0xFF55 => self.dma_length | if self.dma_status == DMAType::NONE { 0x80 } else { 0 },
             _ => panic!("{:04X}", address),
         }
@@ -49,9 +74,9 @@ impl Dma {
 
     pub fn write_byte(&mut self, address: u16, value: u8) {
         match address {
-            0xFF51 => self.dma[0] = value & 0x0F,
+            0xFF51 => self.dma[0] = value,
             0xFF52 => self.dma[1] = value & 0xF0,
-            0xFF53 => self.dma[2] = value & 0x0F,
+            0xFF53 => self.dma[2] = value & 0x1F,
             0xFF54 => self.dma[3] = value & 0xF0,
             0xFF55 => {
                 if self.dma_status == DMAType::HDMA {
@@ -60,11 +85,10 @@ impl Dma {
                 }
                 let src = ((self.dma[0] as u16) << 8) | (self.dma[1] as u16);
                 let dst = ((self.dma[2] as u16) << 8) | (self.dma[3] as u16) | 0x8000;
-                if !(src <= 0x7FF0 || (src >= 0xA000 && src <= 0xDFF0)) { panic!("HDMA transfer with illegal start address {:04X}", src); }
-
                 self.dma_source = src;
                 self.dma_destination = dst;
                 self.dma_length = value & 0x7F;
+                self.h_blank_done = false;
 
                 self.dma_status =
                     if value & 0x80 == 0x80 { DMAType::HDMA }
@@ -85,18 +109,24 @@ pub fn execute_dma_tick(mmu: &mut Mmu) -> u32 {
 }
 
 
-// H-Blank DMA
+// H-Blank DMA, one block at the start of every H-Blank
 fn execute_hdma(mmu: &mut Mmu) -> u32 {
     if mmu.ppu.h_blank == false {
+        mmu.dma.h_blank_done = false;
+        return 0;
+    }
+    if mmu.dma.h_blank_done {
         return 0;
     }
+    mmu.dma.h_blank_done = true;
+
     execute_transfer(mmu);
     if mmu.dma.dma_length == 0x7F { mmu.dma.dma_status = DMAType::NONE; }
 
-    return 8;
+    return HDMA_BLOCK_DOTS;
 }
 
-// General Purpose DMA
+// General Purpose DMA, copies everything at once while the CPU is stalled
 fn execute_gdma(mmu: &mut Mmu) -> u32 {
     let len = mmu.dma.dma_length as u32 + 1;
     for _i in 0 .. len {
@@ -104,26 +134,52 @@ fn execute_gdma(mmu: &mut Mmu) -> u32 {
     }
 
     mmu.dma.dma_status = DMAType::NONE;
-    return len * 8;
+    return len * HDMA_BLOCK_DOTS;
 }
 
 // OAM DMA
+// https://gbdev.io/pandocs/#oam-dma-transfer
 pub fn execute_odma(mmu: &mut Mmu, value: u8) {
+    // Sources from 0xE000 up read the work ram behind echo ram
     let base = (value as u16) << 8;
-    for i in 0 .. 0xA0 {
-        let data = mmu.read_byte(base + i);
-        mmu.write_byte(0xFE00 + i, data);
+    mmu.dma.oam_source = if base >= 0xE000 { base - 0x2000 } else { base };
+    mmu.dma.oam_index = 0;
+    mmu.dma.oam_active = true;
+}
+
+// Copies one byte per machine cycle, the whole transfer takes 160 machine cycles
+pub fn execute_odma_ticks(mmu: &mut Mmu, cycles: u32) {
+    for _i in 0 .. cycles {
+        if !mmu.dma.oam_active { return; }
+
+        let index = mmu.dma.oam_index;
+        let data = mmu.read_bus(mmu.dma.oam_source + index);
+        mmu.ppu.write_byte(0xFE00 + index, data);
+
+        mmu.dma.oam_index += 1;
+        if mmu.dma.oam_index == OAM_DMA_LENGTH { mmu.dma.oam_active = false; }
     }
 }
 
+// https://gbdev.io/pandocs/CGB_Registers.html#ff51ff52--hdma1-hdma2-cgb-mode-only-vram-dma-source-high-low-w
+// Only ROM, cartridge RAM and WRAM can be copied from, the other sources read as open bus
+fn read_source(mmu: &mut Mmu, address: u16) -> u8 {
+    return match address {
+        0x0000 ..= 0x7FFF | 0xA000 ..= 0xDFFF => mmu.read_bus(address),
+        _ => 0xFF,
+    };
+}
+
 pub fn execute_transfer(mmu: &mut Mmu) {
     let mmu_src = mmu.dma.dma_source;
+    // The destination wraps around within VRAM
+    let mmu_dst = 0x8000 | (mmu.dma.dma_destination & 0x1FF0);
     for j in 0 .. 0x10 {
-        let b: u8 = mmu.read_byte(mmu_src + j);
-        mmu.ppu.write_byte(mmu.dma.dma_destination + j, b);
+        let b: u8 = read_source(mmu, mmu_src.wrapping_add(j));
+        mmu.ppu.write_byte(mmu_dst + j, b);
     }
-    mmu.dma.dma_source += 0x10;
-    mmu.dma.dma_destination += 0x10;
+    mmu.dma.dma_source = mmu_src.wrapping_add(0x10);
+    mmu.dma.dma_destination = 0x8000 | (mmu_dst.wrapping_add(0x10) & 0x1FF0);
 
     if mmu.dma.dma_length == 0 {
         mmu.dma.dma_length = 0x7F;
@@ -133,4 +189,106 @@ pub fn execute_transfer(mmu: &mut Mmu) {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_mmu(blocks: u16) -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.model = GameboyType::COLOR;
+        mmu.ppu.reset(GameboyType::COLOR);
+        for i in 0 .. blocks * 0x10 {
+            mmu.write_byte(0xC000 + i, (i as u8).wrapping_add(1));
+        }
+        mmu.write_byte(0xFF51, 0xC0);
+        mmu.write_byte(0xFF52, 0x00);
+        mmu.write_byte(0xFF53, 0x00);
+        mmu.write_byte(0xFF54, 0x00);
+        return mmu;
+    }
+
+    fn run_lines(mmu: &mut Mmu, lines: u32) {
+        for _i in 0 .. lines * 456 / 4 {
+            mmu.execute_ticks(4);
+        }
+    }
+
+    fn copied_blocks(mmu: &Mmu) -> u16 {
+        return (0 .. 4).take_while(|block| mmu.ppu.read_byte(0x8000 + block * 0x10) != 0).count() as u16;
+    }
+
+    #[test]
+    fn general_purpose_transfer_copies_everything() {
+        let mut mmu = create_mmu(4);
+
+        mmu.write_byte(0xFF55, 0x03);
+        mmu.execute_ticks(4);
 
+        for i in 0 .. 0x40 {
+            assert_eq!(mmu.ppu.read_byte(0x8000 + i), (i as u8).wrapping_add(1));
+        }
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn h_blank_transfer_copies_a_block_per_line_until_cancelled() {
+        let mut mmu = create_mmu(4);
+
+        mmu.write_byte(0xFF55, 0x83);
+        assert_eq!(mmu.read_byte(0xFF55), 0x03);
+
+        run_lines(&mut mmu, 1);
+        assert_eq!(copied_blocks(&mmu), 1);
+        assert_eq!(mmu.read_byte(0xFF55), 0x02);
+
+        run_lines(&mut mmu, 1);
+        assert_eq!(copied_blocks(&mmu), 2);
+        assert_eq!(mmu.read_byte(0xFF55), 0x01);
+
+        mmu.write_byte(0xFF55, 0x00);
+        assert_eq!(mmu.read_byte(0xFF55), 0x81);
+
+        run_lines(&mut mmu, 3);
+        assert_eq!(copied_blocks(&mmu), 2);
+    }
+
+    #[test]
+    fn h_blank_transfer_completes() {
+        let mut mmu = create_mmu(2);
+
+        mmu.write_byte(0xFF55, 0x81);
+        run_lines(&mut mmu, 3);
+
+        assert_eq!(copied_blocks(&mmu), 2);
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn transfer_wraps_around_the_end_of_vram() {
+        let mut mmu = create_mmu(2);
+        mmu.write_byte(0xFF53, 0x1F);
+        mmu.write_byte(0xFF54, 0xF0);
+
+        mmu.write_byte(0xFF55, 0x01);
+        mmu.execute_ticks(4);
+
+        for i in 0 .. 0x10 {
+            assert_eq!(mmu.ppu.read_byte(0x9FF0 + i), (i as u8).wrapping_add(1));
+            assert_eq!(mmu.ppu.read_byte(0x8000 + i), (i as u8).wrapping_add(0x11));
+        }
+        assert_eq!(mmu.read_byte(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn transfer_from_an_illegal_source_copies_open_bus() {
+        let mut mmu = create_mmu(0);
+        mmu.write_byte(0xFF51, 0xE0);
+
+        mmu.write_byte(0xFF55, 0x00);
+        mmu.execute_ticks(4);
+
+        for i in 0 .. 0x10 {
+            assert_eq!(mmu.ppu.read_byte(0x8000 + i), 0xFF);
+        }
+    }
+}
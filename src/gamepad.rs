@@ -0,0 +1,240 @@
+use crate::joypad::Button;
+
+// Controller inputs named by position, as gilrs does, so the mapping does not depend on it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadButton {
+    South, East, North, West, Select, Start, DPadUp, DPadDown, DPadLeft, DPadRight
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadEvent {
+    Pressed(PadButton),
+    Released(PadButton),
+    StickX(f32), // -1.0 is left
+    StickY(f32), // -1.0 is down
+    Disconnected,
+}
+
+// Default layout, matching the position of the buttons on a Game Boy:
+//
+//   East            A
+//   South           B
+//   Select / Start  SELECT / START
+//   D-pad           D-pad
+//   Left stick      D-pad, once pushed past half way
+pub struct GamepadMapping {
+    pub buttons: Vec<(PadButton, Button)>,
+    pub stick_threshold: f32,
+}
+
+impl GamepadMapping {
+
+    pub fn new() -> Self {
+        return GamepadMapping {
+            buttons: vec![
+                (PadButton::East, Button::A),
+                (PadButton::South, Button::B),
+                (PadButton::Select, Button::SELECT),
+                (PadButton::Start, Button::START),
+                (PadButton::DPadUp, Button::UP),
+                (PadButton::DPadDown, Button::DOWN),
+                (PadButton::DPadLeft, Button::LEFT),
+                (PadButton::DPadRight, Button::RIGHT),
+            ],
+            stick_threshold: 0.5,
+        }
+    }
+}
+
+// The D-pad can be held through buttons and the stick at once, a joypad button is
+// only released once neither of them holds it
+pub struct GamepadState {
+    buttons: [bool; 8],
+    stick: [bool; 8],
+}
+
+impl GamepadState {
+
+    pub fn new() -> Self {
+        return GamepadState {
+            buttons: [false; 8],
+            stick: [false; 8],
+        }
+    }
+
+    // Applies an event and returns the joypad buttons that changed, true for pressed
+    pub fn apply(&mut self, mapping: &GamepadMapping, event: PadEvent) -> Vec<(Button, bool)> {
+        let before = self.held();
+
+        match event {
+            PadEvent::Pressed(pad_button) | PadEvent::Released(pad_button) => {
+                let pressed = matches!(event, PadEvent::Pressed(_));
+                for &(mapped, button) in mapping.buttons.iter() {
+                    if mapped == pad_button { self.buttons[button as usize] = pressed; }
+                }
+            },
+            PadEvent::StickX(value) => {
+                self.stick[Button::LEFT as usize] = value <= -mapping.stick_threshold;
+                self.stick[Button::RIGHT as usize] = value >= mapping.stick_threshold;
+            },
+            PadEvent::StickY(value) => {
+                self.stick[Button::DOWN as usize] = value <= -mapping.stick_threshold;
+                self.stick[Button::UP as usize] = value >= mapping.stick_threshold;
+            },
+            PadEvent::Disconnected => {
+                self.buttons = [false; 8];
+                self.stick = [false; 8];
+            },
+        }
+
+        let after = self.held();
+        return BUTTONS.iter()
+            .filter(|&&button| before[button as usize] != after[button as usize])
+            .map(|&button| (button, after[button as usize]))
+            .collect();
+    }
+
+    fn held(&self) -> [bool; 8] {
+        let mut held = [false; 8];
+        for index in 0 .. 8 {
+            held[index] = self.buttons[index] || self.stick[index];
+        }
+        return held;
+    }
+}
+
+const BUTTONS: [Button; 8] = [
+    Button::RIGHT, Button::LEFT, Button::UP, Button::DOWN,
+    Button::A, Button::B, Button::SELECT, Button::START,
+];
+
+#[cfg(feature = "gamepad")]
+pub use self::input::GamepadInput;
+
+#[cfg(feature = "gamepad")]
+mod input {
+    use super::{GamepadMapping, GamepadState, PadButton, PadEvent};
+    use crate::console::Console;
+    use gilrs::{Axis, EventType, Gilrs};
+
+    // Polls every connected controller. Controllers can come and go at any time, gilrs
+    // reports them as Connected and Disconnected events.
+    pub struct GamepadInput {
+        gilrs: Gilrs,
+        mapping: GamepadMapping,
+        state: GamepadState,
+    }
+
+    impl GamepadInput {
+
+        pub fn new(mapping: GamepadMapping) -> Result<Self, String> {
+            return Ok(GamepadInput {
+                gilrs: Gilrs::new().map_err(|error| error.to_string())?,
+                mapping,
+                state: GamepadState::new(),
+            });
+        }
+
+        // Forwards the events since the last call, meant to be called once per frame
+        pub fn poll(&mut self, console: &mut Console) {
+            while let Some(event) = self.gilrs.next_event() {
+                let event = match translate(event.event) {
+                    Some(event) => event,
+                    None => continue,
+                };
+
+                for (button, pressed) in self.state.apply(&self.mapping, event) {
+                    if pressed {
+                        console.press_button(button);
+                    } else {
+                        console.release_button(button);
+                    }
+                }
+            }
+        }
+    }
+
+    fn translate(event: EventType) -> Option<PadEvent> {
+        return match event {
+            EventType::ButtonPressed(button, _) => translate_button(button).map(PadEvent::Pressed),
+            EventType::ButtonReleased(button, _) => translate_button(button).map(PadEvent::Released),
+            EventType::AxisChanged(Axis::LeftStickX, value, _) => Some(PadEvent::StickX(value)),
+            EventType::AxisChanged(Axis::LeftStickY, value, _) => Some(PadEvent::StickY(value)),
+            EventType::Disconnected => Some(PadEvent::Disconnected),
+            _ => None,
+        };
+    }
+
+    fn translate_button(button: gilrs::Button) -> Option<PadButton> {
+        return match button {
+            gilrs::Button::South => Some(PadButton::South),
+            gilrs::Button::East => Some(PadButton::East),
+            gilrs::Button::North => Some(PadButton::North),
+            gilrs::Button::West => Some(PadButton::West),
+            gilrs::Button::Select => Some(PadButton::Select),
+            gilrs::Button::Start => Some(PadButton::Start),
+            gilrs::Button::DPadUp => Some(PadButton::DPadUp),
+            gilrs::Button::DPadDown => Some(PadButton::DPadDown),
+            gilrs::Button::DPadLeft => Some(PadButton::DPadLeft),
+            gilrs::Button::DPadRight => Some(PadButton::DPadRight),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_mapping_follows_game_boy_layout() {
+        let mapping = GamepadMapping::new();
+        let mut state = GamepadState::new();
+
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::East)), vec![(Button::A, true)]);
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::South)), vec![(Button::B, true)]);
+        assert_eq!(state.apply(&mapping, PadEvent::Released(PadButton::East)), vec![(Button::A, false)]);
+
+        // Unmapped buttons do nothing
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::North)), vec![]);
+    }
+
+    #[test]
+    fn custom_mapping_replaces_default() {
+        let mapping = GamepadMapping {
+            buttons: vec![(PadButton::South, Button::A), (PadButton::West, Button::B)],
+            stick_threshold: 0.5,
+        };
+        let mut state = GamepadState::new();
+
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::South)), vec![(Button::A, true)]);
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::East)), vec![]);
+    }
+
+    #[test]
+    fn stick_and_dpad_share_directions() {
+        let mapping = GamepadMapping::new();
+        let mut state = GamepadState::new();
+
+        assert_eq!(state.apply(&mapping, PadEvent::StickX(-0.3)), vec![]);
+        assert_eq!(state.apply(&mapping, PadEvent::StickX(-0.8)), vec![(Button::LEFT, true)]);
+        assert_eq!(state.apply(&mapping, PadEvent::Pressed(PadButton::DPadLeft)), vec![]);
+
+        // Still held through the D-pad
+        assert_eq!(state.apply(&mapping, PadEvent::StickX(0.0)), vec![]);
+        assert_eq!(state.apply(&mapping, PadEvent::Released(PadButton::DPadLeft)), vec![(Button::LEFT, false)]);
+
+        assert_eq!(state.apply(&mapping, PadEvent::StickY(0.9)), vec![(Button::UP, true)]);
+        assert_eq!(state.apply(&mapping, PadEvent::StickY(-0.9)), vec![(Button::UP, false), (Button::DOWN, true)]);
+    }
+
+    #[test]
+    fn disconnect_releases_everything() {
+        let mapping = GamepadMapping::new();
+        let mut state = GamepadState::new();
+        state.apply(&mapping, PadEvent::Pressed(PadButton::Start));
+        state.apply(&mapping, PadEvent::StickX(1.0));
+
+        assert_eq!(state.apply(&mapping, PadEvent::Disconnected), vec![(Button::RIGHT, false), (Button::START, false)]);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::console::Console;
+use crate::run_frames;
+use std::io;
+
+// About 4 minutes of emulated time, the same as the testrunner
+pub const DEFAULT_HEADLESS_FRAMES: u32 = 60 * 60 * 4;
+
+pub struct HeadlessOptions {
+    pub frames: u32,
+    pub until_result: bool, // Stop once the serial output says "Passed" or "Failed"
+    pub screenshot_path: Option<String>, // Where the last frame goes as a PNG
+}
+
+impl HeadlessOptions {
+
+    pub fn new() -> Self {
+        return HeadlessOptions {
+            frames: DEFAULT_HEADLESS_FRAMES,
+            until_result: false,
+            screenshot_path: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    FrameLimit,
+    Passed,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeadlessResult {
+    pub frames: u32,
+    pub outcome: Outcome,
+}
+
+// Runs the frames of the window without showing them, see --headless in main.rs
+pub fn run_headless(console: &mut Console, options: &HeadlessOptions) -> Result<HeadlessResult, io::Error> {
+    let mut outcome = Outcome::FrameLimit;
+    let frames = run_frames(console, |console, frames| {
+        if options.until_result {
+            let output = console.serial_output();
+            if output.contains("Passed") { outcome = Outcome::Passed; }
+            if output.contains("Failed") { outcome = Outcome::Failed; }
+            if outcome != Outcome::FrameLimit { return false; }
+        }
+        return frames < options.frames;
+    });
+
+    if let Some(path) = &options.screenshot_path {
+        std::fs::write(path, console.screenshot())?;
+    }
+    return Ok(HeadlessResult { frames, outcome });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends text over the serial port and loops forever
+    fn create_console_printing(text: &str) -> Console {
+        let mut rom = vec![0; 0x8000];
+        let mut code = Vec::new();
+        for byte in text.bytes() {
+            code.extend_from_slice(&[0x3E, byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]); // LD A,byte; LDH (01),A; LD A,81; LDH (02),A
+        }
+        code.extend_from_slice(&[0x18, 0xFE]); // JR -2
+        rom[0x0100 .. 0x0100 + code.len()].copy_from_slice(&code);
+
+        let mut console = Console::new();
+        console.load_from_bytes(rom).unwrap();
+        console.reset();
+        console.set_audio_enabled(false);
+        return console;
+    }
+
+    #[test]
+    fn stops_after_the_frame_limit() {
+        let mut console = create_console_printing("");
+        let options = HeadlessOptions { frames: 30, until_result: true, screenshot_path: None };
+
+        let result = run_headless(&mut console, &options).unwrap();
+
+        assert_eq!(result, HeadlessResult { frames: 30, outcome: Outcome::FrameLimit });
+    }
+
+    #[test]
+    fn stops_on_the_serial_result() {
+        let options = HeadlessOptions { frames: 30, until_result: true, screenshot_path: None };
+
+        let result = run_headless(&mut create_console_printing("Passed"), &options).unwrap();
+        assert_eq!(result, HeadlessResult { frames: 1, outcome: Outcome::Passed });
+
+        let result = run_headless(&mut create_console_printing("Failed"), &options).unwrap();
+        assert_eq!(result, HeadlessResult { frames: 1, outcome: Outcome::Failed });
+
+        // Without until_result the output is ignored
+        let options = HeadlessOptions { until_result: false, ..options };
+        let result = run_headless(&mut create_console_printing("Passed"), &options).unwrap();
+        assert_eq!(result, HeadlessResult { frames: 30, outcome: Outcome::FrameLimit });
+    }
+
+    #[test]
+    fn writes_the_last_frame() {
+        let path = std::env::temp_dir().join(format!("rustyboy-headless-{}.png", std::process::id()));
+        let options = HeadlessOptions { frames: 2, until_result: false, screenshot_path: Some(path.to_string_lossy().into_owned()) };
+
+        run_headless(&mut create_console_printing(""), &options).unwrap();
+
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&png[1 .. 4], b"PNG");
+    }
+}
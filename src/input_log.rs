@@ -0,0 +1,88 @@
+use serde_derive::{Serialize, Deserialize};
+
+// The buttons held from frame on, one bit per joypad::Button, set while pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub buttons: u8,
+}
+
+// Joypad state per frame, counted from the start of the recording. Only changes are kept,
+// so a log stays small no matter how long the run is.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputLog {
+    events: Vec<InputEvent>,
+}
+
+impl InputLog {
+
+    pub fn new() -> Self {
+        return InputLog { events: Vec::new() }
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        return &self.events;
+    }
+
+    // Frames must be recorded in order, the first one is always kept
+    pub fn record(&mut self, frame: u64, buttons: u8) {
+        if self.events.last().is_none_or(|event| event.buttons != buttons) {
+            self.events.push(InputEvent { frame, buttons });
+        }
+    }
+}
+
+// Walks a log frame by frame
+pub struct InputPlayback {
+    log: InputLog,
+    next: usize,
+}
+
+impl InputPlayback {
+
+    pub fn new(log: InputLog) -> Self {
+        return InputPlayback { log, next: 0 }
+    }
+
+    // The buttons to hold from frame on, None while they stay the same
+    pub fn buttons_at(&mut self, frame: u64) -> Option<u8> {
+        let mut buttons = None;
+        while let Some(event) = self.log.events.get(self.next) {
+            if event.frame > frame { break; }
+            buttons = Some(event.buttons);
+            self.next += 1;
+        }
+        return buttons;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.next >= self.log.events.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_keeps_changes_only() {
+        let mut log = InputLog::new();
+        for (frame, buttons) in [(0, 0x00), (1, 0x00), (2, 0x10), (3, 0x10), (4, 0x00)] {
+            log.record(frame, buttons);
+        }
+
+        assert_eq!(log.events(), &[
+            InputEvent { frame: 0, buttons: 0x00 },
+            InputEvent { frame: 2, buttons: 0x10 },
+            InputEvent { frame: 4, buttons: 0x00 },
+        ]);
+
+        let mut playback = InputPlayback::new(log);
+        assert_eq!(playback.buttons_at(0), Some(0x00));
+        assert_eq!(playback.buttons_at(1), None);
+        assert_eq!(playback.buttons_at(2), Some(0x10));
+        assert!(!playback.is_finished());
+        assert_eq!(playback.buttons_at(5), Some(0x00));
+        assert!(playback.is_finished());
+    }
+}
@@ -1,4 +1,5 @@
 use wasm_bindgen::prelude::*;
+use serde_derive::{Serialize, Deserialize};
 
 #[wasm_bindgen]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -6,8 +7,30 @@ pub enum Button {
     RIGHT, LEFT, UP, DOWN, A, B, SELECT, START
 }
 
+// All eight buttons in one byte, the bit of a Button is set while it is pressed. Replays and
+// netplay apply a whole frame of input at once with Joypad::apply_state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InputState(pub u8);
+
+impl InputState {
+
+    pub fn with(self, button: Button) -> InputState {
+        return InputState(self.0 | 1 << button as u8);
+    }
+
+    #[cfg(test)]
+    pub fn without(self, button: Button) -> InputState {
+        return InputState(self.0 & !(1 << button as u8));
+    }
+
+    #[cfg(test)]
+    pub fn is_pressed(self, button: Button) -> bool {
+        return self.0 & 1 << button as u8 != 0;
+    }
+}
+
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Joypad {
     row0: u8,
     row1: u8,
@@ -54,6 +77,18 @@ impl Joypad {
         self.data = (self.data & 0xF0) | new_values;
     }
 
+    pub fn is_any_pressed(&self) -> bool {
+        return self.row0 != 0x0F || self.row1 != 0x0F;
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.press(button);
+        } else {
+            self.release(button);
+        }
+    }
+
     pub fn press(&mut self, button: Button) {
         match button {
             Button::RIGHT => self.row0 &= !(1 << 0),
@@ -83,3 +118,89 @@ impl Joypad {
     }
 }
 
+impl Joypad {
+
+    // Presses and releases every button that differs from state in one go. Like single presses
+    // it requests the joypad interrupt when a selected line goes from high to low.
+    pub fn apply_state(&mut self, state: InputState) {
+        self.row0 = !state.0 & 0x0F;
+        self.row1 = (!state.0 >> 4) & 0x0F;
+        self.trigger_interrupt();
+    }
+
+    pub fn current_state(&self) -> InputState {
+        return InputState(!(self.row0 | (self.row1 << 4)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_round_trip() {
+        let mut joypad = Joypad::new();
+        joypad.press(Button::LEFT);
+        joypad.press(Button::START);
+        assert_eq!(joypad.current_state(), InputState(0x82));
+
+        let mut copy = Joypad::new();
+        copy.apply_state(joypad.current_state());
+        assert_eq!(copy.current_state(), InputState(0x82));
+        copy.write_byte(0xFF00, 0x10);
+        assert_eq!(copy.read_byte(0xFF00) & 0x0F, 0x07);
+    }
+
+    #[test]
+    fn action_row_reads_pressed_a() {
+        let mut joypad = Joypad::new();
+
+        // P15 low selects the action buttons
+        joypad.write_byte(0xFF00, 0x10);
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x0F);
+
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.read_byte(0xFF00) & 0x01, 0x00);
+        assert_eq!(joypad.read_byte(0xFF00) & 0xF0, 0xD0);
+        assert_eq!(joypad.interrupt, 0x10);
+
+        joypad.set_button(Button::A, false);
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x0F);
+    }
+
+    #[test]
+    fn unselected_row_is_ignored() {
+        let mut joypad = Joypad::new();
+
+        // P14 low selects the directions
+        joypad.write_byte(0xFF00, 0x20);
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x0F);
+        assert_eq!(joypad.interrupt, 0x00);
+
+        joypad.set_button(Button::DOWN, true);
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x07);
+        assert_eq!(joypad.interrupt, 0x10);
+    }
+
+    #[test]
+    fn applied_state_presses_buttons_at_once() {
+        let mut joypad = Joypad::new();
+        joypad.write_byte(0xFF00, 0x00);
+
+        let state = InputState::default().with(Button::A).with(Button::DOWN);
+        joypad.apply_state(state);
+        assert_eq!(joypad.current_state(), state);
+        assert!(joypad.current_state().is_pressed(Button::A));
+        assert!(joypad.current_state().is_pressed(Button::DOWN));
+        assert!(!joypad.current_state().is_pressed(Button::B));
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x06);
+        assert_eq!(joypad.interrupt, 0x10);
+
+        // Releasing is not an edge the interrupt looks for
+        joypad.interrupt = 0;
+        joypad.apply_state(state.without(Button::A));
+        assert_eq!(joypad.read_byte(0xFF00) & 0x0F, 0x07);
+        assert_eq!(joypad.interrupt, 0x00);
+    }
+}
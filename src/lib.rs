@@ -1,6 +1,6 @@
 mod cpu;
 mod mmu;
-mod console;
+pub mod console;
 mod ppu;
 mod cartridge;
 mod io;
@@ -9,7 +9,17 @@ mod dma;
 mod timer;
 mod logger;
 mod joypad;
-mod psg;
+mod apu;
+mod serial;
+mod rewind;
+mod cheats;
+mod input_log;
+mod font;
+mod symbols;
+mod backtrace;
+mod sgb;
+mod web;
+mod audio;
 
 extern crate serde_json;
 extern crate wasm_bindgen;
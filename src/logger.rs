@@ -2,23 +2,102 @@ use std::path::Path;
 use std::fs::{OpenOptions, File};
 use std::io::Write;
 use std::convert::TryInto;
+use crate::cpu::Cpu;
+use crate::mmu::Mmu;
 
 const FILE_PATH: &str = "cpu.log";
 
 static mut FILE: Option<File> = Option::None;
 
+// Sends the log to path instead of FILE_PATH, from the next line on
+pub fn set_log_path(path: &Path) {
+    unsafe {
+        FILE = Option::Some(open_file(path));
+    }
+}
+
+fn open_file(path: &Path) -> File {
+    return OpenOptions::new()
+        .create(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+}
+
 pub fn log(value: String) {
     println!("{}", value);
     unsafe {
         if FILE.is_none() {
-            FILE = Option::Some(OpenOptions::new()
-                .create(true)
-                .write(true)
-                .open(FILE_PATH)
-                .unwrap());
+            FILE = Option::Some(open_file(Path::new(FILE_PATH)));
         }
 
         FILE.as_mut().unwrap().write_all((value + "\n").as_bytes());
 
     }
 }
+
+// Problems worth telling the user about that do not stop emulation
+pub fn warn(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::warn_1(&message.into());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("warning: {}", message);
+}
+
+// Debug traces the user asked for, the browser console or stderr
+pub fn debug(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::log_1(&message.into());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", message);
+}
+
+pub struct Logger;
+
+impl Logger {
+
+    // https://robertheaton.com/gameboy-doctor/
+    // One line per instruction in the format Gameboy Doctor and SameBoy compare against
+    pub fn format_cpu_trace(cpu: &Cpu, mmu: &Mmu) -> String {
+        let pc = cpu.pc;
+        return format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                       cpu.a, cpu.f, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.sp, pc,
+                       mmu.read_bus(pc),
+                       mmu.read_bus(pc.wrapping_add(1)),
+                       mmu.read_bus(pc.wrapping_add(2)),
+                       mmu.read_bus(pc.wrapping_add(3)));
+    }
+
+    pub fn trace_cpu(cpu: &Cpu, mmu: &Mmu) {
+        log(Logger::format_cpu_trace(cpu, mmu));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_trace_line_format() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.a = 0x01;
+        cpu.f = 0xB0;
+        cpu.b = 0x00;
+        cpu.c = 0x13;
+        cpu.d = 0x00;
+        cpu.e = 0xD8;
+        cpu.h = 0x01;
+        cpu.l = 0x4D;
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0xC100;
+        for (i, byte) in [0x00, 0xC3, 0x13, 0x02].iter().enumerate() {
+            mmu.write_byte(0xC100 + i as u16, *byte);
+        }
+
+        assert_eq!(Logger::format_cpu_trace(&cpu, &mmu),
+                   "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:C100 PCMEM:00,C3,13,02");
+    }
+}
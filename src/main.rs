@@ -9,20 +9,355 @@ mod dma;
 mod timer;
 mod logger;
 mod joypad;
+mod apu;
+mod serial;
+mod rewind;
+mod cheats;
+mod input_log;
+mod font;
+mod symbols;
+mod backtrace;
+mod sgb;
+mod audio;
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+mod gamepad;
+mod config;
+mod screen;
+mod headless;
 
-const WIDTH: usize = 640;
-const HEIGHT: usize = 360;
+const DEFAULT_SCALE: usize = 4;
+// Frames emulated per displayed frame while Space is held
+const DEFAULT_TURBO: u32 = 4;
+
+// 4194304 / 70224 = ~59.73 frames per second
+const FRAME_DURATION: Duration = Duration::from_micros(16_742);
 
 use crate::console::{Console};
+use crate::ppu::{SCREEN_H, DMG_PALETTE_GREEN};
+use crate::joypad::{Button, InputState};
+use crate::apu::CHANNELS;
+use crate::cartridge::{LoadError, extract_rom, read_header};
+use crate::config::{Config, CONFIG_FILE_NAME};
+use crate::screen::{Filter, OUTPUT_W, OUTPUT_H};
+use crate::headless::{HeadlessOptions, Outcome, run_headless};
+use minifb::{Key, KeyRepeat, Window, WindowOptions, ScaleMode};
+use std::collections::HashMap;
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+struct Options {
+    rom_path: String,
+    info: bool,
+    headless: Option<HeadlessOptions>,
+    scale: usize,
+    audio: bool,
+    boot_rom_path: Option<String>,
+    turbo: u32,
+    palette: [u32; 4],
+    filter: Filter,
+    key_bindings: HashMap<Key, Button>,
+}
+
+// The palettes --palette offers besides DMG_PALETTE_GREEN, lightest shade first
+const DMG_PALETTE_GRAY: [u32; 4] = [0xFFFFFF, 0xC0C0C0, 0x606060, 0x000000];
+const DMG_PALETTE_POCKET: [u32; 4] = [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F];
+// The palette the CGB boot rom gives DMG games when no button is held
+const DMG_PALETTE_BROWN: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+
+const PALETTES: [(&str, [u32; 4]); 4] = [
+    ("green", DMG_PALETTE_GREEN),
+    ("gray", DMG_PALETTE_GRAY),
+    ("pocket", DMG_PALETTE_POCKET),
+    ("brown", DMG_PALETTE_BROWN),
+];
+
+const FILTERS: [(&str, Filter); 3] = [
+    ("nearest", Filter::Nearest),
+    ("scale2x", Filter::Scale2x),
+    ("bilinear", Filter::Bilinear),
+];
+
+fn print_usage(program: &str) {
+    eprintln!("usage: {} [--scale N] [--turbo N] [--palette NAME] [--filter NAME] [--no-audio] [--boot-rom FILE] [--info] <rom>", program);
+    eprintln!("       {} --headless [--frames N] [--until-result] [--screenshot FILE] [--boot-rom FILE] <rom>", program);
+    eprintln!();
+    eprintln!("    --scale N     make the window N times the 144 lines of the screen high (default {})", DEFAULT_SCALE);
+    eprintln!("    --turbo N     run N times faster while Space is held (default {})", DEFAULT_TURBO);
+    eprintln!("    --palette     colors of DMG games: green (default), gray, pocket or brown");
+    eprintln!("    --filter      upscaling of the screen: nearest (default), scale2x or bilinear");
+    eprintln!("    --no-audio    do not generate sound samples");
+    eprintln!("    --boot-rom    run the 256 byte DMG boot rom in FILE first");
+    eprintln!("    --info        print the cartridge header of the rom and exit");
+    eprintln!("    --headless    run without a window and print how many frames ran");
+    eprintln!("    --frames      stop the headless run after N frames (default {})", headless::DEFAULT_HEADLESS_FRAMES);
+    eprintln!("    --until-result  stop the headless run once the serial output says Passed or Failed");
+    eprintln!("    --screenshot  write the last frame of the headless run to FILE as a PNG");
+    eprintln!();
+    eprintln!("Defaults and key bindings are read from {} in the current directory or", CONFIG_FILE_NAME);
+    eprintln!("in $XDG_CONFIG_HOME/rustyboy, see src/config.rs for the format.");
+}
+
+fn find_palette(name: &str) -> Option<[u32; 4]> {
+    return PALETTES.iter().find(|(palette, _)| *palette == name).map(|(_, colors)| *colors);
+}
+
+fn parse_args(args: &[String], config: &Config) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut info = false;
+    let mut headless = false;
+    let mut headless_options = HeadlessOptions::new();
+    let mut scale = config.scale.filter(|&scale| scale > 0).unwrap_or(DEFAULT_SCALE);
+    let mut audio = config.audio.unwrap_or(true);
+    let mut boot_rom_path = None;
+    let mut turbo = DEFAULT_TURBO;
+    let mut palette = DMG_PALETTE_GREEN;
+    let mut filter = Filter::Nearest;
+
+    if let Some(name) = &config.palette {
+        match find_palette(name) {
+            Some(colors) => palette = colors,
+            None => eprintln!("warning: unknown palette '{}' in config", name),
+        }
+    }
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => {
+                let value = args.next().ok_or("--scale expects a value")?;
+                scale = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid scale '{}'", value)),
+                };
+            },
+            "--turbo" => {
+                let value = args.next().ok_or("--turbo expects a value")?;
+                turbo = match value.parse::<u32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid turbo factor '{}'", value)),
+                };
+            },
+            "--palette" => {
+                let value = args.next().ok_or("--palette expects a name")?;
+                palette = match find_palette(value) {
+                    Some(colors) => colors,
+                    None => return Err(format!("unknown palette '{}'", value)),
+                };
+            },
+            "--filter" => {
+                let value = args.next().ok_or("--filter expects a name")?;
+                filter = match FILTERS.iter().find(|(name, _)| name == value) {
+                    Some((_, filter)) => *filter,
+                    None => return Err(format!("unknown filter '{}'", value)),
+                };
+            },
+            "--no-audio" => { audio = false; },
+            "--info" => { info = true; },
+            "--headless" => { headless = true; },
+            "--frames" => {
+                let value = args.next().ok_or("--frames expects a value")?;
+                headless_options.frames = match value.parse::<u32>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid frame count '{}'", value)),
+                };
+            },
+            "--until-result" => { headless_options.until_result = true; },
+            "--screenshot" => {
+                let value = args.next().ok_or("--screenshot expects a file")?;
+                headless_options.screenshot_path = Some(value.clone());
+            },
+            "--boot-rom" => {
+                let value = args.next().ok_or("--boot-rom expects a file")?;
+                boot_rom_path = Some(value.clone());
+            },
+            _ if arg.starts_with("--") => { return Err(format!("unknown option '{}'", arg)); },
+            _ => {
+                if rom_path.is_some() { return Err(format!("unexpected argument '{}'", arg)); }
+                rom_path = Some(arg.clone());
+            },
+        }
+    }
+
+    let headless = if headless { Some(headless_options) } else { None };
+    return match rom_path {
+        Some(rom_path) => Ok(Options { rom_path, info, headless, scale, audio, boot_rom_path, turbo, palette, filter, key_bindings: config.key_bindings() }),
+        None => Err("no rom given".to_string()),
+    };
+}
 
 fn main() {
-    let mut console: Console = Console::new();
-    let cart_path = "./roms/promo_demo.gbc";
+    let args: Vec<String> = std::env::args().collect();
+    let program = args.first().map(String::as_str).unwrap_or("rustyboy");
 
-    //console.load(cart_path);
+    let config = Config::load();
+    let options = match parse_args(&args[1..], &config) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            print_usage(program);
+            process::exit(2);
+        },
+    };
+
+    if options.info {
+        let result = std::fs::read(&options.rom_path)
+            .map_err(|_| LoadError::FileNotFound)
+            .and_then(extract_rom)
+            .and_then(|rom| read_header(&rom));
+        match result {
+            Ok(header) => print!("{}", header),
+            Err(error) => {
+                eprintln!("error: unable to read '{}': {}", options.rom_path, error);
+                process::exit(1);
+            },
+        }
+        return;
+    }
+
+    let mut console: Console = Console::new();
+    if let Err(error) = console.load_from_file(&options.rom_path) {
+        eprintln!("error: unable to load '{}': {}", options.rom_path, error);
+        process::exit(1);
+    }
+    if let Some(boot_rom_path) = &options.boot_rom_path {
+        let result = std::fs::read(boot_rom_path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| console.load_boot_rom(&data).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            eprintln!("error: unable to load boot rom '{}': {}", boot_rom_path, error);
+            process::exit(1);
+        }
+    }
     console.reset();
-    console.execute_ticks(45165847);
+    console.set_audio_enabled(options.audio);
+    console.set_dmg_palette(options.palette);
+    console.record_backtrace(true);
+
+    if let Some(headless_options) = &options.headless {
+        match run_headless(&mut console, headless_options) {
+            Ok(result) => {
+                println!("{}", console.serial_output());
+                println!("{} frames", result.frames);
+                let failed = headless_options.until_result && result.outcome != Outcome::Passed;
+                process::exit(if failed { 1 } else { 0 });
+            },
+            Err(error) => {
+                eprintln!("error: unable to write screenshot: {}", error);
+                process::exit(1);
+            },
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    if options.audio {
+        console.set_sample_rate(audio::OUTPUT_RATE);
+        match audio::CpalSink::new(console.sample_rate()) {
+            Ok(sink) => console.set_audio_sink(Box::new(sink)),
+            Err(error) => eprintln!("warning: no sound output: {}", error),
+        }
+    }
 
-    console.execute_ticks(1);
-    print!("finished")
+    // The window keeps the aspect ratio of the letterboxed output
+    let height = SCREEN_H * options.scale;
+    let width = height * OUTPUT_W / OUTPUT_H;
+    let title = match console.cartridge_header().title.as_str() {
+        "" => "RustyBoy".to_string(),
+        game => format!("RustyBoy - {}", game),
+    };
+    let mut window = Window::new(&title, width, height, WindowOptions {
+        scale_mode: ScaleMode::AspectRatioStretch,
+        ..WindowOptions::default()
+    }).expect("unable to open window");
+    window.limit_update_rate(Some(FRAME_DURATION));
+
+    let mut output = vec![0; OUTPUT_W * OUTPUT_H];
+    let mut pressed = InputState::default();
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = match gamepad::GamepadInput::new(gamepad::GamepadMapping::new()) {
+        Ok(gamepad) => Some(gamepad),
+        Err(error) => { eprintln!("warning: no gamepad support: {}", error); None },
+    };
+
+    // Input is read after each frame for the next one
+    run_frames(&mut console, |console, _| {
+        if window.is_key_pressed(Key::F12, KeyRepeat::No) {
+            save_screenshot(console);
+        }
+
+        screen::scale_frame(console.frame_buffer(), &mut output, options.filter);
+        window.update_with_buffer(&output, OUTPUT_W, OUTPUT_H)
+            .expect("unable to update window");
+        if !window.is_open() { return false; }
+
+        update_buttons(console, &window, &options.key_bindings, &mut pressed);
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepad) = &mut gamepad { gamepad.poll(console); }
+
+        if window.is_key_pressed(Key::P, KeyRepeat::No) {
+            console.set_paused(!console.is_paused());
+            let paused_title = format!("{} (paused)", title);
+            window.set_title(if console.is_paused() { &paused_title } else { &title });
+        }
+
+        // 1 - 4 mute and unmute the sound channels
+        for (key, &channel) in CHANNEL_KEYS.iter().zip(CHANNELS.iter()) {
+            if window.is_key_pressed(*key, KeyRepeat::No) {
+                let enabled = !console.is_channel_enabled(channel);
+                console.set_channel_enabled(channel, enabled);
+                println!("{:?} channel {}", channel, if enabled { "on" } else { "muted" });
+            }
+        }
+
+        // Paused frames run nothing, the window keeps showing the last one
+        let turbo = if window.is_key_down(Key::Space) { options.turbo } else { 1 };
+        console.set_turbo(turbo);
+        return true;
+    });
+
+    if let Err(error) = console.save_battery_ram() {
+        eprintln!("unable to write save file: {}", error);
+    }
 }
+
+// The loop of both the window and --headless. After every frame output gets the console and the
+// number of frames run so far, it shows or checks the frame and returns false to stop.
+fn run_frames(console: &mut Console, mut output: impl FnMut(&mut Console, u32) -> bool) -> u32 {
+    let mut frames = 0;
+    loop {
+        console.execute_frame();
+        frames += 1;
+
+        // Without an audio sink nothing takes the samples, keep the buffer from growing
+        console.drain_samples();
+
+        if !output(console, frames) { return frames; }
+    }
+}
+
+fn save_screenshot(console: &Console) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let path = format!("screenshot-{}.png", timestamp);
+
+    match std::fs::write(&path, console.screenshot()) {
+        Ok(()) => println!("saved {}", path),
+        Err(error) => eprintln!("unable to write {}: {}", path, error),
+    }
+}
+
+const CHANNEL_KEYS: [Key; 4] = [Key::Key1, Key::Key2, Key::Key3, Key::Key4];
+
+// Only when the keys change, the joypad interrupt still fires once per press
+fn update_buttons(console: &mut Console, window: &Window, key_bindings: &HashMap<Key, Button>, pressed: &mut InputState) {
+    let mut current = InputState::default();
+    for key in window.get_keys() {
+        if let Some(&button) = key_bindings.get(&key) {
+            current = current.with(button);
+        }
+    }
+
+    if current == *pressed { return; }
+    console.set_input_state(current);
+    *pressed = current;
+}
+
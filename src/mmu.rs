@@ -1,20 +1,31 @@
-use crate::cartridge::{Cartridge, CartridgeType, HEADER_INDEX_FOR_CARTRIDGE_TYPE};
+use crate::cartridge::{Cartridge, CartridgeType, LoadError, extract_rom, read_header};
+
+pub const BOOT_ROM_SIZE: usize = 0x100;
 use crate::ppu::Ppu;
-use crate::psg::Psg;
-use crate::dma::{Dma, execute_dma_tick, execute_odma};
+use crate::apu::{Apu, CHANNELS, SAMPLE_RATE};
+use crate::serial::Serial;
+use crate::dma::{Dma, execute_dma_tick, execute_odma, execute_odma_ticks};
 use crate::timer::Timer;
+use crate::cheats::Cheats;
 use std::cell::RefCell;
 use std::rc::Rc;
 use crate::joypad::Joypad;
+use crate::sgb::SgbReceiver;
 use crate::console::GameboyType;
+use crate::logger::warn;
 use wasm_bindgen::prelude::*;
-use std::path::Path;
-use std::fs;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hasher;
+use serde_derive::{Serialize, Deserialize};
+use serde_big_array::BigArray;
 
 #[wasm_bindgen]
+#[derive(Serialize, Deserialize)]
 pub struct Mmu {
+    #[serde(with = "BigArray")]
     hram: [u8; 0x7F],
-    sram: [u8; 0x30], // sound
+    #[serde(with = "BigArray")]
     wram: [u8; 0x8000],
     wram_bank: usize,
     switch_speed: bool,
@@ -23,25 +34,141 @@ pub struct Mmu {
     pub interrupt_flags: u8,
     #[wasm_bindgen(skip)]
     pub ppu: Ppu,
-    cartridge: Cartridge,
+    #[wasm_bindgen(skip)]
+    pub apu: Apu,
+    #[wasm_bindgen(skip)]
+    pub cartridge: Cartridge,
+    // Like the cartridge rom the boot rom is not part of save states, only whether it is mapped
+    #[serde(skip)]
+    boot_rom: Vec<u8>,
+    boot_rom_mapped: bool,
     pub dma: Dma,
     pub timer: Timer,
     pub joypad: Joypad,
+    // Super Game Boy packets sent through the joypad register, a packet being sent is lost
+    // by save states
+    #[wasm_bindgen(skip)]
+    #[serde(skip)]
+    pub sgb: SgbReceiver,
+    #[wasm_bindgen(skip)]
+    pub serial: Serial,
     pub model: GameboyType,
+
+    // Debugger watchpoints, not part of save states
+    #[wasm_bindgen(skip)]
+    #[serde(skip)]
+    pub watchpoints: HashSet<u16>,
+    #[wasm_bindgen(skip)]
+    #[serde(skip)]
+    pub watchpoint_hit: Option<u16>,
+
+    // Cheats stay active across save states
+    #[wasm_bindgen(skip)]
+    #[serde(skip)]
+    pub cheats: Cheats,
+
+    // Writes to IE and IF since the console last collected them, None while not tracing
+    #[wasm_bindgen(skip)]
+    #[serde(skip)]
+    pub interrupt_writes: Option<Vec<InterruptWrite>>,
+
+    // M-cycles of the instruction being executed so far, and how many of them the rest of the
+    // system has been clocked through. Both are 0 between instructions.
+    #[serde(skip)]
+    instruction_cycles: u32,
+    #[serde(skip)]
+    synced_cycles: u32,
+    // Clocks of the PPU and APU run since the console last took them. They are the 4 MHz clocks
+    // of the hardware, which the CPU runs twice as fast in double speed mode.
+    #[serde(skip)]
+    clocks: u32,
 }
 
-#[wasm_bindgen]
+// A write by the program to IE (0xFFFF) or IF (0xFF0F), see Console::trace_interrupts
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptWrite {
+    pub pc: u16,
+    pub address: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+// https://gbdev.io/pandocs/#ffff-ie-interrupt-enable-r-w
+const INTERRUPT_NAMES: [&str; 5] = ["V-Blank", "STAT", "Timer", "Serial", "Joypad"];
+
+impl fmt::Display for InterruptWrite {
+    // PC:0150 IE 01 -> 05 (+Timer)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let register = if self.address == 0xFFFF { "IE" } else { "IF" };
+        write!(f, "PC:{:04X} {} {:02X} -> {:02X}", self.pc, register, self.old, self.new)?;
+
+        let changes: Vec<String> = INTERRUPT_NAMES.iter().enumerate()
+            .filter(|(bit, _)| (self.old ^ self.new) & (1 << bit) != 0)
+            .map(|(bit, name)| format!("{}{}", if self.new & (1 << bit) != 0 { "+" } else { "-" }, name))
+            .collect();
+        if !changes.is_empty() {
+            write!(f, " ({})", changes.join(" "))?;
+        }
+        return Ok(());
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Speed {
     FAST, SLOW
 }
 
+// Contents of WRAM, HRAM, VRAM and OAM at power on. Real hardware starts with noise, a seed
+// makes that noise the same on every run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitPattern {
+    Zero,
+    Ones,
+    Seeded(u64),
+}
+
+// Hands out the bytes of a pattern, the regions filled one after the other share one stream
+pub struct MemoryFiller {
+    pattern: InitPattern,
+    state: u64,
+}
+
+impl MemoryFiller {
+
+    pub fn new(pattern: InitPattern) -> Self {
+        let state = if let InitPattern::Seeded(seed) = pattern { seed } else { 0 };
+        return MemoryFiller { pattern, state };
+    }
+
+    pub fn fill(&mut self, memory: &mut [u8]) {
+        match self.pattern {
+            InitPattern::Zero => memory.fill(0x00),
+            InitPattern::Ones => memory.fill(0xFF),
+            InitPattern::Seeded(_) => {
+                for chunk in memory.chunks_mut(8) {
+                    let bytes = self.next().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[.. chunk.len()]);
+                }
+            },
+        }
+    }
+
+    // https://prng.di.unimi.it/splitmix64.c
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        return z ^ (z >> 31);
+    }
+}
+
 #[allow(unused)]
 impl Mmu {
 
     pub fn new() -> Self {
         return Mmu {
-            sram: [0; 0x30],
             wram: [0; 0x8000],
             wram_bank: 1,
             hram: [0; 0x7F],
@@ -51,52 +178,177 @@ impl Mmu {
             interrupt_enable: 0,
 
             cartridge: Cartridge::new(),
+            boot_rom: Vec::new(),
+            boot_rom_mapped: false,
             ppu: Ppu::new(),
+            apu: Apu::new(SAMPLE_RATE),
             dma: Dma::new(),
             timer: Timer::new(),
             joypad: Joypad::new(),
-            model: GameboyType::CLASSIC
+            sgb: SgbReceiver::new(),
+            serial: Serial::new(),
+            model: GameboyType::CLASSIC,
+            watchpoints: HashSet::new(),
+            watchpoint_hit: None,
+            cheats: Cheats::new(),
+            interrupt_writes: None,
+            instruction_cycles: 0,
+            synced_cycles: 0,
+            clocks: 0,
         };
     }
 
-    pub fn load_cartridge_from_js_value(&mut self, result: &JsValue) {
-        let bytes: Vec<u8> = result.into_serde().unwrap();
-        self.load_cartridge_from_bytes(bytes);
+    pub fn has_cartridge(&self) -> bool {
+        return self.cartridge.cartridge_type != CartridgeType::None;
     }
 
-    pub fn load_from_file_address(&mut self, file_path: &str) {
-        let path = Path::new(file_path);
-        let bytes : Vec<u8> = fs::read(path).expect("yabe");
-        self.load_cartridge_from_bytes(bytes);
+    // The WRAM bank mapped at 0xD000 - 0xDFFF
+    pub fn wram_bank(&self) -> usize {
+        return self.wram_bank;
     }
 
-    pub fn load_cartridge_from_bytes(&mut self, bytes: Vec<u8>) {
-        let cartridge_type = bytes[HEADER_INDEX_FOR_CARTRIDGE_TYPE];
-        match cartridge_type {
-            0x00 ..= 0x00 => { self.cartridge.cartridge_type = CartridgeType::MBC0 },
-            0x01 ..= 0x03 => { self.cartridge.cartridge_type = CartridgeType::MBC1 },
-            //0x05..=0x06 => { cartridge.cartridge_type = Some(CartridgeType::MBC2) },
-            0x0F ..= 0x13 => { self.cartridge.cartridge_type = CartridgeType::MBC3 },
-            0x19 ..= 0x1E => { self.cartridge.cartridge_type = CartridgeType::MBC5 },
-            _ => panic!("cartridge type not implemented")
-        }
+    pub fn init_memory(&mut self, pattern: InitPattern) {
+        let mut filler = MemoryFiller::new(pattern);
+        filler.fill(&mut self.wram);
+        filler.fill(&mut self.hram);
+        self.ppu.init_memory(&mut filler);
+    }
+
+    // The current cartridge is left untouched when the rom is rejected
+    pub fn load_cartridge(&mut self, bytes: Vec<u8>) -> Result<(), LoadError> {
+        let bytes = extract_rom(bytes)?;
+        let cartridge_type = read_header(&bytes)?.cartridge_type;
+        self.cartridge.cartridge_type = match cartridge_type {
+            0x00 ..= 0x00 => CartridgeType::MBC0,
+            0x01 ..= 0x03 => CartridgeType::MBC1,
+            0x05 ..= 0x06 => CartridgeType::MBC2,
+            0x0F ..= 0x13 => CartridgeType::MBC3,
+            0x19 ..= 0x1E => CartridgeType::MBC5,
+            _ => return Err(LoadError::UnsupportedCartridge(cartridge_type)),
+        };
         self.cartridge.set_rom(bytes);
         self.model = self.cartridge.get_gameboy_type().clone();
+
+        // The boot rom locks up on a bad header checksum, most emulators run the game anyway
+        if !self.cartridge.header().header_checksum_valid {
+            warn("the cartridge header checksum does not match, the rom may be corrupted");
+        }
+        return Ok(());
+    }
+
+    pub fn load_boot_rom(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if data.len() != BOOT_ROM_SIZE { return Err(LoadError::InvalidBootRomSize(data.len())); }
+
+        self.boot_rom = data.to_vec();
+        return Ok(());
+    }
+
+    pub fn has_boot_rom(&self) -> bool {
+        return !self.boot_rom.is_empty();
+    }
+
+    // https://gbdev.io/pandocs/#power-up-sequence
+    // The boot rom covers 0x0000 - 0x00FF until it writes to 0xFF50
+    pub fn map_boot_rom(&mut self) {
+        self.boot_rom_mapped = self.has_boot_rom();
+    }
+
+    pub fn load_state(&mut self, mut state: Mmu) {
+        let cartridge = std::mem::replace(&mut self.cartridge, Cartridge::new());
+        let saved_cartridge = std::mem::replace(&mut state.cartridge, cartridge);
+        state.cartridge.load_state(saved_cartridge);
+        self.replace(state);
+    }
+
+    // Takes over mmu in place of this one, keeping the front end settings and debugger state
+    // that are not part of the emulated hardware
+    pub fn replace(&mut self, mut state: Mmu) {
+        state.apu.set_sample_output(self.apu.sample_output());
+        state.apu.set_sample_rate(self.apu.sample_rate());
+        for &channel in CHANNELS.iter() {
+            state.apu.set_channel_enabled(channel, self.apu.channel_enabled(channel));
+        }
+        state.ppu.set_dmg_palette(self.ppu.dmg_palette());
+        state.ppu.set_frame_skip(self.ppu.frame_skip());
+        if let Some(callback) = self.ppu.take_scanline_callback() {
+            state.ppu.set_scanline_callback(callback);
+        }
+        if let Some(callback) = self.ppu.take_vblank_callback() {
+            state.ppu.set_vblank_callback(callback);
+        }
+        state.watchpoints = std::mem::take(&mut self.watchpoints);
+        state.cheats = std::mem::take(&mut self.cheats);
+        state.interrupt_writes = self.interrupt_writes.take();
+        state.boot_rom = std::mem::take(&mut self.boot_rom);
+        *self = state;
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
+        // https://gbdev.io/pandocs/#oam-dma-transfer
+        // While OAM DMA runs the CPU can only reach HRAM
+        if self.dma.is_oam_transfer_active() && !(0xFF80 ..= 0xFFFE).contains(&address) { return 0xFF; }
+        if !self.ppu_allows_access(address) { return 0xFF; }
+
+        let value = self.read_bus(address);
+        if address < 0x8000 { return self.cheats.patch_rom_read(address, value); }
+        return value;
+    }
+
+    // VRAM and OAM are locked while the PPU uses them, reads give 0xFF and writes are dropped
+    fn ppu_allows_access(&self, address: u16) -> bool {
+        return match address {
+            0x8000 ..= 0x9FFF => self.ppu.vram_accessible(),
+            0xFE00 ..= 0xFE9F => self.ppu.oam_accessible(),
+            _ => true,
+        };
+    }
+
+    // Pokes the GameShark codes, the bank selects a WRAM bank for 0xD000 - 0xDFFF
+    pub fn apply_cheats(&mut self) {
+        for index in 0 .. self.cheats.game_shark_codes().len() {
+            let code = self.cheats.game_shark_codes()[index];
+            match (code.bank, code.address) {
+                (0x90 ..= 0x97, 0xD000 ..= 0xDFFF) => {
+                    let bank = match code.bank & 0x07 { 0 => 1, n => n as usize };
+                    self.wram[(bank * 0x1000) | (code.address as usize & 0x0FFF)] = code.value;
+                },
+                _ => self.write_byte(code.address, code.value),
+            }
+        }
+    }
+
+    // Copies len bytes starting at start for memory viewers, wrapping past 0xFFFF
+    pub fn dump_range(&self, start: u16, len: u16) -> Vec<u8> {
+        return (0..len).map(|offset| self.read_bus(start.wrapping_add(offset))).collect();
+    }
+
+    // Every WRAM bank, the IO registers, HRAM and IE, then the memory of the PPU
+    pub fn hash_state(&self, hasher: &mut impl Hasher) {
+        hasher.write(&self.wram);
+        for address in 0xFF00 ..= 0xFFFF {
+            hasher.write_u8(self.read_bus(address));
+        }
+        self.ppu.hash_memory(hasher);
+    }
+
+    // Reads without the CPU restrictions, used by the DMA engines
+    pub fn read_bus(&self, address: u16) -> u8 {
         match address {
+            0x0000 ..= 0x00FF if self.boot_rom_mapped => { self.boot_rom[address as usize] },
             0x0000 ..= 0x7FFF => { self.cartridge.read_byte(address) },
             0x8000 ..= 0x9FFF => { self.ppu.read_byte(address) },
-            0xA000 ..= 0xBFFF => { self.cartridge.read_byte(address) },
+            0xA000 ..= 0xBFFF => { self.cartridge.read_ram(address) },
             0xC000 ..= 0xCFFF | (0xE000 ..= 0xEFFF) => { self.wram[address as usize & 0x0FFF] },
-            0xD000 ..= 0xDFFF | (0xF000 ..= 0xFDFF) => { self.wram[(self.wram_bank * 0x1000) | address as usize & 0x0FFF] },
+            0xD000 ..= 0xDFFF | (0xF000 ..= 0xFDFF) => { self.wram[(self.wram_bank * 0x1000) | (address as usize & 0x0FFF)] },
             0xFE00 ..= 0xFE9F => { self.ppu.read_byte(address) },
             0xFF00 ..= 0xFF00 => { self.joypad.read_byte(address) },
-            0xFF01 ..= 0xFF02 => { 0xFF }, // serial transfer
+            0xFF01 ..= 0xFF02 => { self.serial.read_byte(address) },
             0xFF04 ..= 0xFF07 => { self.timer.read_byte(address) },
             0xFF0F => { self.interrupt_flags },
-            0xFF10 ..= 0xFF3F => { self.sram[address as usize - 0xFF10] },
+            0xFF10 ..= 0xFF3F => { self.apu.read_byte(address) },
+            // https://gbdev.io/pandocs/#cgb-registers
+            // A DMG has none of the CGB registers, games probe KEY1 to tell the two apart
+            0xFF4D | 0xFF4F | 0xFF51 ..= 0xFF55 | 0xFF68 ..= 0xFF6C | 0xFF70 if self.model != GameboyType::COLOR => 0xFF,
             0xFF4D => (if self.speed == Speed::FAST { 0x80 } else { 0 }) | (if self.switch_speed { 1 } else { 0 }),
             0xFF40 ..= 0xFF4F => { self.ppu.read_byte(address) },
             0xFF51 ..= 0xFF55 => { self.dma.read_byte(address) },
@@ -104,26 +356,51 @@ impl Mmu {
             0xFF70 ..= 0xFF70 => { self.wram_bank as u8 },
             0xFF80 ..= 0xFFFE => { self.hram[address as usize & 0x007F] },
             0xFFFF => { self.interrupt_enable },
-            _ => 0,
+            // https://gbdev.io/pandocs/#fea0-feff-range
+            // A DMG reads 0x00 from the unusable area while the OAM is not blocked
+            0xFEA0 ..= 0xFEFF => 0x00,
+            // https://gbdev.io/pandocs/#io-ranges
+            // Unused IO registers are open bus and read back 0xFF
+            _ => 0xFF,
         }
     }
 
     pub fn write_byte(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() && self.watchpoints.contains(&address) {
+            self.watchpoint_hit = Some(address);
+        }
+
+        if self.dma.is_oam_transfer_active() && !(0xFF80 ..= 0xFFFE).contains(&address) { return; }
+        if !self.ppu_allows_access(address) { return; }
+
+        if let Some(writes) = &mut self.interrupt_writes {
+            let old = match address { 0xFF0F => Some(self.interrupt_flags), 0xFFFF => Some(self.interrupt_enable), _ => None };
+            if let Some(old) = old {
+                // The console fills in the PC of the instruction
+                writes.push(InterruptWrite { pc: 0, address, old, new: value });
+            }
+        }
+
         match address {
             0x0000 ..= 0x7FFF => { self.cartridge.write_byte(address, value) },
             0x8000 ..= 0x9FFF => { self.ppu.write_byte(address, value) },
-            0xA000 ..= 0xBFFF => { self.cartridge.write_byte(address, value) },
+            0xA000 ..= 0xBFFF => { self.cartridge.write_ram(address, value) },
             0xC000 ..= 0xCFFF | (0xE000 ..= 0xEFFF) => { self.wram[address as usize & 0x0FFF] = value },
             0xD000 ..= 0xDFFF | (0xF000 ..= 0xFDFF) => { self.wram[(self.wram_bank * 0x1000) | (address as usize & 0x0FFF)] = value },
             0xFE00 ..= 0xFE9F => { self.ppu.write_byte(address, value) },
-            0xFF00 => { self.joypad.write_byte(address, value) },
-            0xFF01 ..= 0xFF02 => { }, // serial transfer
+            0xFF00 => {
+                self.sgb.write_joypad(value);
+                self.joypad.write_byte(address, value)
+            },
+            0xFF01 ..= 0xFF02 => { self.serial.write_byte(address, value) },
             0xFF04 ..= 0xFF07 => { self.timer.write_byte(address, value) },
             0xFF0F => { self.interrupt_flags = value },
-            0xFF10 ..= 0xFF3F => { self.sram[address as usize - 0xFF10] = value },
+            0xFF10 ..= 0xFF3F => { self.apu.write_byte(address, value) },
             0xFF46 => { execute_odma(self, value) },
-            0xFF4D => { if value & 0x1 == 0x1 { self.switch_speed = true; } },
+            0xFF4D | 0xFF4F | 0xFF51 ..= 0xFF55 | 0xFF68 ..= 0xFF6C | 0xFF70 if self.model != GameboyType::COLOR => {},
+            0xFF4D => { self.switch_speed = value & 0x1 == 0x1; },
             0xFF40 ..= 0xFF4F => { self.ppu.write_byte(address, value) },
+            0xFF50 => { if value != 0 { self.boot_rom_mapped = false; } },
             0xFF51 ..= 0xFF55 => { self.dma.write_byte(address, value) },
             0xFF68 ..= 0xFF6B => { self.ppu.write_byte(address, value) },
             0xFF70 ..= 0xFF70 => { self.wram_bank = match value & 0x7 { 0 => 1, n => n as usize }; },
@@ -134,18 +411,87 @@ impl Mmu {
     }
 
     pub fn read_word(&self, address: u16) -> u16 {
-        let low = (self.read_byte(address) as u16);
-        let high  = (self.read_byte(address + 1) as u16);
+        // A word at 0xFFFF takes its high byte from 0x0000
+        let low = self.read_byte(address) as u16;
+        let high = self.read_byte(address.wrapping_add(1)) as u16;
 
         return high.overflowing_shl(8).0 | low;
     }
 
+    // Like read_word, but through read_bus for debuggers that must see past the PPU and DMA locks
+    pub fn read_bus_word(&self, address: u16) -> u16 {
+        let low = self.read_bus(address) as u16;
+        let high = self.read_bus(address.wrapping_add(1)) as u16;
+
+        return (high << 8) | low;
+    }
+
     pub fn write_word(&mut self, address: u16, value: u16) {
         let low = (value & 0xFF) as u8;
         let high = (value >> 8) as u8;
 
         self.write_byte(address, low);
-        self.write_byte(address + 1, high);
+        self.write_byte(address.wrapping_add(1), high);
+    }
+
+    // https://gbdev.io/pandocs/#cpu-instruction-set
+    // Each memory access of the CPU takes one M-cycle. The rest of the system is clocked up to
+    // the start of that M-cycle first, so a register read in the middle of an instruction sees
+    // the value it has at that point and a write lands on the right clock.
+    pub fn cpu_read_byte(&mut self, address: u16) -> u8 {
+        self.sync_cycles();
+        let value = self.read_byte(address);
+        self.instruction_cycles += 1;
+        return value;
+    }
+
+    pub fn cpu_write_byte(&mut self, address: u16, value: u8) {
+        self.sync_cycles();
+        self.write_byte(address, value);
+        self.instruction_cycles += 1;
+    }
+
+    pub fn cpu_read_word(&mut self, address: u16) -> u16 {
+        let low = self.cpu_read_byte(address) as u16;
+        let high = self.cpu_read_byte(address.wrapping_add(1)) as u16;
+        return high << 8 | low;
+    }
+
+    pub fn cpu_write_word(&mut self, address: u16, value: u16) {
+        self.cpu_write_byte(address, (value & 0xFF) as u8);
+        self.cpu_write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // An internal M-cycle of the CPU that comes before a later memory access
+    pub fn cpu_idle_cycle(&mut self) {
+        self.instruction_cycles += 1;
+    }
+
+    // Clocks the rest of the system through the M-cycles of the instruction that were not yet
+    // run before a memory access, cycles is the length of the whole instruction
+    pub fn complete_instruction(&mut self, cycles: u32) {
+        debug_assert!(self.instruction_cycles <= cycles, "{} memory cycles in a {} cycle instruction", self.instruction_cycles, cycles);
+        self.instruction_cycles = cycles.max(self.instruction_cycles);
+        self.sync_cycles();
+        self.instruction_cycles = 0;
+        self.synced_cycles = 0;
+    }
+
+    fn sync_cycles(&mut self) {
+        let due = self.instruction_cycles - self.synced_cycles;
+        if due == 0 { return; }
+
+        self.execute_ticks(due * 4);
+        self.synced_cycles = self.instruction_cycles;
+    }
+
+    // The 4 MHz clocks run since the last call
+    pub fn take_clocks(&mut self) -> u32 {
+        return std::mem::take(&mut self.clocks);
+    }
+
+    pub fn is_speed_switch_armed(&self) -> bool {
+        return self.switch_speed;
     }
 
     pub fn toggle_speed(&mut self) {
@@ -155,18 +501,26 @@ impl Mmu {
         self.switch_speed = false;
     }
 
+    // https://gbdev.io/pandocs/#ff4d-key1-cgb-mode-only-prepare-speed-switch
+    // ticks are clocks of the CPU. In double speed mode the CPU, the timer and OAM DMA run twice
+    // as fast while the PPU and APU keep their rate. A HDMA block stalls the CPU for the same
+    // PPU dots, twice as many CPU clocks, in either mode.
     pub fn execute_ticks(&mut self, ticks: u32) {
         let cpu_divider = match self.speed {
             Speed::SLOW => 1,
             Speed::FAST => 2,
         };
 
+        execute_odma_ticks(self, ticks / 4);
+
         let dma_ticks = execute_dma_tick(self);
         let gpu_ticks = ticks / cpu_divider + dma_ticks;
         let timer_ticks = ticks + dma_ticks * cpu_divider;
+        self.clocks += gpu_ticks;
 
         self.timer.execute_ticks(timer_ticks);
         self.ppu.execute_ticks(gpu_ticks);
+        self.apu.execute_ticks(gpu_ticks);
 
         // Gather interrupts
 
@@ -175,12 +529,21 @@ impl Mmu {
 
         self.interrupt_flags |= self.ppu.interrupt_flags;
         self.ppu.interrupt_flags = 0;
+
+        self.interrupt_flags |= self.joypad.interrupt;
+        self.joypad.interrupt = 0;
+
+        self.interrupt_flags |= self.serial.interrupt_flags;
+        self.serial.interrupt_flags = 0;
     }
 
+    // The state the boot rom leaves behind, see Console::reset
     pub fn reset(&mut self, model: GameboyType) {
+        self.apu.reset(model);
         self.write_byte(0xFF05, 0);
         self.write_byte(0xFF06, 0);
         self.write_byte(0xFF07, 0);
+        self.write_byte(0xFF26, 0xF1);
         self.write_byte(0xFF10, 0x80);
         self.write_byte(0xFF11, 0xBF);
         self.write_byte(0xFF12, 0xF3);
@@ -199,7 +562,6 @@ impl Mmu {
         self.write_byte(0xFF23, 0xBF);
         self.write_byte(0xFF24, 0x77);
         self.write_byte(0xFF25, 0xF3);
-        self.write_byte(0xFF26, 0xF1);
         self.write_byte(0xFF40, 0x91);
         self.write_byte(0xFF42, 0);
         self.write_byte(0xFF43, 0);
@@ -209,6 +571,269 @@ impl Mmu {
         self.write_byte(0xFF49, 0xFF);
         self.write_byte(0xFF4A, 0);
         self.write_byte(0xFF4B, 0);
+
+        self.timer.reset(model);
+        self.ppu.reset(model);
+        self.dma.reset(model);
+        self.serial.reset(model);
+        self.sgb = SgbReceiver::new();
+        self.interrupt_flags = 0;
+        self.interrupt_enable = 0;
+        self.wram_bank = 1;
+        self.speed = Speed::SLOW;
+        self.switch_speed = false;
+        self.boot_rom_mapped = false;
+        self.instruction_cycles = 0;
+        self.synced_cycles = 0;
+        self.clocks = 0;
+        self.watchpoint_hit = None; // The writes above are not the program's
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::GpuMode;
+    use crate::sgb::SgbCommand;
+    use crate::cartridge::HEADER_INDEX_FOR_CARTRIDGE_TYPE;
+
+    #[test]
+    fn read_word_at_top_of_memory() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000] = 0x12;
+        let mut mmu = Mmu::new();
+        mmu.load_cartridge(rom).unwrap();
+
+        mmu.write_byte(0xFFFE, 0xCD);
+        mmu.write_byte(0xFFFF, 0xAB);
+
+        assert_eq!(mmu.read_word(0xFFFE), 0xABCD);
+        assert_eq!(mmu.read_word(0xFFFF), 0x12AB);
+    }
+
+    #[test]
+    fn write_word_at_top_of_memory_wraps() {
+        let mut mmu = Mmu::new();
+
+        mmu.write_word(0xFFFE, 0x1F34);
+        assert_eq!(mmu.read_byte(0xFFFE), 0x34);
+        assert_eq!(mmu.interrupt_enable, 0x1F);
+
+        // The high byte lands in rom space, which ignores it with no cartridge loaded
+        mmu.write_word(0xFFFF, 0x0004);
+        assert_eq!(mmu.interrupt_enable, 0x04);
+    }
+
+    #[test]
+    fn oam_dma_takes_160_machine_cycles() {
+        let mut mmu = Mmu::new();
+        for i in 0 .. 0xA0 {
+            mmu.write_byte(0xC000 + i, i as u8 + 1);
+        }
+        mmu.write_byte(0xFF80, 0x42);
+
+        mmu.write_byte(0xFF46, 0xC0);
+        assert_eq!(mmu.read_byte(0xC000), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF80), 0x42);
+        mmu.write_byte(0xC000, 0x00);
+
+        mmu.execute_ticks(159 * 4);
+        assert_eq!(mmu.ppu.read_byte(0xFE9E), 0x9F);
+        assert_eq!(mmu.ppu.read_byte(0xFE9F), 0x00);
+        assert!(mmu.dma.is_oam_transfer_active());
+
+        mmu.execute_ticks(4);
+        assert!(!mmu.dma.is_oam_transfer_active());
+        for i in 0 .. 0xA0 {
+            assert_eq!(mmu.read_byte(0xFE00 + i), i as u8 + 1);
+        }
+        assert_eq!(mmu.read_byte(0xC000), 0x01);
+    }
+
+    #[test]
+    fn dump_range_returns_written_wram() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xC100, 0x12);
+        mmu.write_byte(0xC101, 0x34);
+        mmu.write_byte(0xC103, 0x78);
+
+        assert_eq!(mmu.dump_range(0xC0FF, 6), vec![0x00, 0x12, 0x34, 0x00, 0x78, 0x00]);
+        assert_eq!(mmu.dump_range(0xC100, 0), Vec::<u8>::new());
     }
 
+    #[test]
+    fn vram_and_oam_are_locked_while_the_ppu_uses_them() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xFF40, 0x11);
+        mmu.write_byte(0x8000, 0x12);
+        mmu.write_byte(0xFE00, 0x34);
+
+        // Turning the LCD on starts a line with the OAM search
+        mmu.write_byte(0xFF40, 0x91);
+        assert_eq!(mmu.ppu.mode(), GpuMode::Read);
+        assert_eq!(mmu.read_byte(0x8000), 0x12);
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+
+        mmu.ppu.execute_ticks(80);
+        assert_eq!(mmu.ppu.mode(), GpuMode::Transfer);
+        assert_eq!(mmu.read_byte(0x8000), 0xFF);
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+        mmu.write_byte(0x8000, 0x56);
+        mmu.write_byte(0xFE00, 0x78);
+        assert_eq!(mmu.ppu.read_byte(0x8000), 0x12);
+        assert_eq!(mmu.ppu.read_byte(0xFE00), 0x34);
+
+        mmu.ppu.execute_ticks(172);
+        assert_eq!(mmu.ppu.mode(), GpuMode::HBlank);
+        assert_eq!(mmu.read_byte(0x8000), 0x12);
+        assert_eq!(mmu.read_byte(0xFE00), 0x34);
+    }
+
+    fn create_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        return writer.finish().unwrap().into_inner();
+    }
+
+    fn create_color_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134 .. 0x139].copy_from_slice(b"TETRA");
+        rom[0x143] = 0x80;
+        rom[HEADER_INDEX_FOR_CARTRIDGE_TYPE] = 0x1B;
+        rom[0x149] = 0x02;
+        rom[0x4000] = 0x42;
+        return rom;
+    }
+
+    #[test]
+    fn zipped_rom_loads_like_raw_rom() {
+        let rom = create_color_rom();
+        let zip = create_zip(&[("readme.txt", b"hello"), ("Tetra.GBC", &rom)]);
+
+        let mut raw = Mmu::new();
+        raw.load_cartridge(rom).unwrap();
+        let mut zipped = Mmu::new();
+        zipped.load_cartridge(zip).unwrap();
+
+        assert_eq!(zipped.model, GameboyType::COLOR);
+        assert_eq!(zipped.model, raw.model);
+        assert_eq!(zipped.cartridge.cartridge_type, raw.cartridge.cartridge_type);
+        assert_eq!(zipped.read_byte(0x0143), raw.read_byte(0x0143));
+        assert_eq!(zipped.read_byte(0x4000), 0x42);
+    }
+
+    #[test]
+    fn zip_needs_exactly_one_rom() {
+        let rom = create_color_rom();
+        let mut mmu = Mmu::new();
+
+        assert_eq!(mmu.load_cartridge(create_zip(&[("readme.txt", b"hello")])), Err(LoadError::NoRomInArchive));
+        assert_eq!(mmu.load_cartridge(create_zip(&[("a.gb", &rom), ("b.gbc", &rom)])), Err(LoadError::MultipleRomsInArchive(2)));
+        assert_eq!(mmu.load_cartridge(b"PK\x03\x04 not really a zip".to_vec()), Err(LoadError::InvalidArchive));
+    }
+
+    #[test]
+    fn corrupted_header_checksum_is_reported_but_loads() {
+        let mut rom = include_bytes!("../roms/dmg-acid2.gb").to_vec();
+        rom[0x14D] ^= 0xFF;
+
+        let mut mmu = Mmu::new();
+        assert_eq!(mmu.load_cartridge(rom), Ok(()));
+        assert!(!mmu.cartridge.header().header_checksum_valid);
+        // The header checksum byte is part of the global sum
+        assert!(!mmu.cartridge.header().global_checksum_valid);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut mmu = Mmu::new();
+        mmu.model = GameboyType::COLOR;
+
+        mmu.write_byte(0xE000, 0x12);
+        assert_eq!(mmu.read_byte(0xC000), 0x12);
+        mmu.write_byte(0xCFFF, 0x34);
+        assert_eq!(mmu.read_byte(0xEFFF), 0x34);
+
+        // The banked half follows the bank selected in SVBK, up to 0xFDFF
+        mmu.write_byte(0xFF70, 0x02);
+        mmu.write_byte(0xF000, 0x56);
+        mmu.write_byte(0xDDFF, 0x78);
+        assert_eq!(mmu.read_byte(0xD000), 0x56);
+        assert_eq!(mmu.read_byte(0xFDFF), 0x78);
+
+        mmu.write_byte(0xFF70, 0x03);
+        assert_eq!(mmu.read_byte(0xF000), 0x00);
+        mmu.write_byte(0xF000, 0x9A);
+        assert_eq!(mmu.read_byte(0xD000), 0x9A);
+
+        mmu.write_byte(0xFF70, 0x02);
+        assert_eq!(mmu.read_byte(0xF000), 0x56);
+        assert_eq!(mmu.read_byte(0xC000), 0x12);
+    }
+
+    #[test]
+    fn joypad_writes_carry_sgb_packets() {
+        let mut mmu = Mmu::new();
+        mmu.load_cartridge(vec![0; 0x8000]).unwrap();
+
+        // MASK_EN freezing the screen, the rest of the packet is 0
+        let packet = [0x17 << 3 | 1, 0x01];
+        mmu.write_byte(0xFF00, 0x00);
+        mmu.write_byte(0xFF00, 0x30);
+        for index in 0 .. 16 * 8 + 1 {
+            let one = packet.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0);
+            mmu.write_byte(0xFF00, if one { 0x10 } else { 0x20 });
+            mmu.write_byte(0xFF00, 0x30);
+        }
+
+        let commands = mmu.sgb.take_commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].command, SgbCommand::MaskEn);
+        assert_eq!(commands[0].data[.. 3], [0xB9, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn unused_io_registers_read_0xff() {
+        let mut mmu = Mmu::new();
+        mmu.load_cartridge(vec![0; 0x8000]).unwrap();
+
+        for &address in [0xFF03, 0xFF08, 0xFF0E, 0xFF4C, 0xFF56, 0xFF67, 0xFF7F].iter() {
+            assert_eq!(mmu.read_byte(address), 0xFF, "{:04X}", address);
+        }
+
+        // The unusable area below the IO registers is not open bus, a DMG reads 0x00 there
+        assert_eq!(mmu.read_byte(0xFEA0), 0x00);
+        assert_eq!(mmu.read_byte(0xFEFF), 0x00);
+    }
+
+    #[test]
+    fn cgb_registers_are_open_bus_on_a_dmg() {
+        let mut dmg = Mmu::new();
+        dmg.load_cartridge(vec![0; 0x8000]).unwrap();
+        dmg.write_byte(0xFF70, 0x03);
+        dmg.write_byte(0xFF4F, 0x01);
+        dmg.write_byte(0xFF4D, 0x01);
+
+        for &address in [0xFF4D, 0xFF4F, 0xFF51, 0xFF55, 0xFF68, 0xFF6B, 0xFF70].iter() {
+            assert_eq!(dmg.read_byte(address), 0xFF, "{:04X}", address);
+        }
+        assert_eq!(dmg.wram_bank(), 1);
+        assert!(!dmg.is_speed_switch_armed());
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        let mut cgb = Mmu::new();
+        cgb.load_cartridge(rom).unwrap();
+        cgb.write_byte(0xFF70, 0x03);
+
+        assert_eq!(cgb.read_byte(0xFF4D), 0x00);
+        assert_eq!(cgb.read_byte(0xFF70), 0x03);
+    }
 }
@@ -1,6 +1,9 @@
 use crate::cpu::Cpu;
 use crate::mmu::Mmu;
 
+// Dispatch is a single match the compiler turns into a jump table, there is no table of function
+// pointers to call through. Forcing it inline into Cpu::execute_tick measured no faster in
+// benches/emulation.rs.
 #[allow(unreachable_patterns)]
 pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
     match opcode {
@@ -9,12 +12,12 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x01 => {
-            cpu.set_bc(mmu.read_word(cpu.pc + 1));
+            cpu.set_bc(mmu.cpu_read_word(cpu.pc + 1));
             cpu.pc += 3;
             cpu.cycles += 3;
         }
         0x02 => {
-            mmu.write_byte(cpu.get_bc(), cpu.a);
+            mmu.cpu_write_byte(cpu.get_bc(), cpu.a);
             cpu.pc += 1;
             cpu.cycles += 2;
         }
@@ -34,7 +37,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x06 => {
-            cpu.b = mmu.read_byte(cpu.pc + 1);
+            cpu.b = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.pc += 2;
             cpu.cycles += 2;
         }
@@ -46,8 +49,8 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x08 => {
-            let address = mmu.read_word(cpu.pc + 1);
-            mmu.write_word(address, cpu.sp);
+            let address = mmu.cpu_read_word(cpu.pc + 1);
+            mmu.cpu_write_word(address, cpu.sp);
 
             cpu.pc += 3;
             cpu.cycles += 5;
@@ -60,7 +63,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0x0A => {
-            cpu.a = mmu.read_byte(cpu.get_bc());
+            cpu.a = mmu.cpu_read_byte(cpu.get_bc());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -85,7 +88,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x0E => {
-            cpu.c = mmu.read_byte(cpu.pc + 1);
+            cpu.c = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -98,19 +101,26 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x10 => {
-            mmu.toggle_speed();
+            // On the CGB an armed speed switch is performed instead of stopping
+            if mmu.is_speed_switch_armed() {
+                mmu.toggle_speed();
+                cpu.cycles += 2050;
+            } else {
+                cpu.stopped = true;
+                cpu.cycles += 1;
+            }
+            mmu.timer.write_byte(0xFF04, 0);
 
             cpu.pc += 2;
-            cpu.cycles += 1;
         }
         0x11 => {
-            cpu.set_de(mmu.read_word(cpu.pc + 1));
+            cpu.set_de(mmu.cpu_read_word(cpu.pc + 1));
 
             cpu.pc += 3;
             cpu.cycles += 3;
         }
         0x12 => {
-            mmu.write_byte(cpu.get_de(), cpu.a);
+            mmu.cpu_write_byte(cpu.get_de(), cpu.a);
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -134,7 +144,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x16 => {
-            cpu.d = mmu.read_byte(cpu.pc + 1);
+            cpu.d = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -147,7 +157,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x18 => {
-            let offset = mmu.read_byte(cpu.pc + 1) as i8;
+            let offset = mmu.cpu_read_byte(cpu.pc + 1) as i8;
             cpu.pc = ((cpu.pc as u32 as i32) + (offset as i32)) as u16;
 
             cpu.pc += 2;
@@ -161,7 +171,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0x1A => {
-            cpu.a = mmu.read_byte(cpu.get_de());
+            cpu.a = mmu.cpu_read_byte(cpu.get_de());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -185,7 +195,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x1E => {
-            cpu.e = mmu.read_byte(cpu.pc + 1);
+            cpu.e = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -199,7 +209,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0x20 => {
             if !cpu.get_f_zero() {
-                let offset = mmu.read_byte(cpu.pc + 1) as i8;
+                let offset = mmu.cpu_read_byte(cpu.pc + 1) as i8;
                 cpu.pc = ((cpu.pc as u32 as i32) + (offset as i32)) as u16;
 
                 cpu.pc += 2;
@@ -210,14 +220,14 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0x21 => {
-            let value = mmu.read_word(cpu.pc + 1);
+            let value = mmu.cpu_read_word(cpu.pc + 1);
             cpu.set_hl(value);
 
             cpu.pc += 3;
-            cpu.cycles += 2;
+            cpu.cycles += 3;
         }
         0x22 => {
-            mmu.write_byte(cpu.get_hl(), cpu.a);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.a);
             cpu.set_hl(cpu.get_hl().wrapping_add(1));
 
             cpu.pc += 1;
@@ -243,7 +253,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x26 => {
-            cpu.h = mmu.read_byte(cpu.pc + 1);
+            cpu.h = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -271,7 +281,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0x28 => {
             if cpu.get_f_zero() {
-                let offset = mmu.read_byte(cpu.pc + 1) as i8;
+                let offset = mmu.cpu_read_byte(cpu.pc + 1) as i8;
                 cpu.pc = ((cpu.pc as u32 as i32) + (offset as i32)) as u16;
 
                 cpu.pc += 2;
@@ -289,7 +299,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0x2A => {
-            cpu.a = mmu.read_byte(cpu.get_hl());
+            cpu.a = mmu.cpu_read_byte(cpu.get_hl());
             cpu.set_hl(cpu.get_hl().wrapping_add(1));
 
             cpu.pc += 1;
@@ -314,7 +324,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x2E => {
-            cpu.l = mmu.read_byte(cpu.pc + 1);
+            cpu.l = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -329,7 +339,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0x30 => {
             if !cpu.get_f_carry() {
-                let offset = mmu.read_byte(cpu.pc + 1) as i8;
+                let offset = mmu.cpu_read_byte(cpu.pc + 1) as i8;
                 cpu.pc = ((cpu.pc as u32 as i32) + (offset as i32)) as u16;
 
                 cpu.pc += 2;
@@ -340,13 +350,13 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0x31 => {
-            cpu.sp = mmu.read_word(cpu.pc + 1);
+            cpu.sp = mmu.cpu_read_word(cpu.pc + 1);
 
             cpu.pc += 3;
             cpu.cycles += 3;
         }
         0x32 => {
-            mmu.write_byte(cpu.get_hl(), cpu.a);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.a);
             cpu.set_hl(cpu.get_hl() - 1);
 
             cpu.pc += 1;
@@ -360,25 +370,25 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0x34 => {
             let addr = cpu.get_hl();
-            let mut value = mmu.read_byte(addr);
+            let mut value = mmu.cpu_read_byte(addr);
             value = cpu.apply_inc_u8_with_flags(value);
-            mmu.write_byte(addr, value);
+            mmu.cpu_write_byte(addr, value);
 
             cpu.pc += 1;
             cpu.cycles += 3;
         }
         0x35 => {
             let addr = cpu.get_hl();
-            let mut value = mmu.read_byte(addr);
+            let mut value = mmu.cpu_read_byte(addr);
             value = cpu.apply_dec_u8_with_flags(value);
-            mmu.write_byte(addr, value);
+            mmu.cpu_write_byte(addr, value);
 
             cpu.pc += 1;
             cpu.cycles += 3;
         }
         0x36 => {
-            let value = mmu.read_byte(cpu.pc + 1);
-            mmu.write_byte(cpu.get_hl(), value);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
+            mmu.cpu_write_byte(cpu.get_hl(), value);
 
             cpu.pc += 2;
             cpu.cycles += 3;
@@ -393,7 +403,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0x38 => {
             if cpu.get_f_carry() {
-                let offset = mmu.read_byte(cpu.pc + 1) as i8;
+                let offset = mmu.cpu_read_byte(cpu.pc + 1) as i8;
                 cpu.pc = ((cpu.pc as u32 as i32) + (offset as i32)) as u16;
 
                 cpu.pc += 2;
@@ -411,7 +421,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0x3A => {
-            cpu.a = mmu.read_byte(cpu.get_hl());
+            cpu.a = mmu.cpu_read_byte(cpu.get_hl());
             cpu.set_hl(cpu.get_hl() - 1);
 
             cpu.pc += 1;
@@ -436,7 +446,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x3E => {
-            cpu.a = mmu.read_byte(cpu.pc + 1);
+            cpu.a = mmu.cpu_read_byte(cpu.pc + 1);
 
             cpu.pc += 2;
             cpu.cycles += 2;
@@ -484,7 +494,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x46 => {
-            cpu.b = mmu.read_byte(cpu.get_hl());
+            cpu.b = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -530,7 +540,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x4E => {
-            cpu.c = mmu.read_byte(cpu.get_hl());
+            cpu.c = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -576,7 +586,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x56 => {
-            cpu.d = mmu.read_byte(cpu.get_hl());
+            cpu.d = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -622,7 +632,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x5E => {
-            cpu.e = mmu.read_byte(cpu.get_hl());
+            cpu.e = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -668,7 +678,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x66 => {
-            cpu.h = mmu.read_byte(cpu.get_hl());
+            cpu.h = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -714,7 +724,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x6E => {
-            cpu.l = mmu.read_byte(cpu.get_hl());
+            cpu.l = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -726,49 +736,57 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x70 => {
-            mmu.write_byte(cpu.get_hl(), cpu.b);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.b);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x71 => {
-            mmu.write_byte(cpu.get_hl(), cpu.c);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.c);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x72 => {
-            mmu.write_byte(cpu.get_hl(), cpu.d);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.d);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x73 => {
-            mmu.write_byte(cpu.get_hl(), cpu.e);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.e);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x74 => {
-            mmu.write_byte(cpu.get_hl(), cpu.h);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.h);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x75 => {
-            mmu.write_byte(cpu.get_hl(), cpu.l);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.l);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0x76 => {
-            cpu.halted = true;
+            // https://gbdev.io/pandocs/#halt-bug
+            // With IME clear and an interrupt already pending HALT does not halt, instead
+            // the byte after it is read twice because PC fails to increment.
+            let interrupt_pending = mmu.interrupt_enable & mmu.interrupt_flags & 0x1F != 0;
+            if !cpu.interrupt_master_enable && interrupt_pending {
+                cpu.halt_bug = true;
+            } else {
+                cpu.halted = true;
+            }
 
             cpu.pc += 1;
             cpu.cycles += 1;
         }
         0x77 => {
-            mmu.write_byte(cpu.get_hl(), cpu.a);
+            mmu.cpu_write_byte(cpu.get_hl(), cpu.a);
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -810,7 +828,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x7E => {
-            cpu.a = mmu.read_byte(cpu.get_hl());
+            cpu.a = mmu.cpu_read_byte(cpu.get_hl());
 
             cpu.pc += 1;
             cpu.cycles += 2;
@@ -856,7 +874,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x86 => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_add_u8_with_flags(cpu.a, value, false);
 
             cpu.pc += 1;
@@ -905,7 +923,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0x8E => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_add_u8_with_flags(cpu.a, value, true);
 
             cpu.pc += 1;
@@ -954,7 +972,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x96 => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_sub_u8_with_flags(cpu.a, value, false);
 
             cpu.pc += 1;
@@ -1003,7 +1021,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0x9E => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_sub_u8_with_flags(cpu.a, value, true);
 
             cpu.pc += 1;
@@ -1052,7 +1070,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0xA6 => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_and_u8_with_flags(cpu.a, value);
 
             cpu.pc += 1;
@@ -1101,7 +1119,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0xAE => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_xor_u8_with_flags(cpu.a, value);
 
             cpu.pc += 1;
@@ -1150,7 +1168,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0xB6 => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.a = cpu.apply_or_u8_with_flags(cpu.a, value);
 
             cpu.pc += 1;
@@ -1199,7 +1217,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0xBE => {
-            let value = mmu.read_byte(cpu.get_hl());
+            let value = mmu.cpu_read_byte(cpu.get_hl());
             cpu.apply_sub_u8_with_flags(cpu.a, value, false);
 
             cpu.pc += 1;
@@ -1229,7 +1247,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xC2 => {
             if !cpu.get_f_zero() {
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = mmu.cpu_read_word(cpu.pc + 1);
 
                 cpu.cycles += 4;
             } else {
@@ -1238,13 +1256,14 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0xC3 => {
-            cpu.pc = mmu.read_word(cpu.pc + 1);
+            cpu.pc = mmu.cpu_read_word(cpu.pc + 1);
             cpu.cycles += 4;
         }
         0xC4 => {
             if !cpu.get_f_zero() {
+                let address = mmu.cpu_read_word(cpu.pc + 1);
                 cpu.push_word(mmu, cpu.pc + 3);
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = address;
 
                 cpu.cycles += 6;
             } else {
@@ -1259,7 +1278,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xC6 => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_add_u8_with_flags(cpu.a, value, false);
 
 
@@ -1288,7 +1307,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xCA => {
             if cpu.get_f_zero() {
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = mmu.cpu_read_word(cpu.pc + 1);
 
                 cpu.cycles += 4;
             } else {
@@ -1301,8 +1320,9 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xCC => {
             if cpu.get_f_zero() {
-                cpu.push_word(mmu,cpu.pc + 3);
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                let address = mmu.cpu_read_word(cpu.pc + 1);
+                cpu.push_word(mmu, cpu.pc + 3);
+                cpu.pc = address;
 
                 cpu.cycles += 6;
             } else {
@@ -1311,13 +1331,14 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0xCD => {
+            let address = mmu.cpu_read_word(cpu.pc + 1);
             cpu.push_word(mmu, cpu.pc + 3);
-            cpu.pc = mmu.read_word(cpu.pc + 1);
+            cpu.pc = address;
 
             cpu.cycles += 6;
         }
         0xCE => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_add_u8_with_flags(cpu.a, value, true);
 
             cpu.pc += 2;
@@ -1348,7 +1369,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xD2 => {
             if !cpu.get_f_carry() {
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = mmu.cpu_read_word(cpu.pc + 1);
                 cpu.cycles += 4;
             } else {
                 cpu.pc += 3;
@@ -1356,12 +1377,13 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0xD3 => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xD4 => {
             if !cpu.get_f_carry() {
+                let address = mmu.cpu_read_word(cpu.pc + 1);
                 cpu.push_word(mmu, cpu.pc + 3);
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = address;
                 cpu.cycles += 6;
             } else {
                 cpu.pc += 3;
@@ -1375,7 +1397,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xD6 => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_sub_u8_with_flags(cpu.a, value, false);
 
             cpu.pc += 2;
@@ -1399,13 +1421,13 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xD9 => {
             cpu.pc = cpu.pop_word(mmu);
-            cpu.enable_interrupt_counter = 1;
+            cpu.interrupt_master_enable = true;
 
             cpu.cycles += 4;
         }
         0xDA => {
             if cpu.get_f_carry() {
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = mmu.cpu_read_word(cpu.pc + 1);
 
                 cpu.cycles += 4;
             } else {
@@ -1415,12 +1437,13 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0xDB => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xDC => {
             if cpu.get_f_carry() {
+                let address = mmu.cpu_read_word(cpu.pc + 1);
                 cpu.push_word(mmu, cpu.pc + 3);
-                cpu.pc = mmu.read_word(cpu.pc + 1);
+                cpu.pc = address;
                 cpu.cycles += 6;
             } else {
                 cpu.pc += 3;
@@ -1428,10 +1451,10 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             }
         }
         0xDD => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xDE => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_sub_u8_with_flags(cpu.a, value, true);
 
             cpu.pc += 2;
@@ -1444,10 +1467,10 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xE0 => {
-            let value = mmu.read_byte(cpu.pc + 1) as u16;
+            let value = mmu.cpu_read_byte(cpu.pc + 1) as u16;
             let a = 0xFF00 | value;
 
-            mmu.write_byte(a, cpu.a);
+            mmu.cpu_write_byte(a, cpu.a);
 
             cpu.pc += 2;
             cpu.cycles += 3;
@@ -1461,16 +1484,16 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xE2 => {
             let address = 0xFF00 | (cpu.c as u16);
-            mmu.write_byte(address, cpu.a);
+            mmu.cpu_write_byte(address, cpu.a);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0xE3 => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xE4 => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xE5 => {
             cpu.push_word(mmu, cpu.get_hl());
@@ -1479,7 +1502,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xE6 => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_and_u8_with_flags(cpu.a, value);
 
             cpu.pc += 2;
@@ -1492,7 +1515,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xE8 => {
-            let value = mmu.read_byte(cpu.pc + 1) as i8 as i16;
+            let value = mmu.cpu_read_byte(cpu.pc + 1) as i8 as i16;
             let result = cpu.apply_add_i16_with_flags(cpu.sp as i16, value);
             cpu.sp = result;
 
@@ -1505,23 +1528,23 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 1;
         }
         0xEA => {
-            let a = mmu.read_word(cpu.pc + 1);
-            mmu.write_byte(a, cpu.a);
+            let a = mmu.cpu_read_word(cpu.pc + 1);
+            mmu.cpu_write_byte(a, cpu.a);
 
             cpu.pc += 3;
             cpu.cycles += 4;
         }
         0xEB => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xEC => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xED => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xEE => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_xor_u8_with_flags(cpu.a, value);
 
             cpu.pc += 2;
@@ -1534,14 +1557,14 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xF0 => {
-            let address = 0xFF00 | mmu.read_byte(cpu.pc + 1) as u16;
-            cpu.a = mmu.read_byte(address);
+            let address = 0xFF00 | mmu.cpu_read_byte(cpu.pc + 1) as u16;
+            cpu.a = mmu.cpu_read_byte(address);
 
             cpu.pc += 2;
             cpu.cycles += 3;
         }
         0xF1 => {
-            let value = cpu.pop_word(mmu) & 0xFFF0;
+            let value = cpu.pop_word(mmu);
             cpu.set_af(value);
 
             cpu.pc += 1;
@@ -1549,20 +1572,21 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
         }
         0xF2 => {
             let address = 0xFF00 | cpu.c as u16;
-            cpu.a = mmu.read_byte(address);
+            cpu.a = mmu.cpu_read_byte(address);
 
             cpu.pc += 1;
             cpu.cycles += 2;
         }
         0xF3 => {
-            // Schedules interrupt handling to be enabled after the next machine cycle
-            cpu.disable_interrupt_counter = 2;
+            // Unlike EI this takes effect right away and cancels a pending EI
+            cpu.interrupt_master_enable = false;
+            cpu.enable_interrupts_pending = false;
 
             cpu.pc += 1;
             cpu.cycles += 1;
         }
         0xF4 => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xF5 => {
             cpu.push_word(mmu, cpu.get_af());
@@ -1571,7 +1595,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xF6 => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.a = cpu.apply_or_u8_with_flags(cpu.a, value);
 
             cpu.pc += 2;
@@ -1584,7 +1608,7 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 4;
         }
         0xF8 => {
-            let value = mmu.read_byte(cpu.pc + 1) as i8 as i16;
+            let value = mmu.cpu_read_byte(cpu.pc + 1) as i8 as i16;
             let result = cpu.apply_add_i16_with_flags(cpu.sp as i16, value);
             cpu.set_hl(result);
 
@@ -1598,26 +1622,26 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
             cpu.cycles += 2;
         }
         0xFA => {
-            let address = mmu.read_word(cpu.pc + 1);
-            cpu.a = mmu.read_byte(address);
+            let address = mmu.cpu_read_word(cpu.pc + 1);
+            cpu.a = mmu.cpu_read_byte(address);
 
             cpu.pc += 3;
             cpu.cycles += 4;
         }
         0xFB => {
-            cpu.enable_interrupt_counter = 2;
+            cpu.enable_interrupts_pending = true;
 
             cpu.pc += 1;
             cpu.cycles += 1;
         }
         0xFC => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xFD => {
-            panic!("not implemented");
+            cpu.lock_up();
         }
         0xFE => {
-            let value = mmu.read_byte(cpu.pc + 1);
+            let value = mmu.cpu_read_byte(cpu.pc + 1);
             cpu.apply_sub_u8_with_flags(cpu.a, value, false);
 
             cpu.pc += 2;
@@ -1629,13 +1653,13 @@ pub fn execute_operation(opcode: u8, cpu: &mut Cpu, mmu: &mut Mmu) -> () {
 
             cpu.cycles += 4;
         }
-        _ => { panic!("opcode not found {}", opcode) }
+        _ => { panic!("unimplemented opcode {:02X} at PC {:04X}", opcode, cpu.pc) }
     }
 }
 
 #[allow(unreachable_patterns)]
 pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
-    let opcode = mmu.read_byte(cpu.pc + 1);
+    let opcode = mmu.cpu_read_byte(cpu.pc + 1);
 
     cpu.opcode = (cpu.opcode << 8) as u16 | opcode as u16;
     cpu.pc += 1;
@@ -1672,10 +1696,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x06 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_rotate_left_with_flags(value, true);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1717,10 +1741,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x0E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_rotate_right_with_flags(value, true);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1762,10 +1786,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x16 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_rotate_left_with_flags(value, false);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1807,10 +1831,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x1E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_rotate_right_with_flags(value, false);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1852,10 +1876,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x26 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_shift_left_with_flags(value);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1897,10 +1921,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x2E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_shift_right_with_flags(value, true);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1942,10 +1966,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x36 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_swap_bytes(value);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -1987,10 +2011,10 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x3E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             let result = cpu.apply_shift_right_with_flags(value, false);
-            mmu.write_byte(address, result);
+            mmu.cpu_write_byte(address, result);
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2032,12 +2056,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x46 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 0);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x47 => {
             cpu.apply_bit_test(cpu.a, 0);
@@ -2076,12 +2100,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x4E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 1);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x4F => {
             cpu.apply_bit_test(cpu.a, 1);
@@ -2120,12 +2144,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x56 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 2);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x57 => {
             cpu.apply_bit_test(cpu.a, 2);
@@ -2164,12 +2188,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x5E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 3);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x5F => {
             cpu.apply_bit_test(cpu.a, 3);
@@ -2208,12 +2232,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x66 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 4);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x67 => {
             cpu.apply_bit_test(cpu.a, 4);
@@ -2252,12 +2276,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x6E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 5);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x6F => {
             cpu.apply_bit_test(cpu.a, 5);
@@ -2296,12 +2320,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x76 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 6);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x77 => {
             cpu.apply_bit_test(cpu.a, 6);
@@ -2340,12 +2364,12 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x7E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
             cpu.apply_bit_test(value, 7);
 
             cpu.pc += 1;
-            cpu.cycles += 4;
+            cpu.cycles += 3;
         }
         0x7F => {
             cpu.apply_bit_test(cpu.a, 7);
@@ -2384,9 +2408,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x86 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 0));
+            mmu.cpu_write_byte(address, value & !(1 << 0));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2428,9 +2452,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x8E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 1));
+            mmu.cpu_write_byte(address, value & !(1 << 1));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2472,9 +2496,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x96 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 2));
+            mmu.cpu_write_byte(address, value & !(1 << 2));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2516,9 +2540,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0x9E => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 3));
+            mmu.cpu_write_byte(address, value & !(1 << 3));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2560,9 +2584,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xA6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 4));
+            mmu.cpu_write_byte(address, value & !(1 << 4));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2604,9 +2628,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xAE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 5));
+            mmu.cpu_write_byte(address, value & !(1 << 5));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2648,9 +2672,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xB6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 6));
+            mmu.cpu_write_byte(address, value & !(1 << 6));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2692,9 +2716,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xBE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value & !(1 << 7));
+            mmu.cpu_write_byte(address, value & !(1 << 7));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2736,9 +2760,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xC6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 0));
+            mmu.cpu_write_byte(address, value | (1 << 0));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2780,9 +2804,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xCE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 1));
+            mmu.cpu_write_byte(address, value | (1 << 1));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2824,9 +2848,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xD6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 2));
+            mmu.cpu_write_byte(address, value | (1 << 2));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2868,9 +2892,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xDE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 3));
+            mmu.cpu_write_byte(address, value | (1 << 3));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2912,9 +2936,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xE6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 4));
+            mmu.cpu_write_byte(address, value | (1 << 4));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -2956,9 +2980,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xEE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 5));
+            mmu.cpu_write_byte(address, value | (1 << 5));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -3000,9 +3024,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xF6 => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 6));
+            mmu.cpu_write_byte(address, value | (1 << 6));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -3044,9 +3068,9 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
         }
         0xFE => {
             let address = cpu.get_hl();
-            let value = mmu.read_byte(address);
+            let value = mmu.cpu_read_byte(address);
 
-            mmu.write_byte(address, value | (1 << 7));
+            mmu.cpu_write_byte(address, value | (1 << 7));
 
             cpu.pc += 1;
             cpu.cycles += 4;
@@ -3056,6 +3080,346 @@ pub fn op_cb(cpu: &mut Cpu, mmu: &mut Mmu) {
             cpu.pc += 1;
             cpu.cycles += 2;
         }
-        _ => { panic!("opcode not found {}", opcode) }
+        _ => { panic!("unimplemented opcode CB {:02X} at PC {:04X}", opcode, cpu.pc) }
+    }
+}
+
+// https://gbdev.io/gb-opcodes/optables/
+// Opcodes are decoded from their bit fields xx yyy zzz, with y split into pp q for the 16 bit groups
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const ACCUMULATOR_OPS: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+// Returns the mnemonic of the instruction at pc and its length in bytes
+pub fn disassemble(mmu: &Mmu, pc: u16) -> (String, u8) {
+    let opcode = mmu.read_bus(pc);
+    let d8 = mmu.read_bus(pc.wrapping_add(1));
+    let d16 = mmu.read_bus_word(pc.wrapping_add(1));
+    let r8 = d8 as i8;
+    let jr_target = pc.wrapping_add(2).wrapping_add(r8 as u16);
+
+    let x = (opcode >> 6) as usize;
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    let p = y >> 1;
+    let q = y & 0x01;
+
+    let (text, length) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("NOP".to_string(), 1),
+            1 => (format!("LD (${:04X}),SP", d16), 3),
+            2 => ("STOP".to_string(), 2),
+            3 => (format!("JR ${:04X}", jr_target), 2),
+            _ => (format!("JR {},${:04X}", CC[y - 4], jr_target), 2),
+        },
+        (0, 1) => match q {
+            0 => (format!("LD {},${:04X}", RP[p], d16), 3),
+            _ => (format!("ADD HL,{}", RP[p]), 1),
+        },
+        (0, 2) => {
+            let target = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            match q {
+                0 => (format!("LD {},A", target), 1),
+                _ => (format!("LD A,{}", target), 1),
+            }
+        },
+        (0, 3) => (format!("{} {}", if q == 0 { "INC" } else { "DEC" }, RP[p]), 1),
+        (0, 4) => (format!("INC {}", R[y]), 1),
+        (0, 5) => (format!("DEC {}", R[y]), 1),
+        (0, 6) => (format!("LD {},${:02X}", R[y], d8), 2),
+        (0, _) => (ACCUMULATOR_OPS[y].to_string(), 1),
+        (1, _) => {
+            if y == 6 && z == 6 { ("HALT".to_string(), 1) } else { (format!("LD {},{}", R[y], R[z]), 1) }
+        },
+        (2, _) => (format!("{}{}", ALU[y], R[z]), 1),
+        (3, 0) => match y {
+            0 ..= 3 => (format!("RET {}", CC[y]), 1),
+            4 => (format!("LDH ($FF{:02X}),A", d8), 2),
+            5 => (format!("ADD SP,{}", r8), 2),
+            6 => (format!("LDH A,($FF{:02X})", d8), 2),
+            _ => (format!("LD HL,SP{:+}", r8), 2),
+        },
+        (3, 1) => match (q, p) {
+            (0, _) => (format!("POP {}", RP2[p]), 1),
+            (_, 0) => ("RET".to_string(), 1),
+            (_, 1) => ("RETI".to_string(), 1),
+            (_, 2) => ("JP HL".to_string(), 1),
+            _ => ("LD SP,HL".to_string(), 1),
+        },
+        (3, 2) => match y {
+            0 ..= 3 => (format!("JP {},${:04X}", CC[y], d16), 3),
+            4 => ("LD (C),A".to_string(), 1),
+            5 => (format!("LD (${:04X}),A", d16), 3),
+            6 => ("LD A,(C)".to_string(), 1),
+            _ => (format!("LD A,(${:04X})", d16), 3),
+        },
+        (3, 3) => match y {
+            0 => (format!("JP ${:04X}", d16), 3),
+            1 => (disassemble_cb(d8), 2),
+            6 => ("DI".to_string(), 1),
+            7 => ("EI".to_string(), 1),
+            _ => (format!("DB ${:02X}", opcode), 1),
+        },
+        (3, 4) => match y {
+            0 ..= 3 => (format!("CALL {},${:04X}", CC[y], d16), 3),
+            _ => (format!("DB ${:02X}", opcode), 1),
+        },
+        (3, 5) => match (q, p) {
+            (0, _) => (format!("PUSH {}", RP2[p]), 1),
+            (_, 0) => (format!("CALL ${:04X}", d16), 3),
+            _ => (format!("DB ${:02X}", opcode), 1),
+        },
+        (3, 6) => (format!("{}${:02X}", ALU[y], d8), 2),
+        _ => (format!("RST ${:02X}", y * 8), 1),
+    };
+
+    return (text, length);
+}
+
+fn disassemble_cb(opcode: u8) -> String {
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+
+    return match opcode >> 6 {
+        0 => format!("{} {}", ROT[y], R[z]),
+        1 => format!("BIT {},{}", y, R[z]),
+        2 => format!("RES {},{}", y, R[z]),
+        _ => format!("SET {},{}", y, R[z]),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::GameboyType;
+    use crate::joypad::Button;
+    use crate::mmu::Speed;
+    use crate::ppu::GpuMode;
+
+    fn execute_daa(a: u8, negative: bool, half_carry: bool, carry: bool) -> Cpu {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.a = a;
+        cpu.set_f_negative(negative);
+        cpu.set_f_half_carry(half_carry);
+        cpu.set_f_carry(carry);
+
+        execute_operation(0x27, &mut cpu, &mut mmu);
+        return cpu;
+    }
+
+    #[test]
+    fn daa_adjusts_after_addition() {
+        // (A, H, C) -> (A, Z, C)
+        let cases = [
+            (0x0A, false, false, 0x10, false, false),
+            (0x7D, false, false, 0x83, false, false),
+            (0x00, true, false, 0x06, false, false),
+            (0x9A, false, false, 0x00, true, true),
+            (0xA0, false, false, 0x00, true, true),
+            (0x12, false, true, 0x72, false, true),
+        ];
+
+        for &(a, h, c, expected, zero, carry) in cases.iter() {
+            let cpu = execute_daa(a, false, h, c);
+            assert_eq!(cpu.a, expected, "A={:02X} H={} C={}", a, h, c);
+            assert_eq!(cpu.get_f_zero(), zero);
+            assert_eq!(cpu.get_f_carry(), carry);
+            assert!(!cpu.get_f_half_carry());
+            assert!(!cpu.get_f_substract());
+        }
+    }
+
+    // Runs ADD SP,r8 or LD HL,SP+r8 with every flag set beforehand
+    fn execute_sp_offset(opcode: u8, sp: u16, offset: i8) -> Cpu {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = sp;
+        cpu.f = 0xF0;
+        mmu.write_byte(0xC000, opcode);
+        mmu.write_byte(0xC001, offset as u8);
+
+        execute_operation(opcode, &mut cpu, &mut mmu);
+        return cpu;
+    }
+
+    #[test]
+    fn sp_offset_flags_come_from_the_low_byte() {
+        // (SP, r8) -> (result, H, C)
+        let cases = [
+            (0x0008, 0x08, 0x0010, true, false),
+            (0x00F8, 0x08, 0x0100, true, true),
+            (0x1000, 0x01, 0x1001, false, false),
+            (0xFFFF, 0x01, 0x0000, true, true),
+            (0x0000, -0x01, 0xFFFF, false, false),
+            (0x0001, -0x01, 0x0000, true, true),
+            (0x1234, -0x80, 0x11B4, false, false),
+            (0xD00F, -0x02, 0xD00D, true, true),
+        ];
+
+        for &(sp, offset, expected, half_carry, carry) in cases.iter() {
+            let add = execute_sp_offset(0xE8, sp, offset);
+            let load = execute_sp_offset(0xF8, sp, offset);
+            assert_eq!(add.sp, expected, "ADD SP,{} with SP={:04X}", offset, sp);
+            assert_eq!((load.get_hl(), load.sp), (expected, sp), "LD HL,SP{:+} with SP={:04X}", offset, sp);
+
+            for cpu in [add, load].iter() {
+                assert_eq!(cpu.get_f_half_carry(), half_carry, "SP={:04X} r8={}", sp, offset);
+                assert_eq!(cpu.get_f_carry(), carry, "SP={:04X} r8={}", sp, offset);
+                assert!(!cpu.get_f_zero());
+                assert!(!cpu.get_f_substract());
+            }
+        }
+    }
+
+    #[test]
+    fn daa_adjusts_after_subtraction() {
+        // (A, H, C) -> (A, Z, C)
+        let cases = [
+            (0x29, true, false, 0x23, false, false),
+            (0xF0, false, true, 0x90, false, true),
+            (0xEA, true, true, 0x84, false, true),
+            (0x00, false, false, 0x00, true, false),
+            (0x0F, false, false, 0x0F, false, false),
+        ];
+
+        for &(a, h, c, expected, zero, carry) in cases.iter() {
+            let cpu = execute_daa(a, true, h, c);
+            assert_eq!(cpu.a, expected, "A={:02X} H={} C={}", a, h, c);
+            assert_eq!(cpu.get_f_zero(), zero);
+            assert_eq!(cpu.get_f_carry(), carry);
+            assert!(!cpu.get_f_half_carry());
+            assert!(cpu.get_f_substract());
+        }
+    }
+
+    fn execute_sbc_d8(a: u8, value: u8, carry: bool) -> Cpu {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.a = a;
+        cpu.pc = 0xC000;
+        cpu.set_f_carry(carry);
+        mmu.write_byte(0xC000, 0xDE);
+        mmu.write_byte(0xC001, value);
+
+        execute_operation(0xDE, &mut cpu, &mut mmu);
+        return cpu;
+    }
+
+    #[test]
+    fn sbc_d8_borrow_boundaries() {
+        // (A, d8, C) -> (A, Z, H, C)
+        let cases = [
+            (0x10, 0x0F, true, 0x00, true, true, false),
+            (0x1F, 0x0F, true, 0x0F, false, true, false),
+            (0x00, 0x00, true, 0xFF, false, true, true),
+            (0x00, 0xFF, true, 0x00, true, true, true),
+            (0xFF, 0xFF, true, 0xFF, false, true, true),
+            (0x3B, 0x2A, true, 0x10, false, false, false),
+            (0x3B, 0x4F, false, 0xEC, false, true, true),
+            (0x0F, 0x0E, true, 0x00, true, false, false),
+        ];
+
+        for &(a, value, c, expected, zero, half_carry, carry) in cases.iter() {
+            let cpu = execute_sbc_d8(a, value, c);
+            assert_eq!(cpu.a, expected, "A={:02X} d8={:02X} C={}", a, value, c);
+            assert_eq!(cpu.get_f_zero(), zero, "Z for A={:02X} d8={:02X} C={}", a, value, c);
+            assert_eq!(cpu.get_f_half_carry(), half_carry, "H for A={:02X} d8={:02X} C={}", a, value, c);
+            assert_eq!(cpu.get_f_carry(), carry, "C for A={:02X} d8={:02X} C={}", a, value, c);
+            assert!(cpu.get_f_substract());
+        }
+    }
+
+    #[test]
+    fn stop_performs_armed_speed_switch() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        mmu.model = GameboyType::COLOR;
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xC000, 0x10);
+        mmu.write_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x01);
+
+        execute_operation(0x10, &mut cpu, &mut mmu);
+
+        assert_eq!(mmu.speed, Speed::FAST);
+        assert_eq!(mmu.read_byte(0xFF4D), 0x80);
+        assert_eq!(cpu.cycles, 2050);
+        assert_eq!(cpu.pc, 0xC002);
+        assert!(!cpu.stopped);
+    }
+
+    #[test]
+    fn stop_without_speed_switch_waits_for_button() {
+        let mut cpu = Cpu::new();
+        let mut mmu = Mmu::new();
+        cpu.pc = 0xC000;
+        mmu.write_byte(0xC000, 0x10);
+        mmu.write_byte(0xC002, 0x00);
+        mmu.write_byte(0xFF4D, 0x01);
+
+        cpu.execute_tick(&mut mmu);
+        assert!(cpu.stopped);
+        assert_eq!(mmu.speed, Speed::SLOW);
+
+        cpu.execute_tick(&mut mmu);
+        assert_eq!(cpu.pc, 0xC002);
+
+        mmu.joypad.press(Button::START);
+        cpu.execute_tick(&mut mmu);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, 0xC003);
+    }
+
+    fn disassemble_bytes(bytes: &[u8]) -> (String, u8) {
+        let mut mmu = Mmu::new();
+        for (i, byte) in bytes.iter().enumerate() {
+            mmu.write_byte(0xC000 + i as u16, *byte);
+        }
+        return disassemble(&mmu, 0xC000);
+    }
+
+    #[test]
+    fn disassemble_instructions() {
+        assert_eq!(disassemble_bytes(&[0x00]), ("NOP".to_string(), 1));
+        assert_eq!(disassemble_bytes(&[0x2A]), ("LD A,(HL+)".to_string(), 1));
+        assert_eq!(disassemble_bytes(&[0x01, 0x34, 0x12]), ("LD BC,$1234".to_string(), 3));
+        assert_eq!(disassemble_bytes(&[0xCD, 0x00, 0x40]), ("CALL $4000".to_string(), 3));
+        assert_eq!(disassemble_bytes(&[0x3E, 0x7F]), ("LD A,$7F".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0x20, 0xFE]), ("JR NZ,$C000".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0xE0, 0x44]), ("LDH ($FF44),A".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0xF8, 0xFE]), ("LD HL,SP-2".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0x76]), ("HALT".to_string(), 1));
+        assert_eq!(disassemble_bytes(&[0xAE]), ("XOR (HL)".to_string(), 1));
+        assert_eq!(disassemble_bytes(&[0xFF]), ("RST $38".to_string(), 1));
+        assert_eq!(disassemble_bytes(&[0xD3]), ("DB $D3".to_string(), 1));
+    }
+
+    #[test]
+    fn disassemble_vram_while_the_ppu_locks_it() {
+        let mut mmu = Mmu::new();
+        mmu.write_byte(0xFF40, 0x11);
+        for (i, byte) in [0x01, 0x34, 0x12].iter().enumerate() {
+            mmu.write_byte(0x8000 + i as u16, *byte);
+        }
+        mmu.write_byte(0xFF40, 0x91);
+        mmu.ppu.execute_ticks(80);
+        assert_eq!(mmu.ppu.mode(), GpuMode::Transfer);
+        assert_eq!(mmu.read_byte(0x8000), 0xFF);
+
+        assert_eq!(disassemble(&mmu, 0x8000), ("LD BC,$1234".to_string(), 3));
+    }
+
+    #[test]
+    fn disassemble_cb_instructions() {
+        assert_eq!(disassemble_bytes(&[0xCB, 0x7C]), ("BIT 7,H".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0xCB, 0x37]), ("SWAP A".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0xCB, 0x86]), ("RES 0,(HL)".to_string(), 2));
+        assert_eq!(disassemble_bytes(&[0xCB, 0xFF]), ("SET 7,A".to_string(), 2));
     }
 }
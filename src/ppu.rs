@@ -1,17 +1,37 @@
 use crate::console::GameboyType;
 use crate::logger::log;
-use crate::mmu::Mmu;
+use crate::mmu::{Mmu, MemoryFiller};
+use crate::font::{glyph, GLYPH_W, GLYPH_H};
 use wasm_bindgen::prelude::*;
 use web_sys::CanvasRenderingContext2d;
 use js_sys::*;
+use serde_derive::{Serialize, Deserialize};
+use serde_big_array::BigArray;
+use std::hash::Hasher;
 
 pub const VRAM_SIZE: usize = 0x4000;
 pub const VOAM_SIZE: usize = 0xA0;
 pub const SCREEN_W: usize = 160;
 pub const SCREEN_H: usize = 144;
-pub const INTERRUPT_TIMER_MASK: u8 = 0x02;
+pub const DOTS_PER_LINE: u32 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+const OAM_SEARCH_DOTS: u32 = 80;
+const PIXEL_TRANSFER_DOTS: u32 = 172;
+const LINE_153_LY_RESET_DOTS: u32 = 4;
+const MAX_SPRITES_PER_LINE: usize = 10;
+// Debug views: 384 tiles 16 per row, and a full 32x32 tile map
+pub const TILE_DATA_W: usize = 128;
+pub const TILE_DATA_H: usize = 192;
+pub const TILE_MAP_SIZE: usize = 256;
+
+// Colors for the four DMG shades, lightest first, as 0xRRGGBB
+pub const DMG_PALETTE_GREEN: [u32; 4] = [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F];
+pub const INTERRUPT_LCD_STAT_MASK: u8 = 0x02;
 pub const INTERRUPT_V_BLANK_MASK: u8 = 0x01;
 
+// Gets the frame buffer at the start of every V-Blank, see Ppu::set_vblank_callback
+pub type VBlankCallback = Box<dyn FnMut(&[u32])>;
+
 #[derive(PartialEq, Copy, Clone)]
 enum PaletteType {
     BACKGROUND,
@@ -43,7 +63,17 @@ pub struct SpriteOam {
     pal_palette_index: u8,
 }
 
+// Raw attributes of one of the 40 OAM entries, as seen by a debugger
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpriteEntry {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 enum PriorityType {
     None,
     BgColor0,
@@ -51,7 +81,7 @@ enum PriorityType {
 }
 
 // https://gbdev.io/pandocs/#ff41-stat-lcdc-status-r-w
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GpuMode {
     Read = 2,
     Transfer = 3,
@@ -59,7 +89,44 @@ pub enum GpuMode {
     VBlank = 1,
 }
 
+// The CGB screen does not show the 5 bit channels linearly, mixing them like higan does
+// gets rid of the oversaturated look of a plain `value << 3`.
+fn cgb_color_to_rgb(color: u16) -> [u8; 3] {
+    let r = (color & 0x1F) as u32;
+    let g = ((color >> 5) & 0x1F) as u32;
+    let b = ((color >> 10) & 0x1F) as u32;
+
+    let correct = |value: u32| (value.min(960) * 255 / 960) as u8;
+
+    return [
+        correct(r * 26 + g * 4 + b * 2),
+        correct(g * 24 + b * 8),
+        correct(r * 6 + g * 4 + b * 22),
+    ];
+}
+
+fn dmg_color_to_rgb(color: u32) -> [u8; 3] {
+    return [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+}
+
+fn default_dmg_palette() -> [u32; 4] {
+    return DMG_PALETTE_GREEN;
+}
+
+fn empty_scanline_priority() -> [PriorityType; SCREEN_W] {
+    return [PriorityType::None; SCREEN_W];
+}
+
+fn empty_frame() -> Vec<u8> {
+    return vec![0; SCREEN_W * SCREEN_H * 4];
+}
+
+fn empty_frame_buffer() -> Vec<u32> {
+    return vec![0; SCREEN_W * SCREEN_H];
+}
+
 #[wasm_bindgen]
+#[derive(Serialize, Deserialize)]
 pub struct Ppu {
     // 0xFF40 (http://bgb.bircd.org/pandocs.htm#videodisplay)
     lcd_display_enable: bool,
@@ -76,13 +143,14 @@ pub struct Ppu {
     mode_2_interrupt: bool,
     mode_1_interrupt: bool,
     mode_0_interrupt: bool,
+    // Combined state of the enabled STAT sources, the interrupt is only requested on a rising edge
+    stat_line: bool,
 
     // http://bgb.bircd.org/pandocs.htm#lcdpositionandscrolling
     scroll_y_coord: u8,
     scroll_x_coord: u8,
     window_y_coord: u8,
     window_x_coord: u8,
-    lcd_y_coordinate: u8,
     lyc: u8,
 
     // http://bgb.bircd.org/pandocs.htm#lcdmonochromepalettes
@@ -90,21 +158,30 @@ pub struct Ppu {
     pal_obj_palette_0_data: u8,
     pal_obj_palette_1_data: u8,
 
-    pal_bg_palette: [u8; 4],
-    pal_obj_palette_0: [u8; 4],
-    pal_obj_palette_1: [u8; 4],
+    pal_bg_palette: [[u8; 3]; 4],
+    pal_obj_palette_0: [[u8; 3]; 4],
+    pal_obj_palette_1: [[u8; 3]; 4],
+    // A front-end setting rather than console state, Mmu::load_state carries it over
+    #[serde(skip, default = "default_dmg_palette")]
+    dmg_palette: [u32; 4],
 
     // http://bgb.bircd.org/pandocs.htm#lcdcolorpalettescgbonly
     cbg_bg_palette_index: u8,
     cbg_bg_palette_increment: bool,
+    #[serde(with = "BigArray")]
+    cbg_bg_palette_ram: [u8; 64],
     cbg_bg_palette: [[[u8; 3]; 4]; 8],
 
     cbg_obj_index: u8,
     cbg_obj_increment: bool,
+    #[serde(with = "BigArray")]
+    cbg_obj_ram: [u8; 64],
     cbg_obj: [[[u8; 3]; 4]; 8],
 
     vram_bank: usize,
+    #[serde(with = "BigArray")]
     vram: [u8; VRAM_SIZE],
+    #[serde(with = "BigArray")]
     voam: [u8; VOAM_SIZE],
 
     // https://gbdev.io/pandocs/#ff0f-if-interrupt-flag-r-w
@@ -112,6 +189,7 @@ pub struct Ppu {
     pub h_blank: bool,
     pub v_blank: bool,
 
+    #[serde(skip, default = "empty_scanline_priority")]
     scanline_priority: [PriorityType; SCREEN_W],
     obj_master_priority: bool,
 
@@ -120,8 +198,25 @@ pub struct Ppu {
     ly: u8,
     wly: u32,
     model: GameboyType,
-    frame: [u8; SCREEN_W * SCREEN_H * 4],
-    buffer: [u8; SCREEN_W * SCREEN_H * 4],
+    // The rendered frames are not part of save states
+    #[serde(skip, default = "empty_frame")]
+    frame: Vec<u8>,
+    #[serde(skip, default = "empty_frame")]
+    buffer: Vec<u8>,
+    #[serde(skip, default = "empty_frame_buffer")]
+    frame_buffer: Vec<u32>,
+    frame_ready: bool,
+    // Called with LY whenever a line ends, not part of save states
+    #[serde(skip)]
+    scanline_callback: Option<Box<dyn FnMut(u8)>>,
+    // Called with the frame buffer whenever V-Blank starts, not part of save states
+    #[serde(skip)]
+    vblank_callback: Option<VBlankCallback>,
+    // Frames left undrawn after each drawn one, a front-end setting like dmg_palette
+    #[serde(skip)]
+    frame_skip: u32,
+    #[serde(skip)]
+    frame_skip_phase: u32, // Counts the frames since the last drawn one, 0 draws
 }
 
 #[wasm_bindgen]
@@ -129,7 +224,7 @@ pub struct Ppu {
 impl Ppu {
 
     pub fn new() -> Self {
-        return Ppu {
+        let mut ppu = Ppu {
             lcd_display_enable: true,
             window_tile_map_select: 0x9800,
             window_display_enable: false,
@@ -143,28 +238,31 @@ impl Ppu {
             mode_2_interrupt: false,
             mode_1_interrupt: false,
             mode_0_interrupt: false,
+            stat_line: false,
 
             scroll_y_coord: 0,
             scroll_x_coord: 0,
             window_y_coord: 0,
             window_x_coord: 0,
-            lcd_y_coordinate: 0,
             lyc: 0,
 
             pal_bg_palette_data: 0xFC,
             pal_obj_palette_0_data: 0xFF,
             pal_obj_palette_1_data: 0xFF,
 
-            pal_bg_palette: [0; 4],
-            pal_obj_palette_0: [0; 4],
-            pal_obj_palette_1: [0; 4],
+            pal_bg_palette: [[0; 3]; 4],
+            pal_obj_palette_0: [[0; 3]; 4],
+            pal_obj_palette_1: [[0; 3]; 4],
+            dmg_palette: default_dmg_palette(),
 
             cbg_bg_palette_index: 0,
             cbg_bg_palette_increment: false,
+            cbg_bg_palette_ram: [0; 64],
             cbg_bg_palette: [[[0u8; 3]; 4]; 8],
 
             cbg_obj_index: 0,
             cbg_obj_increment: false,
+            cbg_obj_ram: [0; 64],
             cbg_obj: [[[0u8; 3]; 4]; 8],
 
             vram_bank: 0,
@@ -184,9 +282,17 @@ impl Ppu {
             wly: 0,
             model: GameboyType::CLASSIC,
 
-            frame: [0; SCREEN_W * SCREEN_H * 4],
-            buffer: [0; SCREEN_W * SCREEN_H * 4],
+            frame: empty_frame(),
+            buffer: empty_frame(),
+            frame_buffer: empty_frame_buffer(),
+            frame_ready: false,
+            scanline_callback: None,
+            vblank_callback: None,
+            frame_skip: 0,
+            frame_skip_phase: 0,
         };
+        ppu.update_pal_palettes();
+        return ppu;
     }
 
     pub fn reset(&mut self, model: GameboyType) {
@@ -197,6 +303,9 @@ impl Ppu {
         self.mode = GpuMode::Read;
         self.model = model;
         self.ly = 0;
+        self.wly = 0;
+        self.stat_line = false;
+        self.vram_bank = 0;
     }
 
     pub fn execute_ticks(&mut self, ticks: u32) -> () {
@@ -207,68 +316,112 @@ impl Ppu {
 
     #[allow(unused)]
     pub fn execute_tick(&mut self) -> () {
-        // TODO LOOK OVER THIS
-        // if !self.lcd_display_enable {
-        //     return;
-        // }
+        // https://gbdev.io/pandocs/#lcdc7---lcd-display-enable
+        // The PPU is stopped while the LCD is off
+        if !self.lcd_display_enable {
+            return;
+        }
 
+        // clock counts the dots of the current line
         self.clock += 1;
 
-        // http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-GPU-Timings
         // https://gbdev.io/pandocs/#pixel-fifo
+        // Visible lines go through mode 2, 3 and 0; lines 144 - 153 stay in mode 1
         match self.mode {
             GpuMode::Read => {
-                if self.clock >= 80 {
+                if self.clock == OAM_SEARCH_DOTS {
                     self.set_mode(GpuMode::Transfer);
-                    self.clock = 0;
                 }
             },
             GpuMode::Transfer => {
-                if self.clock >= 172 {
+                if self.clock == OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS {
                     self.set_mode(GpuMode::HBlank);
-                    self.clock = 0;
-
-                    // Render scanline here
-                    self.render_scan_line();
+                    if self.frame_skip_phase == 0 { self.render_scan_line(); } else { self.skip_scan_line(); }
                 }
             },
-            GpuMode::HBlank => {
-                if self.clock >= 204 {
+            GpuMode::HBlank | GpuMode::VBlank => {
+                // LY already reads 0 for most of line 153
+                if self.ly == LINES_PER_FRAME - 1 && self.clock == LINE_153_LY_RESET_DOTS {
+                    self.update_stat_interrupt();
+                }
+
+                if self.clock >= DOTS_PER_LINE {
+                    if let Some(callback) = &mut self.scanline_callback {
+                        callback(self.ly);
+                    }
                     self.clock = 0;
-                    self.ly += 1;
+                    self.next_line();
+                }
+            },
+        };
+    }
 
-                    // Check interrupt here
-                    self.update_interrupt_for_lyc();
+    fn next_line(&mut self) {
+        self.ly += 1;
 
-                    if self.ly >= 143 {
-                        self.set_mode(GpuMode::VBlank);
-                        self.interrupt_flags |= INTERRUPT_V_BLANK_MASK;
+        if self.ly == SCREEN_H as u8 {
+            self.set_mode(GpuMode::VBlank);
+            self.interrupt_flags |= INTERRUPT_V_BLANK_MASK;
+            self.frame_ready = true;
 
-                        self.render_frame();
-                    } else {
-                        self.set_mode(GpuMode::Read);
-                    }
+            if self.frame_skip_phase == 0 { self.render_frame(); }
+            if let Some(callback) = &mut self.vblank_callback {
+                callback(&self.frame_buffer);
+            }
+        } else if self.ly >= LINES_PER_FRAME {
+            self.frame_skip_phase = (self.frame_skip_phase + 1) % (self.frame_skip + 1);
+            self.ly = 0;
+            self.wly = 0;
+            self.obj_master_priority = false;
+            self.set_mode(GpuMode::Read);
+        } else if self.mode == GpuMode::VBlank {
+            self.update_stat_interrupt();
+        } else {
+            self.set_mode(GpuMode::Read);
+        }
+    }
 
-                }
-            },
-            GpuMode::VBlank => {
-                if self.clock >= 456 {
-                    self.clock = 0;
-                    self.ly += 1;
+    // Turning the LCD off leaves LY at 0 in mode 0 and blanks the screen, turning it
+    // back on starts a new frame from OAM search
+    fn switch_lcd(&mut self) {
+        self.ly = 0;
+        self.wly = 0;
+        self.clock = 0;
 
-                    if self.ly > 153 {
-                        self.ly = 0;
-                        self.wly = 0;
-                        self.obj_master_priority = false;
-                        self.set_mode(GpuMode::Read);
-                    }
-                }
-            },
-            _ => {
-                panic!("error");
+        if self.lcd_display_enable {
+            self.set_mode(GpuMode::Read);
+        } else {
+            self.mode = GpuMode::HBlank;
+            self.h_blank = false;
+            self.v_blank = false;
+            self.update_stat_interrupt();
+            self.blank_screen();
+        }
+    }
+
+    fn blank_screen(&mut self) {
+        self.fill_buffer(self.lightest_color());
+        self.render_frame();
+    }
+
+    fn lightest_color(&self) -> [u8; 3] {
+        return if self.model == GameboyType::COLOR { [255, 255, 255] } else { dmg_color_to_rgb(self.dmg_palette[0]) };
+    }
+
+    fn fill_buffer(&mut self, [r, g, b]: [u8; 3]) {
+        for y in 0 .. SCREEN_H {
+            for x in 0 .. SCREEN_W {
+                self.set_rgb_at(x, y, r, g, b);
             }
-        };
+        }
+    }
 
+    // https://gbdev.io/pandocs/#ff44-ly-lcd-y-coordinate-r
+    fn current_ly(&self) -> u8 {
+        if self.ly == LINES_PER_FRAME - 1 && self.clock >= LINE_153_LY_RESET_DOTS {
+            return 0;
+        }
+        return self.ly;
     }
 
     pub fn get_frame(&self) -> js_sys::Array {
@@ -280,24 +433,42 @@ impl Ppu {
     }
 
     fn render_frame(&mut self) {
-        self.frame = self.buffer.clone();
+        self.frame.copy_from_slice(&self.buffer);
+
+        for (pixel, rgba) in self.frame_buffer.iter_mut().zip(self.buffer.chunks_exact(4)) {
+            *pixel = (rgba[0] as u32) << 16 | (rgba[1] as u32) << 8 | rgba[2] as u32;
+        }
     }
 
     fn render_scan_line(&mut self) {
+        // With the background off a DMG shows its lightest shade
+        let [r, g, b] = if self.model == GameboyType::COLOR { [255, 255, 255] } else { dmg_color_to_rgb(self.dmg_palette[0]) };
         for x in 0 .. SCREEN_W {
-            self.set_rgb_at(x, self.ly as usize, 255, 255, 255);
-            self.scanline_priority[x] = PriorityType::None;
+            self.set_rgb_at(x, self.ly as usize, r, g, b);
+            self.scanline_priority[x] = PriorityType::BgColor0;
         }
 
         self.render_bg_line();
         self.render_sprite_line();
     }
 
+    // Only the window line counter moves on when a line is not drawn, exactly when
+    // render_bg_line would have drawn the window on it
+    fn skip_scan_line(&mut self) {
+        let draw_background = self.model == GameboyType::COLOR || self.bg_display_enable;
+        let draw_window = self.window_display_enable && draw_background;
+        if draw_window && self.ly >= self.window_y_coord && self.window_x_coord <= (SCREEN_W + 6) as u8 {
+            self.wly += 1;
+        }
+    }
+
     fn render_bg_line(&mut self) {
         let mut window_has_rendered = false;
 
-        let draw_window = self.window_display_enable && self.model == GameboyType::COLOR;
+        // On the DMG LCDC bit 0 blanks the window together with the background,
+        // on the CGB it only takes away their priority over sprites
         let draw_background = self.model == GameboyType::COLOR || self.bg_display_enable;
+        let draw_window = self.window_display_enable && draw_background;
 
         if !draw_window && !draw_background { return; }
 
@@ -367,9 +538,9 @@ impl Ppu {
 
                 self.set_rgb_at(display_x as usize, self.ly as usize, r, g, b);
             } else {
-                let r = self.pal_bg_palette[palette_index];
-                let g = self.pal_bg_palette[palette_index];
-                let b = self.pal_bg_palette[palette_index];
+                let r = self.pal_bg_palette[palette_index][0];
+                let g = self.pal_bg_palette[palette_index][1];
+                let b = self.pal_bg_palette[palette_index][2];
 
                 self.set_rgb_at(display_x as usize, self.ly as usize, r, g, b);
             }
@@ -471,35 +642,36 @@ impl Ppu {
             return;
         }
 
-        let mut sprite_counter = 0;
-
         let display_y = self.ly as i32;
         let sprite_size = self.sprite_size as i32;
 
-        // https://gbdev.io/pandocs/#fifo-pixel-fetcher
-        // http://imrannazar.com/GameBoy-Emulation-in-JavaScript:-Sprites
-
+        // https://gbdev.io/pandocs/#oam
+        // The first 10 sprites in OAM order that overlap the line are selected, the X coordinate
+        // does not matter so sprites hidden off screen still count towards the limit.
+        let mut sprites: Vec<SpriteOam> = Vec::with_capacity(MAX_SPRITES_PER_LINE);
         for index in 0 .. 40 {
-
-            // If we reach max sprite per line exit.
-            if sprite_counter >= 10 {
-                break;
-            }
-
-            let mut is_rendered = false;
             let sprite_oam: SpriteOam = self.get_sprite_attributes(index);
 
-            // is y out of bounds
             if display_y < sprite_oam.y_cord || display_y >= sprite_oam.y_cord + sprite_size { continue }
 
-            // is x out of bounds
-            if sprite_oam.x_cord < (-7) || sprite_oam.x_cord >= SCREEN_W as i32 { continue }
+            sprites.push(sprite_oam);
+            if sprites.len() >= MAX_SPRITES_PER_LINE { break; }
+        }
 
-            let sprite_tile = self.get_sprite_tile_at_y(&sprite_oam, display_y);
+        // https://gbdev.io/pandocs/#drawing-priority
+        // On the DMG the smaller X coordinate wins and the OAM index breaks ties,
+        // the CGB only looks at the OAM index. The sort is stable so ties keep OAM order.
+        if self.model == GameboyType::CLASSIC {
+            sprites.sort_by_key(|sprite_oam| sprite_oam.x_cord);
+        }
 
-            for display_x in 0 .. 8i32 {
-                is_rendered = true;
+        // The opaque pixel of the highest priority sprite is kept even when the background then hides it
+        let mut is_claimed = [false; SCREEN_W];
 
+        for sprite_oam in sprites.iter() {
+            let sprite_tile = self.get_sprite_tile_at_y(sprite_oam, display_y);
+
+            for display_x in 0 .. 8i32 {
                 let sprite_x_cord = sprite_oam.x_cord + display_x;
                 let sprite_y_cord = display_y;
 
@@ -512,9 +684,10 @@ impl Ppu {
                     (if sprite_tile.tile_1 & bit_mask != 0 {1} else {0}) |
                     (if sprite_tile.tile_2 & bit_mask != 0 {2} else {0});
 
-                if palette_index == 0 {
+                if palette_index == 0 || is_claimed[sprite_x_cord as usize] {
                     continue
                 }
+                is_claimed[sprite_x_cord as usize] = true;
 
                 let priority = self.scanline_priority[sprite_x_cord as usize];
                 if !self.should_sprite_render(priority, sprite_oam.has_priority) {
@@ -532,16 +705,12 @@ impl Ppu {
                 } else {
                     let palette = if sprite_oam.pal_palette_index == 1 { self.pal_obj_palette_1 } else { self.pal_obj_palette_0 };
 
-                    let r = palette[palette_index];
-                    let g = palette[palette_index];
-                    let b = palette[palette_index];
+                    let r = palette[palette_index][0];
+                    let g = palette[palette_index][1];
+                    let b = palette[palette_index][2];
 
                     self.set_rgb_at(sprite_x_cord as usize, sprite_y_cord as usize, r, g, b);
                 }
-
-            }
-            if is_rendered {
-                sprite_counter += 1;
             }
         }
     }
@@ -648,6 +817,21 @@ impl Ppu {
         return self.vram[(bank as usize) * 0x2000 + (address as usize & 0x1FFF)]
     }
 
+    fn draw_tile(&self, image: &mut [u32], width: usize, x: usize, y: usize, tile_address: u16) {
+        for row in 0 .. 8 {
+            let tile_1 = self.read_byte_from_vram(0, tile_address + row as u16 * 2);
+            let tile_2 = self.read_byte_from_vram(0, tile_address + row as u16 * 2 + 1);
+
+            for column in 0 .. 8 {
+                let bit = 7 - column;
+                let palette_index = ((tile_1 >> bit) & 1) | ((tile_2 >> bit) & 1) << 1;
+                let [r, g, b] = self.pal_bg_palette[palette_index as usize];
+
+                image[(y + row) * width + x + column] = 0xFF000000 | (r as u32) << 16 | (g as u32) << 8 | b as u32;
+            }
+        }
+    }
+
     fn set_rgb_at(&mut self, x: usize, y: usize, red: u8, green: u8, blue: u8) {
         let base = (y as usize * SCREEN_W * 4) + (x * 4);
 
@@ -678,27 +862,22 @@ impl Ppu {
             }
         }
 
-        self.update_interrupt_for_mode();
-    }
-
-    fn update_interrupt_for_mode(&mut self) {
-        if self.mode == GpuMode::Read && self.mode_2_interrupt {
-            self.interrupt_flags |= INTERRUPT_TIMER_MASK;
-        }
-        if self.mode == GpuMode::HBlank && self.mode_0_interrupt {
-            self.interrupt_flags |= INTERRUPT_TIMER_MASK;
-        }
-        if self.mode == GpuMode::VBlank && self.mode_1_interrupt {
-            self.interrupt_flags |= INTERRUPT_TIMER_MASK;
-        }
+        self.update_stat_interrupt();
     }
 
-    fn update_interrupt_for_lyc(&mut self) {
-        if self.lyc_interrupt_enable {
-            if self.ly == self.lyc {
-                self.interrupt_flags |= INTERRUPT_TIMER_MASK;
-            }
+    // https://gbdev.io/pandocs/#ff41-stat-lcd-status-r-w
+    // All enabled sources are ORed into one line, so a source becoming active while another
+    // one already holds the line high does not request a second interrupt ("STAT blocking").
+    fn update_stat_interrupt(&mut self) {
+        let line = (self.lyc_interrupt_enable && self.current_ly() == self.lyc)
+            || (self.mode_2_interrupt && self.mode == GpuMode::Read)
+            || (self.mode_1_interrupt && self.mode == GpuMode::VBlank)
+            || (self.mode_0_interrupt && self.mode == GpuMode::HBlank);
+
+        if line && !self.stat_line {
+            self.interrupt_flags |= INTERRUPT_LCD_STAT_MASK;
         }
+        self.stat_line = line;
     }
 
     fn update_pal_palettes(&mut self) {
@@ -709,13 +888,9 @@ impl Ppu {
         }
     }
 
-    fn get_pal_color(&self, value: u8, index: usize) -> u8 {
-        match (value >> 2 * index) & 0x03 {
-            0 => 255,
-            1 => 192,
-            2 => 96,
-            _ => 0
-        }
+    fn get_pal_color(&self, value: u8, index: usize) -> [u8; 3] {
+        let shade = (value >> 2 * index) & 0x03;
+        return dmg_color_to_rgb(self.dmg_palette[shade as usize]);
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
@@ -733,16 +908,17 @@ impl Ppu {
                     (if self.bg_display_enable { 0x01 } else { 0 })
             },
             0xFF41 => {
-                (if self.lyc_interrupt_enable { 0x40 } else { 0 }) |
+                0x80 |
+                    (if self.lyc_interrupt_enable { 0x40 } else { 0 }) |
                     (if self.mode_2_interrupt { 0x20 } else { 0 }) |
                     (if self.mode_1_interrupt { 0x10 } else { 0 }) |
                     (if self.mode_0_interrupt { 0x08 } else { 0 }) |
-                    (if self.ly == self.lcd_y_coordinate { 0x04 } else { 0 }) |
+                    (if self.current_ly() == self.lyc { 0x04 } else { 0 }) |
                     self.mode as u8
             },
             0xFF42 => self.scroll_y_coord,
             0xFF43 => self.scroll_x_coord,
-            0xFF44 => self.ly,
+            0xFF44 => self.current_ly(),
             0xFF45 => self.lyc,
             0xFF46 => 0,
             0xFF47 => self.pal_bg_palette_data,
@@ -750,27 +926,12 @@ impl Ppu {
             0xFF49 => self.pal_obj_palette_1_data,
             0xFF4A => self.window_y_coord,
             0xFF4B => self.window_x_coord,
+            0xFF4C | 0xFF4E => 0xFF, // Unused
             0xFF4F => self.vram_bank as u8,
             0xFF68 => { self.cbg_bg_palette_index | (if self.cbg_bg_palette_increment { 0x80 } else { 0 }) },
-            0xFF69 => {
-                let palnum = (self.cbg_bg_palette_index >> 3) as usize;
-                let colnum = ((self.cbg_bg_palette_index >> 1) & 0x3) as usize;
-                if self.cbg_bg_palette_index & 0x01 == 0x00 {
-                    self.cbg_bg_palette[palnum][colnum][0] | ((self.cbg_bg_palette[palnum][colnum][1] & 0x07) << 5)
-                } else {
-                    ((self.cbg_bg_palette[palnum][colnum][1] & 0x18) >> 3) | (self.cbg_bg_palette[palnum][colnum][2] << 2)
-                }
-            },
+            0xFF69 => self.cbg_bg_palette_ram[self.cbg_bg_palette_index as usize],
             0xFF6A => { self.cbg_obj_index | (if self.cbg_obj_increment { 0x80 } else { 0 }) },
-            0xFF6B => {
-                let palnum = (self.cbg_obj_index >> 3) as usize;
-                let colnum = ((self.cbg_obj_index >> 1) & 0x3) as usize;
-                if self.cbg_obj_index & 0x01 == 0x00 {
-                    self.cbg_obj[palnum][colnum][0] | ((self.cbg_obj[palnum][colnum][1] & 0x07) << 5)
-                } else {
-                    ((self.cbg_obj[palnum][colnum][1] & 0x18) >> 3) | (self.cbg_obj[palnum][colnum][2] << 2)
-                }
-            },
+            0xFF6B => self.cbg_obj_ram[self.cbg_obj_index as usize],
             0xFF6C => 0x0,
             _ => panic!("invalid"),
         }
@@ -793,10 +954,8 @@ impl Ppu {
                 self.sprite_enable = value & 0x02 == 0x02;
                 self.bg_display_enable = value & 0x01 == 0x01;
 
-                if last_lcd_display_enable && !self.lcd_display_enable {
-                    self.mode = GpuMode::HBlank;
-                    self.ly = 0;
-                    self.clock = 0;
+                if last_lcd_display_enable != self.lcd_display_enable {
+                    self.switch_lcd();
                 }
 
                 if self.model == GameboyType::COLOR {
@@ -810,11 +969,12 @@ impl Ppu {
                 self.mode_2_interrupt = value & 0x20 == 0x20;
                 self.mode_1_interrupt = value & 0x10 == 0x10;
                 self.mode_0_interrupt = value & 0x08 == 0x08;
+                self.update_stat_interrupt();
             },
             0xFF42 => self.scroll_y_coord = value,
             0xFF43 => self.scroll_x_coord = value,
             0xFF44 => {},
-            0xFF45 => self.lyc = value,
+            0xFF45 => { self.lyc = value; self.update_stat_interrupt(); },
             0xFF46 => {},
             0xFF47 => { self.pal_bg_palette_data = value; self.update_pal_palettes(); },
             0xFF48 => { self.pal_obj_palette_0_data = value; self.update_pal_palettes(); },
@@ -843,54 +1003,712 @@ impl Ppu {
     }
 
     fn update_palette(&mut self, palette_type: PaletteType, palette_value: u8) {
-        // To get the full color GB requires two writes (two bytes)
+        // Each color takes two bytes, little endian
         // Bit 0-4   Red Intensity   (00-1F)
         // Bit 5-9   Green Intensity (00-1F)
         // Bit 10-14 Blue Intensity  (00-1F)
 
-        let mut pal_num: usize;
-        let mut col_num: usize;
-        let mut palette: &mut [u8];
-        let mut byte_index;
+        let (ram, colors, index) = match palette_type {
+            PaletteType::BACKGROUND => (&mut self.cbg_bg_palette_ram, &mut self.cbg_bg_palette, self.cbg_bg_palette_index),
+            PaletteType::OBJECTS => (&mut self.cbg_obj_ram, &mut self.cbg_obj, self.cbg_obj_index),
+        };
+
+        ram[index as usize] = palette_value;
 
-        if palette_type == PaletteType::BACKGROUND {
-            pal_num = (self.cbg_bg_palette_index >> 3) as usize;
-            col_num = ((self.cbg_bg_palette_index >> 1) & 0x03) as usize;
-            palette = &mut self.cbg_bg_palette[pal_num][col_num];
-            byte_index = if self.cbg_bg_palette_index & 0x01 == 0x00 {0} else {1};
-        } else {
-            pal_num = (self.cbg_obj_index >> 3) as usize;
-            col_num = ((self.cbg_obj_index >> 1) & 0x03) as usize;
-            palette = &mut self.cbg_obj[pal_num][col_num];
-            byte_index = if self.cbg_obj_index & 0x01 == 0x00 {0} else {1};
+        let color_index = (index & 0x3E) as usize;
+        let color = (ram[color_index] as u16) | ((ram[color_index + 1] as u16) << 8);
+        colors[color_index >> 3][(color_index >> 1) & 0x03] = cgb_color_to_rgb(color);
+    }
+
+    pub fn set_gameboy_type(&mut self, model: GameboyType) {
+        self.model = model;
+    }
+
+    // https://gbdev.io/pandocs/#vram-tile-data
+    // Every tile of VRAM bank 0 shaded with BGP, as 0xAARRGGBB pixels of a 128x192 image
+    pub fn render_tile_data(&self) -> Vec<u32> {
+        let mut image = vec![0; TILE_DATA_W * TILE_DATA_H];
+
+        for tile in 0 .. 384 {
+            let x = (tile % 16) * 8;
+            let y = (tile / 16) * 8;
+            self.draw_tile(&mut image, TILE_DATA_W, x, y, 0x8000 + tile as u16 * 16);
         }
 
-        const RED: usize = 0;
-        const GREEN: usize = 1;
-        const BLUE: usize = 2;
+        return image;
+    }
 
-        if byte_index == 0 {
-            // First write
-            palette[RED] = palette_value & 0x1F;
-            palette[GREEN] = (palette[GREEN] & 0x18) | (palette_value >> 5) ;
-        } else {
-            // Second write
-            palette[GREEN] = (palette[GREEN] & 0x07) | ((palette_value & 0x3) << 3) & 0x1F;
-            palette[BLUE] = (palette_value >> 2) & 0x1F;
+    // https://gbdev.io/pandocs/#vram-background-maps
+    // The 256x256 background of the map at 0x9800 (which = 0) or 0x9C00 (which = 1), using the LCDC tile data area
+    pub fn render_tile_map(&self, which: u8) -> Vec<u32> {
+        let mut image = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+        let map_address: u16 = if which == 0 { 0x9800 } else { 0x9C00 };
+
+        for index in 0 .. 32 * 32 {
+            let tile_id = self.read_byte_from_vram(0, map_address + index as u16);
+            let tile_address = if self.bg_tile_data_select == 0x8000 {
+                0x8000 + tile_id as u16 * 16
+            } else {
+                (0x9000 + (tile_id as i8) as i32 * 16) as u16
+            };
+
+            self.draw_tile(&mut image, TILE_MAP_SIZE, (index % 32) * 8, (index / 32) * 8, tile_address);
         }
 
-        if byte_index == 1 {
-            // all colors are in range of 0x00 - 0x1F
-            // need logic to transform it to 00 - 255
+        return image;
+    }
+
+}
 
-            palette[RED] *= 8;
-            palette[GREEN] *= 8;
-            palette[BLUE] *= 8;
+impl Ppu {
+
+    // The last completed frame, 160x144 pixels row by row, each one packed as 0x00RRGGBB
+    pub fn frame_buffer(&self) -> &[u32] {
+        return &self.frame_buffer;
+    }
+
+    // Colors shown for the four shades of DMG games, lightest first, as 0xAARRGGBB with alpha ignored
+    pub fn set_dmg_palette(&mut self, colors: [u32; 4]) {
+        self.dmg_palette = colors;
+        self.update_pal_palettes();
+    }
+
+    pub fn dmg_palette(&self) -> [u32; 4] {
+        return self.dmg_palette;
+    }
+
+    // Replaces the frame with centered lines of text, darkest shade on the lightest one
+    pub fn show_text(&mut self, lines: &[&str]) {
+        const CELL_W: usize = GLYPH_W + 1;
+        const CELL_H: usize = GLYPH_H + 3;
+
+        self.fill_buffer(self.lightest_color());
+        let [r, g, b] = if self.model == GameboyType::COLOR { [0, 0, 0] } else { dmg_color_to_rgb(self.dmg_palette[3]) };
+
+        let top = SCREEN_H.saturating_sub(lines.len() * CELL_H) / 2;
+        for (row, line) in lines.iter().enumerate() {
+            let left = SCREEN_W.saturating_sub(line.chars().count() * CELL_W) / 2;
+
+            for (column, c) in line.chars().enumerate() {
+                let rows = match glyph(c) { Some(rows) => rows, None => continue };
+                for (y, bits) in rows.iter().enumerate() {
+                    for x in 0 .. GLYPH_W {
+                        let (screen_x, screen_y) = (left + column * CELL_W + x, top + row * CELL_H + y);
+                        if bits & (0x10 >> x) != 0 && screen_x < SCREEN_W && screen_y < SCREEN_H {
+                            self.set_rgb_at(screen_x, screen_y, r, g, b);
+                        }
+                    }
+                }
+            }
         }
+
+        self.render_frame();
     }
 
-    pub fn set_gameboy_type(&mut self, model: GameboyType) {
-        self.model = model;
+    // For raster effect capture and profiling, called at the end of every line including
+    // the V-Blank ones. Only LY is passed so the callback can not touch the PPU mid tick.
+    pub fn set_scanline_callback(&mut self, callback: Box<dyn FnMut(u8)>) {
+        self.scanline_callback = Some(callback);
+    }
+
+    pub fn take_scanline_callback(&mut self) -> Option<Box<dyn FnMut(u8)>> {
+        return self.scanline_callback.take();
     }
 
+    // For hosts that drive their own loop, called with the completed frame at the start of every
+    // V-Blank. A skipped frame passes the last drawn one again. The callback only gets the pixels,
+    // it has no way back into the PPU or the console while they are running.
+    pub fn set_vblank_callback(&mut self, callback: VBlankCallback) {
+        self.vblank_callback = Some(callback);
+    }
+
+    pub fn take_vblank_callback(&mut self) -> Option<VBlankCallback> {
+        return self.vblank_callback.take();
+    }
+
+    pub fn init_memory(&mut self, filler: &mut MemoryFiller) {
+        filler.fill(&mut self.vram);
+        filler.fill(&mut self.voam);
+    }
+
+    // The same frame as RGBA bytes
+    pub fn frame_rgba(&self) -> &[u8] {
+        return &self.frame;
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        return self.lcd_display_enable;
+    }
+
+    // Timing, interrupts and LY stay the same, only the pixels of skipped frames are not drawn
+    pub fn set_frame_skip(&mut self, frames: u32) {
+        self.frame_skip = frames;
+        self.frame_skip_phase = 0;
+    }
+
+    pub fn frame_skip(&self) -> u32 {
+        return self.frame_skip;
+    }
+
+    pub fn mode(&self) -> GpuMode {
+        return self.mode;
+    }
+
+    // https://gbdev.io/pandocs/#accessing-vram-and-oam
+    // The CPU can not reach VRAM while a line is drawn, nor OAM while it is also being searched.
+    // With the LCD off both are always free.
+    pub fn vram_accessible(&self) -> bool {
+        return !self.lcd_display_enable || self.mode != GpuMode::Transfer;
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        return !self.lcd_display_enable || self.mode == GpuMode::HBlank || self.mode == GpuMode::VBlank;
+    }
+
+    // True once a V-Blank started since the flag was last taken
+    pub fn frame_ready(&self) -> bool {
+        return self.frame_ready;
+    }
+
+    pub fn take_frame_ready(&mut self) -> bool {
+        return std::mem::replace(&mut self.frame_ready, false);
+    }
+
+    // Both VRAM banks, OAM and the CGB palettes, see Console::hash_state
+    pub fn hash_memory(&self, hasher: &mut impl Hasher) {
+        hasher.write(&self.vram);
+        hasher.write(&self.voam);
+        hasher.write(&self.cbg_bg_palette_ram);
+        hasher.write(&self.cbg_obj_ram);
+    }
+
+    // https://gbdev.io/pandocs/#vram-sprite-attribute-table-oam
+    pub fn oam_sprites(&self) -> Vec<SpriteEntry> {
+        return self.voam.chunks(4).enumerate().map(|(index, entry)| SpriteEntry {
+            index: index as u8,
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            flags: entry[3],
+        }).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plain grays so the pixels compare against white and black
+    const DMG_PALETTE_GRAY: [u32; 4] = [0xFFFFFF, 0xC0C0C0, 0x606060, 0x000000];
+
+    fn create_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.reset(GameboyType::CLASSIC);
+        ppu.set_dmg_palette(DMG_PALETTE_GRAY);
+        return ppu;
+    }
+
+    #[test]
+    fn lyc_interrupt_fires_on_matching_line() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF45, 10);
+        ppu.write_byte(0xFF41, 0x40);
+
+        ppu.execute_ticks(DOTS_PER_LINE * 10 - 1);
+        assert_eq!(ppu.read_byte(0xFF44), 9);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0);
+
+        ppu.execute_ticks(1);
+        assert_eq!(ppu.read_byte(0xFF44), 10);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, INTERRUPT_LCD_STAT_MASK);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0x04);
+    }
+
+    #[test]
+    fn lyc_match_without_enable_bit_does_not_interrupt() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF45, 10);
+
+        ppu.execute_ticks(DOTS_PER_LINE * 10);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0x04);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+    }
+
+    #[test]
+    fn hblank_interrupt_fires_once_per_line() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF41, 0x08);
+
+        ppu.execute_ticks(80 + 172 - 1);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+
+        ppu.execute_ticks(1);
+        assert_eq!(ppu.read_byte(0xFF41) & 0x03, GpuMode::HBlank as u8);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, INTERRUPT_LCD_STAT_MASK);
+
+        ppu.interrupt_flags = 0;
+        ppu.execute_ticks(DOTS_PER_LINE - 1);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+
+        ppu.execute_ticks(1);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, INTERRUPT_LCD_STAT_MASK);
+    }
+
+    #[test]
+    fn stat_blocking_suppresses_second_source() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF45, 0);
+        ppu.write_byte(0xFF41, 0x60);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, INTERRUPT_LCD_STAT_MASK);
+        ppu.interrupt_flags = 0;
+
+        // LY=LYC holds the line high until mode 2 of line 1 takes over
+        ppu.execute_ticks(DOTS_PER_LINE);
+        assert_eq!(ppu.read_byte(0xFF44), 1);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+
+        ppu.write_byte(0xFF45, 1);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, 0);
+    }
+
+    fn mode(ppu: &Ppu) -> u8 {
+        return ppu.read_byte(0xFF41) & 0x03;
+    }
+
+    #[test]
+    fn visible_line_mode_timing() {
+        let mut ppu = create_ppu();
+        assert_eq!(mode(&ppu), 2);
+
+        ppu.execute_ticks(79);
+        assert_eq!(mode(&ppu), 2);
+        ppu.execute_ticks(1);
+        assert_eq!(mode(&ppu), 3);
+
+        ppu.execute_ticks(171);
+        assert_eq!(mode(&ppu), 3);
+        ppu.execute_ticks(1);
+        assert_eq!(mode(&ppu), 0);
+
+        ppu.execute_ticks(203);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 0));
+        ppu.execute_ticks(1);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (1, 2));
+    }
+
+    #[test]
+    fn vblank_starts_on_line_144() {
+        let mut ppu = create_ppu();
+
+        ppu.execute_ticks(DOTS_PER_LINE * 144 - 1);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (143, 0));
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_V_BLANK_MASK, 0);
+
+        ppu.execute_ticks(1);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (144, 1));
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_V_BLANK_MASK, INTERRUPT_V_BLANK_MASK);
+
+        ppu.execute_ticks(DOTS_PER_LINE * 9);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (153, 1));
+    }
+
+    #[test]
+    fn ly_reads_zero_for_most_of_line_153() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF45, 0);
+        ppu.write_byte(0xFF41, 0x40);
+
+        ppu.execute_ticks(DOTS_PER_LINE * 153 + 3);
+        assert_eq!(ppu.read_byte(0xFF44), 153);
+        ppu.interrupt_flags = 0;
+
+        ppu.execute_ticks(1);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 1));
+        assert_eq!(ppu.read_byte(0xFF41) & 0x04, 0x04);
+        assert_eq!(ppu.interrupt_flags & INTERRUPT_LCD_STAT_MASK, INTERRUPT_LCD_STAT_MASK);
+
+        ppu.execute_ticks(DOTS_PER_LINE - 4);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 2));
+        assert!(ppu.frame_ready());
+    }
+
+    #[test]
+    fn frame_ready_stays_set_from_v_blank_until_taken() {
+        let mut ppu = create_ppu();
+
+        ppu.execute_ticks(DOTS_PER_LINE * SCREEN_H as u32 - 1);
+        assert!(!ppu.frame_ready());
+        ppu.execute_ticks(1);
+        assert!(ppu.frame_ready());
+
+        ppu.execute_ticks(DOTS_PER_LINE * 20);
+        assert!(ppu.take_frame_ready());
+        assert!(!ppu.frame_ready());
+    }
+
+    #[test]
+    fn scanline_callback_sees_every_line_in_order() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&lines);
+        ppu.set_scanline_callback(Box::new(move |ly| recorded.borrow_mut().push(ly)));
+
+        ppu.execute_ticks(DOTS_PER_LINE * LINES_PER_FRAME as u32 - 1);
+        assert_eq!(lines.borrow().len(), LINES_PER_FRAME as usize - 1);
+        ppu.execute_ticks(1);
+        assert_eq!(*lines.borrow(), (0 ..= 153).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn lcd_off_holds_ly_at_zero_in_mode_0() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0xE4);
+        ppu.execute_ticks(DOTS_PER_LINE * 10 + 100);
+        assert_eq!(ppu.read_byte(0xFF44), 10);
+
+        ppu.write_byte(0xFF40, 0x11);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 0));
+        assert!(ppu.frame_rgba().iter().all(|&channel| channel == 255));
+
+        ppu.execute_ticks(DOTS_PER_LINE * 200);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 0));
+        assert!(!ppu.frame_ready());
+
+        // Back on, a new frame starts with OAM search
+        ppu.write_byte(0xFF40, 0x91);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 2));
+        ppu.execute_ticks(OAM_SEARCH_DOTS);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (0, 3));
+        ppu.execute_ticks(DOTS_PER_LINE * 144 - OAM_SEARCH_DOTS);
+        assert_eq!((ppu.read_byte(0xFF44), mode(&ppu)), (144, 1));
+    }
+
+    // Low bits 0x55 and high bits 0x33 give the color indexes 0, 1, 2, 3, 0, 1, 2, 3
+    fn write_tile_row(ppu: &mut Ppu, address: u16) {
+        ppu.write_byte(address, 0x55);
+        ppu.write_byte(address + 1, 0x33);
+    }
+
+    fn render_line(ppu: &mut Ppu, ly: u32) -> Vec<u8> {
+        let elapsed = ppu.ly as u32 * DOTS_PER_LINE + ppu.clock;
+        ppu.execute_ticks(ly * DOTS_PER_LINE + OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS - elapsed);
+        return (0 .. SCREEN_W).map(|x| ppu.buffer[(ly as usize * SCREEN_W + x) * 4]).collect();
+    }
+
+    #[test]
+    fn background_maps_colors_through_bgp() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0xE4);
+        write_tile_row(&mut ppu, 0x8010);
+        ppu.write_byte(0x9800, 0x01);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 9], [255, 192, 96, 0, 255, 192, 96, 0, 255]);
+
+        // Inverted palette
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0x1B);
+        write_tile_row(&mut ppu, 0x8010);
+        ppu.write_byte(0x9800, 0x01);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [0, 96, 192, 255]);
+    }
+
+    #[test]
+    fn background_scrolls_and_wraps() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0xE4);
+        write_tile_row(&mut ppu, 0x8010);
+        ppu.write_byte(0xFF42, 8);
+        ppu.write_byte(0xFF43, 0xFE);
+        // Second row of the map, last and first column
+        ppu.write_byte(0x983F, 0x01);
+        ppu.write_byte(0x9820, 0x01);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [96, 0, 255, 192]);
+    }
+
+    #[test]
+    fn background_signed_tile_addressing() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x81);
+        ppu.write_byte(0xFF47, 0xE4);
+        // Tile 0 is at 0x9000 and tile 0x80 (-128) at 0x8800
+        write_tile_row(&mut ppu, 0x9000);
+        write_tile_row(&mut ppu, 0x8800);
+        ppu.write_byte(0x9800, 0x00);
+        ppu.write_byte(0x9801, 0x80);
+        ppu.write_byte(0x9802, 0x01);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [255, 192, 96, 0]);
+        assert_eq!(line[8 .. 12], [255, 192, 96, 0]);
+        assert_eq!(line[16 .. 20], [255, 255, 255, 255]);
+    }
+
+    fn create_window_ppu() -> Ppu {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF47, 0xE4);
+        // Window rows 0 and 1 of tile 1 are color 3 and color 2
+        ppu.write_byte(0x8010, 0xFF);
+        ppu.write_byte(0x8011, 0xFF);
+        ppu.write_byte(0x8012, 0x00);
+        ppu.write_byte(0x8013, 0xFF);
+        for i in 0 .. 0x400 {
+            ppu.write_byte(0x9C00 + i, 0x01);
+        }
+        ppu.write_byte(0xFF4A, 40);
+        ppu.write_byte(0xFF4B, 87);
+        // Window map at 0x9C00 and enabled, background map at 0x9800
+        ppu.write_byte(0xFF40, 0xF1);
+        return ppu;
+    }
+
+    #[test]
+    fn window_starts_at_wy_and_wx_minus_7() {
+        let mut ppu = create_window_ppu();
+
+        let line = render_line(&mut ppu, 39);
+        assert_eq!(line[80], 255);
+
+        let line = render_line(&mut ppu, 40);
+        assert_eq!(line[79], 255);
+        assert_eq!(line[80], 0);
+        assert_eq!(line[159], 0);
+    }
+
+    #[test]
+    fn window_line_counter_skips_hidden_lines() {
+        let mut ppu = create_window_ppu();
+
+        let line = render_line(&mut ppu, 40);
+        assert_eq!(line[80], 0);
+
+        ppu.write_byte(0xFF40, 0xD1);
+        let line = render_line(&mut ppu, 41);
+        assert_eq!(line[80], 255);
+
+        // Continues with the second window row instead of the third
+        ppu.write_byte(0xFF40, 0xF1);
+        let line = render_line(&mut ppu, 42);
+        assert_eq!(line[80], 96);
+    }
+
+    #[test]
+    fn frame_skip_draws_alternate_frames_with_same_timing() {
+        let mut skipping = create_window_ppu();
+        let mut drawing = create_window_ppu();
+        skipping.set_frame_skip(1);
+
+        let mut skipped_frames = Vec::new();
+        let mut drawn_frames = Vec::new();
+        // Skipped frames would show up with the all black palette 0xFF
+        for palette in [0xE4, 0xFF, 0x1B, 0xFF] {
+            skipping.write_byte(0xFF47, palette);
+            drawing.write_byte(0xFF47, palette);
+
+            for _ in 0 .. DOTS_PER_LINE * LINES_PER_FRAME as u32 {
+                skipping.execute_tick();
+                drawing.execute_tick();
+                assert_eq!((skipping.ly, skipping.mode, skipping.wly), (drawing.ly, drawing.mode, drawing.wly));
+                assert_eq!(skipping.interrupt_flags, drawing.interrupt_flags);
+            }
+            skipped_frames.push(skipping.frame_buffer().to_vec());
+            drawn_frames.push(drawing.frame_buffer().to_vec());
+        }
+
+        assert_eq!(skipped_frames[0], drawn_frames[0]);
+        assert_eq!(skipped_frames[1], drawn_frames[0]);
+        assert_eq!(skipped_frames[2], drawn_frames[2]);
+        assert_eq!(skipped_frames[3], drawn_frames[2]);
+        assert_ne!(drawn_frames[1], drawn_frames[0]);
+        assert_ne!(drawn_frames[2], drawn_frames[0]);
+    }
+
+    #[test]
+    fn dmg_background_disable_hides_window() {
+        let mut ppu = create_window_ppu();
+        ppu.write_byte(0xFF40, 0xF0);
+
+        let line = render_line(&mut ppu, 40);
+        assert_eq!(line[80], 255);
+    }
+
+    fn create_sprite_ppu() -> Ppu {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x93);
+        ppu.write_byte(0xFF47, 0xE4);
+        ppu.write_byte(0xFF48, 0xE4);
+        ppu.write_byte(0xFF49, 0x40);
+        // Tile 1 is solid color 3, tile 2 uses the 0, 1, 2, 3 row pattern
+        for row in 0 .. 8 {
+            ppu.write_byte(0x8010 + row * 2, 0xFF);
+            ppu.write_byte(0x8011 + row * 2, 0xFF);
+            write_tile_row(&mut ppu, 0x8020 + row * 2);
+        }
+        return ppu;
+    }
+
+    fn write_sprite(ppu: &mut Ppu, index: u16, x: u8, y: u8, tile: u8, flags: u8) {
+        let address = 0xFE00 + index * 4;
+        ppu.write_byte(address, y + 16);
+        ppu.write_byte(address + 1, x + 8);
+        ppu.write_byte(address + 2, tile);
+        ppu.write_byte(address + 3, flags);
+    }
+
+    #[test]
+    fn dmg_sprite_with_smaller_x_wins() {
+        let mut ppu = create_sprite_ppu();
+        // OBP0 draws color 3 as black, OBP1 as light gray
+        write_sprite(&mut ppu, 0, 12, 0, 0x01, 0x00);
+        write_sprite(&mut ppu, 1, 8, 0, 0x01, 0x10);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[8 .. 16], [192; 8]);
+        assert_eq!(line[16 .. 20], [0; 4]);
+        assert_eq!(line[20], 255);
+    }
+
+    #[test]
+    fn sprite_limit_counts_off_screen_sprites() {
+        let mut ppu = create_sprite_ppu();
+        for index in 0 .. 10 {
+            write_sprite(&mut ppu, index, 200, 0, 0x01, 0x00);
+        }
+        write_sprite(&mut ppu, 10, 8, 0, 0x01, 0x00);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[8 .. 16], [255; 8]);
+    }
+
+    #[test]
+    fn sprite_flips_and_transparency() {
+        let mut ppu = create_sprite_ppu();
+        write_sprite(&mut ppu, 0, 0, 0, 0x02, 0x00);
+        write_sprite(&mut ppu, 1, 8, 0, 0x02, 0x20);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [255, 192, 96, 0]);
+        assert_eq!(line[12 .. 16], [0, 96, 192, 255]);
+    }
+
+    #[test]
+    fn sprite_behind_background_shows_through_color_0() {
+        let mut ppu = create_sprite_ppu();
+        write_tile_row(&mut ppu, 0x8030);
+        ppu.write_byte(0x9800, 0x03);
+        write_sprite(&mut ppu, 0, 0, 0, 0x01, 0x90);
+
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [192, 192, 96, 0]);
+    }
+
+    #[test]
+    fn cgb_palette_auto_increment_round_trip() {
+        let mut ppu = Ppu::new();
+        ppu.reset(GameboyType::COLOR);
+
+        // Palette 1 color 0, magenta
+        ppu.write_byte(0xFF68, 0x88);
+        ppu.write_byte(0xFF69, 0x1F);
+        ppu.write_byte(0xFF69, 0x7C);
+        assert_eq!(ppu.read_byte(0xFF68), 0x8A);
+
+        ppu.write_byte(0xFF68, 0x08);
+        assert_eq!(ppu.read_byte(0xFF69), 0x1F);
+        ppu.write_byte(0xFF68, 0x09);
+        assert_eq!(ppu.read_byte(0xFF69), 0x7C);
+        assert_eq!(ppu.cbg_bg_palette[1][0], cgb_color_to_rgb(0x7C1F));
+
+        // The index wraps after the last object palette byte
+        ppu.write_byte(0xFF6A, 0xBF);
+        ppu.write_byte(0xFF6B, 0x7F);
+        ppu.write_byte(0xFF6B, 0xFF);
+        assert_eq!(ppu.read_byte(0xFF6A), 0x81);
+        ppu.write_byte(0xFF6A, 0x00);
+        assert_eq!(ppu.read_byte(0xFF6B), 0xFF);
+        assert_eq!(ppu.cbg_obj[7][3], cgb_color_to_rgb(0x7F00));
+        assert_eq!(ppu.cbg_obj[0][0], cgb_color_to_rgb(0x00FF));
+    }
+
+    #[test]
+    fn cgb_color_correction_keeps_black_and_white() {
+        assert_eq!(cgb_color_to_rgb(0x0000), [0, 0, 0]);
+        assert_eq!(cgb_color_to_rgb(0x7FFF), [255, 255, 255]);
+    }
+
+    #[test]
+    fn tile_data_view_shows_written_tile() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF47, 0xE4);
+
+        // Tile 17 is the second tile of the second row of the view
+        for row in 0 .. 8 {
+            ppu.write_byte(0x8110 + row * 2, 0xFF);
+            ppu.write_byte(0x8110 + row * 2 + 1, 0xFF);
+        }
+
+        let image = ppu.render_tile_data();
+        assert_eq!(image.len(), TILE_DATA_W * TILE_DATA_H);
+
+        for y in 8 .. 16 {
+            for x in 8 .. 16 {
+                assert_eq!(image[y * TILE_DATA_W + x], 0xFF000000);
+            }
+        }
+        assert_eq!(image[8 * TILE_DATA_W + 7], 0xFFFFFFFF);
+        assert_eq!(image[7 * TILE_DATA_W + 8], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn tile_map_view_uses_selected_map() {
+        let mut ppu = create_ppu();
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0xE4);
+        write_tile_row(&mut ppu, 0x8010);
+        ppu.write_byte(0x9C21, 0x01);
+
+        let image = ppu.render_tile_map(1);
+        let shades: Vec<u32> = (0 .. 8).map(|x| image[8 * TILE_MAP_SIZE + 8 + x] & 0xFF).collect();
+        assert_eq!(shades, vec![255, 192, 96, 0, 255, 192, 96, 0]);
+
+        assert!(ppu.render_tile_map(0).iter().all(|&pixel| pixel == 0xFFFFFFFF));
+    }
+
+    #[test]
+    fn dmg_palette_maps_shades_to_colors() {
+        let mut ppu = Ppu::new();
+        ppu.reset(GameboyType::CLASSIC);
+        ppu.write_byte(0xFF40, 0x91);
+        ppu.write_byte(0xFF47, 0xE4);
+        write_tile_row(&mut ppu, 0x8000);
+        write_tile_row(&mut ppu, 0x8002);
+
+        // Green by default
+        let line = render_line(&mut ppu, 0);
+        assert_eq!(line[0 .. 4], [0x9B, 0x8B, 0x30, 0x0F]);
+
+        ppu.set_dmg_palette([0xFF112233, 0x00445566, 0x778899, 0xAABBCC]);
+        render_line(&mut ppu, 1);
+        let pixels: Vec<&[u8]> = ppu.buffer[SCREEN_W * 4 .. SCREEN_W * 4 + 16].chunks(4).collect();
+        assert_eq!(pixels, vec![
+            &[0x11, 0x22, 0x33, 0xFF][..],
+            &[0x44, 0x55, 0x66, 0xFF][..],
+            &[0x77, 0x88, 0x99, 0xFF][..],
+            &[0xAA, 0xBB, 0xCC, 0xFF][..],
+        ]);
+    }
 }
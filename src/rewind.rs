@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+// A snapshot a second for the last minute at 60 frames per second
+const DEFAULT_INTERVAL: usize = 60;
+const DEFAULT_CAPACITY: usize = 60;
+
+// Keeps the newest save state in full and every older one as a delta against its successor.
+// Consecutive states differ in a few kilobytes at most, so the XOR of two of them is mostly
+// zeros which the run length encoding below collapses.
+pub struct RewindBuffer {
+    interval: usize, // Frames between snapshots
+    capacity: usize, // Snapshots kept, including the newest one
+    frames_since_snapshot: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>, // Oldest first
+}
+
+impl Default for RewindBuffer {
+    fn default() -> Self {
+        return RewindBuffer::new(DEFAULT_INTERVAL, DEFAULT_CAPACITY);
+    }
+}
+
+impl RewindBuffer {
+
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        return RewindBuffer {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames_since_snapshot: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Counts an emulated frame, returns true when a snapshot is due
+    pub fn frame_elapsed(&mut self) -> bool {
+        self.frames_since_snapshot += 1;
+        return self.latest.is_none() || self.frames_since_snapshot >= self.interval;
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&latest, &state));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(state);
+        self.frames_since_snapshot = 0;
+    }
+
+    // Returns the newest snapshot taken at least frames frames ago, or the oldest one if the buffer
+    // does not reach back that far. Every newer snapshot is dropped.
+    pub fn rewind(&mut self, frames: usize) -> Option<Vec<u8>> {
+        let mut state = self.latest.take()?;
+
+        let steps = if frames <= self.frames_since_snapshot { 0 } else {
+            (frames - self.frames_since_snapshot).div_ceil(self.interval)
+        };
+
+        for _ in 0 .. steps {
+            match self.deltas.pop_back() {
+                Some(delta) => state = apply_delta(&state, &delta),
+                None => break,
+            }
+        }
+
+        self.latest = Some(state.clone());
+        self.frames_since_snapshot = 0;
+        return Some(state);
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        return self.deltas.len() + if self.latest.is_some() { 1 } else { 0 };
+    }
+
+    // Bytes held by all snapshots
+    #[cfg(test)]
+    pub fn memory_size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |state| state.len());
+        return latest + self.deltas.iter().map(|delta| delta.len()).sum::<usize>();
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.frames_since_snapshot = 0;
+    }
+}
+
+// Layout: length of older as u32 LE, then runs of (zero count u16 LE, literal count u16 LE, literals)
+// over older XOR newer, the shorter of the two padded with zeros.
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let length = older.len().max(newer.len());
+    let xor = |index: usize| older.get(index).unwrap_or(&0) ^ newer.get(index).unwrap_or(&0);
+
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(older.len() as u32).to_le_bytes());
+
+    let mut index = 0;
+    while index < length {
+        let zeros_start = index;
+        while index < length && index - zeros_start < 0xFFFF && xor(index) == 0 { index += 1; }
+        let zeros = index - zeros_start;
+
+        let literals_start = index;
+        while index < length && index - literals_start < 0xFFFF && xor(index) != 0 { index += 1; }
+
+        delta.extend_from_slice(&(zeros as u16).to_le_bytes());
+        delta.extend_from_slice(&((index - literals_start) as u16).to_le_bytes());
+        delta.extend((literals_start .. index).map(xor));
+    }
+
+    return delta;
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let older_length = u32::from_le_bytes([delta[0], delta[1], delta[2], delta[3]]) as usize;
+
+    let mut older = newer.to_vec();
+    older.resize(older_length.max(newer.len()), 0);
+
+    let mut index = 0;
+    let mut position = 4;
+    while position < delta.len() {
+        let zeros = u16::from_le_bytes([delta[position], delta[position + 1]]) as usize;
+        let literals = u16::from_le_bytes([delta[position + 2], delta[position + 3]]) as usize;
+        position += 4;
+        index += zeros;
+
+        for offset in 0 .. literals {
+            older[index + offset] ^= delta[position + offset];
+        }
+        index += literals;
+        position += literals;
+    }
+
+    older.truncate(older_length);
+    return older;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip_handles_length_changes() {
+        let older: Vec<u8> = (0 .. 200000).map(|i| (i % 251) as u8).collect();
+        let mut newer = older.clone();
+        newer[10] = 0xAA;
+        newer[150000] ^= 0x01;
+        newer.extend_from_slice(&[1, 2, 3]);
+
+        let delta = encode_delta(&older, &newer);
+        assert!(delta.len() < 64);
+        assert_eq!(apply_delta(&newer, &delta), older);
+
+        let delta = encode_delta(&newer, &older);
+        assert_eq!(apply_delta(&older, &delta), newer);
+    }
+
+    #[test]
+    fn rewind_walks_back_by_interval() {
+        let mut buffer = RewindBuffer::new(2, 3);
+        for frame in 0 .. 8u8 {
+            if buffer.frame_elapsed() { buffer.push(vec![frame; 4]); }
+        }
+
+        // Snapshots of frames 0, 2, 4 and 6 were taken, the capacity keeps the last three
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.rewind(1), Some(vec![6; 4]));
+        assert_eq!(buffer.rewind(2), Some(vec![4; 4]));
+        assert_eq!(buffer.rewind(100), Some(vec![2; 4]));
+        assert_eq!(buffer.len(), 1);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::ppu::{SCREEN_W, SCREEN_H};
+
+// Size of the buffer handed to the window, 16:9 so the Game Boy screen sits between bars
+pub const OUTPUT_W: usize = 640;
+pub const OUTPUT_H: usize = 360;
+
+const BORDER_COLOR: u32 = 0x000000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Scale2x,
+    Bilinear,
+}
+
+// Scales a frame to OUTPUT_W x OUTPUT_H, keeping the aspect ratio of the screen
+pub fn scale_frame(src: &[u32], dst: &mut [u32], filter: Filter) {
+    scale(src, SCREEN_W, SCREEN_H, dst, OUTPUT_W, OUTPUT_H, filter);
+}
+
+pub fn scale(src: &[u32], src_w: usize, src_h: usize, dst: &mut [u32], dst_w: usize, dst_h: usize, filter: Filter) {
+    assert_eq!(src.len(), src_w * src_h);
+    assert_eq!(dst.len(), dst_w * dst_h);
+
+    dst.fill(BORDER_COLOR);
+    let area = letterbox(src_w, src_h, dst_w, dst_h);
+
+    match filter {
+        Filter::Nearest => nearest(src, src_w, src_h, dst, dst_w, area),
+        Filter::Scale2x => {
+            let doubled = scale2x(src, src_w, src_h);
+            nearest(&doubled, src_w * 2, src_h * 2, dst, dst_w, area);
+        },
+        Filter::Bilinear => bilinear(src, src_w, src_h, dst, dst_w, area),
+    }
+}
+
+// The largest rectangle with the aspect ratio of the source centered in the destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Area {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+}
+
+fn letterbox(src_w: usize, src_h: usize, dst_w: usize, dst_h: usize) -> Area {
+    let (w, h) = if dst_w * src_h <= dst_h * src_w {
+        (dst_w, src_h * dst_w / src_w)
+    } else {
+        (src_w * dst_h / src_h, dst_h)
+    };
+
+    return Area { x: (dst_w - w) / 2, y: (dst_h - h) / 2, w, h };
+}
+
+fn nearest(src: &[u32], src_w: usize, src_h: usize, dst: &mut [u32], dst_w: usize, area: Area) {
+    for y in 0..area.h {
+        let src_row = (y * src_h / area.h) * src_w;
+        let dst_row = (area.y + y) * dst_w + area.x;
+        for x in 0..area.w {
+            dst[dst_row + x] = src[src_row + x * src_w / area.w];
+        }
+    }
+}
+
+// https://www.scale2x.it/algorithm
+// Every pixel becomes 2x2, a corner takes the color of its two neighbors when they agree
+// and the pixel is not on a straight edge
+fn scale2x(src: &[u32], src_w: usize, src_h: usize) -> Vec<u32> {
+    let out_w = src_w * 2;
+    let mut out = vec![0; out_w * src_h * 2];
+    let pixel = |x: usize, y: usize| src[y * src_w + x];
+
+    for y in 0..src_h {
+        for x in 0..src_w {
+            let p = pixel(x, y);
+            let a = pixel(x, y.saturating_sub(1));
+            let b = pixel((x + 1).min(src_w - 1), y);
+            let c = pixel(x.saturating_sub(1), y);
+            let d = pixel(x, (y + 1).min(src_h - 1));
+
+            let top = (y * 2) * out_w + x * 2;
+            let bottom = top + out_w;
+            out[top] = if c == a && c != d && a != b { a } else { p };
+            out[top + 1] = if a == b && a != c && b != d { b } else { p };
+            out[bottom] = if d == c && d != b && c != a { c } else { p };
+            out[bottom + 1] = if b == d && b != a && d != c { d } else { p };
+        }
+    }
+
+    return out;
+}
+
+fn bilinear(src: &[u32], src_w: usize, src_h: usize, dst: &mut [u32], dst_w: usize, area: Area) {
+    // Sample at pixel centers so the edges do not shift by half a pixel
+    let position = |dst_pos: usize, dst_len: usize, src_len: usize| -> (usize, usize, f32) {
+        let pos = ((dst_pos as f32 + 0.5) * src_len as f32 / dst_len as f32 - 0.5).max(0.0);
+        let first = (pos as usize).min(src_len - 1);
+        let second = (first + 1).min(src_len - 1);
+        return (first, second, pos - first as f32);
+    };
+
+    for y in 0..area.h {
+        let (y0, y1, fy) = position(y, area.h, src_h);
+        let dst_row = (area.y + y) * dst_w + area.x;
+        for x in 0..area.w {
+            let (x0, x1, fx) = position(x, area.w, src_w);
+            let top = mix(src[y0 * src_w + x0], src[y0 * src_w + x1], fx);
+            let bottom = mix(src[y1 * src_w + x0], src[y1 * src_w + x1], fx);
+            dst[dst_row + x] = mix(top, bottom, fy);
+        }
+    }
+}
+
+// Per channel interpolation of two 0RGB colors
+fn mix(first: u32, second: u32, weight: f32) -> u32 {
+    let mut color = 0;
+    for shift in [0, 8, 16] {
+        let a = ((first >> shift) & 0xFF) as f32;
+        let b = ((second >> shift) & 0xFF) as f32;
+        let channel = (a + (b - a) * weight).round() as u32;
+        color |= channel.min(0xFF) << shift;
+    }
+    return color;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_replicates_pixels() {
+        let src = [
+            0x111111, 0x222222,
+            0x333333, 0x444444,
+        ];
+        let mut dst = [0; 16];
+        scale(&src, 2, 2, &mut dst, 4, 4, Filter::Nearest);
+
+        assert_eq!(dst, [
+            0x111111, 0x111111, 0x222222, 0x222222,
+            0x111111, 0x111111, 0x222222, 0x222222,
+            0x333333, 0x333333, 0x444444, 0x444444,
+            0x333333, 0x333333, 0x444444, 0x444444,
+        ]);
+    }
+
+    #[test]
+    fn frame_is_letterboxed() {
+        assert_eq!(letterbox(SCREEN_W, SCREEN_H, OUTPUT_W, OUTPUT_H), Area { x: 120, y: 0, w: 400, h: 360 });
+
+        let src = vec![0xFFFFFF; SCREEN_W * SCREEN_H];
+        let mut dst = vec![0x123456; OUTPUT_W * OUTPUT_H];
+        for filter in [Filter::Nearest, Filter::Scale2x, Filter::Bilinear] {
+            scale_frame(&src, &mut dst, filter);
+            assert_eq!(dst[0], BORDER_COLOR);
+            assert_eq!(dst[119], BORDER_COLOR);
+            assert_eq!(dst[120], 0xFFFFFF);
+            assert_eq!(dst[OUTPUT_W * OUTPUT_H - 121], 0xFFFFFF);
+            assert_eq!(dst[OUTPUT_W * OUTPUT_H - 1], BORDER_COLOR);
+        }
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonal_edges() {
+        // The top right corner of the bottom left pixel joins the dark diagonal
+        let src = [
+            0x000000, 0xFFFFFF,
+            0xFFFFFF, 0x000000,
+        ];
+        let out = scale2x(&src, 2, 2);
+        assert_eq!(out[2 * 4 + 1], 0x000000);
+        assert_eq!(out[2 * 4], 0xFFFFFF);
+        assert_eq!(out[0], 0x000000);
+    }
+
+    #[test]
+    fn bilinear_blends_neighbors() {
+        let src = [0x000000, 0xFEFEFE];
+        let mut dst = [0; 8];
+        scale(&src, 2, 1, &mut dst, 4, 2, Filter::Bilinear);
+
+        assert_eq!(dst[0], 0x000000);
+        assert_eq!(dst[1], 0x404040);
+        assert_eq!(dst[2], 0xBFBFBF);
+        assert_eq!(dst[3], 0xFEFEFE);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::console::GameboyType;
+use serde_derive::{Serialize, Deserialize};
+
+pub const INTERRUPT_SERIAL_MASK: u8 = 0x08;
+
+// https://gbdev.io/pandocs/#serial-data-transfer-link-cable
+// There is no link partner, so transfers complete instantly and shift in 0xFF.
+// Everything sent is collected so test roms that print over serial can be checked.
+#[derive(Serialize, Deserialize)]
+pub struct Serial {
+    data: u8,
+    control: u8,
+    output: String,
+    pub interrupt_flags: u8,
+}
+
+impl Serial {
+
+    pub fn new() -> Self {
+        return Serial {
+            data: 0,
+            control: 0,
+            output: String::new(),
+            interrupt_flags: 0,
+        }
+    }
+
+    pub fn reset(&mut self, model: GameboyType) {
+        self.data = 0;
+        self.control = 0;
+        self.output.clear();
+        self.interrupt_flags = 0;
+    }
+
+    pub fn read_byte(&self, address: u16) -> u8 {
+        match address {
+            0xFF01 => self.data,
+            0xFF02 => self.control | 0x7E,
+            _ => panic!("{:04X}", address),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF01 => self.data = value,
+            0xFF02 => {
+                self.control = value & 0x81;
+                if value & 0x80 == 0x80 {
+                    self.transfer();
+                }
+            },
+            _ => panic!("{:04X}", address),
+        }
+    }
+
+    fn transfer(&mut self) {
+        self.output.push(self.data as char);
+        self.data = 0xFF;
+        self.control &= !0x80;
+        self.interrupt_flags |= INTERRUPT_SERIAL_MASK;
+    }
+
+    pub fn get_output(&self) -> &str {
+        return &self.output;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_collects_output_and_raises_interrupt() {
+        let mut serial = Serial::new();
+
+        for byte in "Passed".bytes() {
+            serial.write_byte(0xFF01, byte);
+            serial.write_byte(0xFF02, 0x81);
+            assert_eq!(serial.read_byte(0xFF02) & 0x80, 0);
+        }
+
+        assert_eq!(serial.get_output(), "Passed");
+        assert_eq!(serial.read_byte(0xFF01), 0xFF);
+        assert_eq!(serial.interrupt_flags, INTERRUPT_SERIAL_MASK);
+    }
+
+    #[test]
+    fn no_transfer_without_start_bit() {
+        let mut serial = Serial::new();
+
+        serial.write_byte(0xFF01, b'A');
+        serial.write_byte(0xFF02, 0x01);
+
+        assert_eq!(serial.get_output(), "");
+        assert_eq!(serial.read_byte(0xFF01), b'A');
+        assert_eq!(serial.interrupt_flags, 0);
+    }
+}
@@ -0,0 +1,212 @@
+// https://gbdev.io/pandocs/#sgb-command-packet
+// Super Game Boy games talk to the SNES through the P14 and P15 lines of the joypad register.
+// A packet starts with both lines low, then each of its 128 bits is P15 low for a 0 or P14 low
+// for a 1 with both lines high in between, least significant bit of every byte first. A 0 bit
+// ends the packet. Only the reception is emulated, the commands themselves are not acted on.
+
+pub const PACKET_SIZE: usize = 16;
+
+// The command in the upper 5 bits of the first byte of a packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SgbCommand {
+    Pal01,
+    Pal23,
+    Pal03,
+    Pal12,
+    AttrBlk,
+    AttrLin,
+    AttrDiv,
+    AttrChr,
+    Sound,
+    SouTrn,
+    PalSet,
+    PalTrn,
+    AtrcEn,
+    TestEn,
+    IconEn,
+    DataSnd,
+    DataTrn,
+    MltReq,
+    Jump,
+    ChrTrn,
+    PctTrn,
+    AttrTrn,
+    AttrSet,
+    MaskEn,
+    ObjTrn,
+    Unknown(u8),
+}
+
+impl SgbCommand {
+
+    pub fn from_code(code: u8) -> SgbCommand {
+        return match code {
+            0x00 => SgbCommand::Pal01,
+            0x01 => SgbCommand::Pal23,
+            0x02 => SgbCommand::Pal03,
+            0x03 => SgbCommand::Pal12,
+            0x04 => SgbCommand::AttrBlk,
+            0x05 => SgbCommand::AttrLin,
+            0x06 => SgbCommand::AttrDiv,
+            0x07 => SgbCommand::AttrChr,
+            0x08 => SgbCommand::Sound,
+            0x09 => SgbCommand::SouTrn,
+            0x0A => SgbCommand::PalSet,
+            0x0B => SgbCommand::PalTrn,
+            0x0C => SgbCommand::AtrcEn,
+            0x0D => SgbCommand::TestEn,
+            0x0E => SgbCommand::IconEn,
+            0x0F => SgbCommand::DataSnd,
+            0x10 => SgbCommand::DataTrn,
+            0x11 => SgbCommand::MltReq,
+            0x12 => SgbCommand::Jump,
+            0x13 => SgbCommand::ChrTrn,
+            0x14 => SgbCommand::PctTrn,
+            0x15 => SgbCommand::AttrTrn,
+            0x16 => SgbCommand::AttrSet,
+            0x17 => SgbCommand::MaskEn,
+            0x18 => SgbCommand::ObjTrn,
+            code => SgbCommand::Unknown(code),
+        };
+    }
+}
+
+// A complete command, data holds every packet it took including the command byte
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SgbPacket {
+    pub command: SgbCommand,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SgbReceiver {
+    receiving: bool, // Between a reset pulse and the stop bit
+    released: bool, // Both lines went high since the last bit, the next low line is a new bit
+    bit: usize, // Of the current packet
+    packet: [u8; PACKET_SIZE],
+    data: Vec<u8>, // The packets received so far of a command that spans several
+    commands: Vec<SgbPacket>,
+}
+
+#[allow(unused)]
+impl SgbReceiver {
+
+    pub fn new() -> Self {
+        return SgbReceiver::default();
+    }
+
+    // Takes every value written to 0xFF00
+    pub fn write_joypad(&mut self, value: u8) {
+        match value & 0x30 {
+            0x00 => {
+                self.receiving = true;
+                self.released = false;
+                self.bit = 0;
+                self.packet = [0; PACKET_SIZE];
+            },
+            0x30 => self.released = self.receiving,
+            lines => {
+                if !self.released { return; }
+                self.released = false;
+
+                let one = lines == 0x10;
+                if self.bit < PACKET_SIZE * 8 {
+                    if one { self.packet[self.bit / 8] |= 1 << (self.bit % 8); }
+                    self.bit += 1;
+                    return;
+                }
+
+                self.receiving = false;
+                if !one { self.packet_received(); }
+            },
+        }
+    }
+
+    fn packet_received(&mut self) {
+        self.data.extend_from_slice(&self.packet);
+
+        // The low 3 bits of the first byte give the number of packets of the command
+        let packets = (self.data[0] & 0x07).max(1) as usize;
+        if self.data.len() < packets * PACKET_SIZE { return; }
+
+        let data = std::mem::take(&mut self.data);
+        self.commands.push(SgbPacket { command: SgbCommand::from_code(data[0] >> 3), data });
+    }
+
+    // The commands received since the last call, oldest first
+    pub fn take_commands(&mut self) -> Vec<SgbPacket> {
+        return std::mem::take(&mut self.commands);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The joypad writes that send packet
+    fn pulse_train(packet: &[u8; PACKET_SIZE]) -> Vec<u8> {
+        let mut writes = vec![0x00, 0x30];
+        for index in 0 .. PACKET_SIZE * 8 {
+            let one = packet[index / 8] & (1 << (index % 8)) != 0;
+            writes.push(if one { 0x10 } else { 0x20 });
+            writes.push(0x30);
+        }
+        writes.extend_from_slice(&[0x20, 0x30]);
+        return writes;
+    }
+
+    #[test]
+    fn decodes_a_single_packet_command() {
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = 0x11 << 3 | 1; // MLT_REQ, 1 packet
+        packet[1] = 0x01; // Two players
+
+        let mut receiver = SgbReceiver::new();
+        for value in pulse_train(&packet) {
+            receiver.write_joypad(value);
+        }
+
+        assert_eq!(receiver.take_commands(), vec![SgbPacket { command: SgbCommand::MltReq, data: packet.to_vec() }]);
+        assert!(receiver.take_commands().is_empty());
+    }
+
+    #[test]
+    fn collects_every_packet_of_a_command() {
+        let mut first = [0xA5; PACKET_SIZE];
+        first[0] = 0x04 << 3 | 2; // ATTR_BLK, 2 packets
+        let second = [0x3C; PACKET_SIZE];
+
+        let mut receiver = SgbReceiver::new();
+        for value in pulse_train(&first) {
+            receiver.write_joypad(value);
+        }
+        assert!(receiver.take_commands().is_empty());
+        for value in pulse_train(&second) {
+            receiver.write_joypad(value);
+        }
+
+        let commands = receiver.take_commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].command, SgbCommand::AttrBlk);
+        assert_eq!(commands[0].data, [first, second].concat());
+    }
+
+    #[test]
+    fn joypad_polling_is_not_a_packet() {
+        let mut receiver = SgbReceiver::new();
+        for _ in 0 .. 200 {
+            for &value in [0x20, 0x10, 0x30].iter() {
+                receiver.write_joypad(value);
+            }
+        }
+        assert!(receiver.take_commands().is_empty());
+
+        // Neither is a packet cut short by a reset pulse
+        let mut writes = pulse_train(&[0; PACKET_SIZE]);
+        writes.insert(100, 0x00);
+        for value in writes {
+            receiver.write_joypad(value);
+        }
+        assert!(receiver.take_commands().is_empty());
+    }
+}
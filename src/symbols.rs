@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolError {
+    FileNotFound,
+    InvalidLine(usize), // 1 based
+    UnknownSymbol(String),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::FileNotFound => write!(f, "symbol file not found"),
+            SymbolError::InvalidLine(line) => write!(f, "line {} is not of the form BB:AAAA LABEL", line),
+            SymbolError::UnknownSymbol(name) => write!(f, "no symbol named '{}'", name),
+        }
+    }
+}
+
+// Labels of a .sym file as written by RGBDS and read by SameBoy and BGB, one per line:
+//
+//   ; comment
+//   00:0150 Start
+//   01:4000 Main.loop
+//
+// The bank is the ROM bank for 0x4000 - 0x7FFF and the WRAM bank for 0xD000 - 0xDFFF, it is 0
+// everywhere else.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    labels: HashMap<(u16, u16), String>, // (bank, address) to the first label found there
+    addresses: HashMap<String, (u16, u16)>,
+}
+
+impl SymbolTable {
+
+    pub fn new() -> Self {
+        return SymbolTable {
+            labels: HashMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() { continue; }
+
+            let invalid = SymbolError::InvalidLine(index + 1);
+            let mut fields = line.split_whitespace();
+            let location = fields.next().ok_or(invalid.clone())?;
+            let name = fields.next().ok_or(invalid.clone())?;
+            if fields.next().is_some() { return Err(invalid); }
+
+            let (bank, address) = location.split_once(':').ok_or(invalid.clone())?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid.clone())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid.clone())?;
+
+            table.labels.entry((bank, address)).or_insert_with(|| name.to_string());
+            table.addresses.insert(name.to_string(), (bank, address));
+        }
+
+        return Ok(table);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.labels.is_empty();
+    }
+
+    // The label at address in bank, see Console::mapped_bank for the bank
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        return self.labels.get(&(bank, address)).map(String::as_str);
+    }
+
+    // Bank and address of the label called name
+    pub fn address(&self, name: &str) -> Option<(u16, u16)> {
+        return self.addresses.get(name).copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_banked_labels() {
+        let table = SymbolTable::parse("; rgblink\n00:0150 Start\n\n01:4000 Main.loop ; inner loop\n02:4000 Other\n00:0150 Entry\n").unwrap();
+
+        assert_eq!(table.label(0x00, 0x0150), Some("Start"));
+        assert_eq!(table.label(0x01, 0x4000), Some("Main.loop"));
+        assert_eq!(table.label(0x02, 0x4000), Some("Other"));
+        assert_eq!(table.label(0x03, 0x4000), None);
+        assert_eq!(table.address("Entry"), Some((0x00, 0x0150)));
+        assert_eq!(table.address("Other"), Some((0x02, 0x4000)));
+        assert_eq!(table.address("Missing"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(SymbolTable::parse("00:0150 Start\n0150 Start").unwrap_err(), SymbolError::InvalidLine(2));
+        assert_eq!(SymbolTable::parse("00:XYZ0 Start").unwrap_err(), SymbolError::InvalidLine(1));
+        assert_eq!(SymbolTable::parse("00:0150").unwrap_err(), SymbolError::InvalidLine(1));
+        assert_eq!(SymbolTable::parse("00:0150 Two Labels").unwrap_err(), SymbolError::InvalidLine(1));
+        assert!(SymbolTable::parse("; only a comment\n").unwrap().is_empty());
+    }
+}
@@ -1,99 +1,298 @@
 use wasm_bindgen::prelude::*;
 use crate::console::GameboyType;
+use serde_derive::{Serialize, Deserialize};
 
 #[wasm_bindgen]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Timer {
-    div:  u8,
+    // Incremented every clock, DIV is its upper byte
+    system_counter: u16,
     tima: u8,
     tma:  u8,
-    tac:  u32,
-    enabled: bool,
-    divider_counter: u32,
-    timer_counter: u32,
+    tac:  u8,
+    // Clocks until an overflowed TIMA is reloaded from TMA, TIMA reads 0 meanwhile
+    reload_delay: u8,
+    // Clocks left in the machine cycle that reloaded TIMA
+    reload_window: u8,
     pub interrupt_flags: u8,
 }
 
-// https://www.coranac.com/tonc/text/timers.htm#sec-intro
+// https://gbdev.io/pandocs/#timer-overflow-behaviour
+// The reload happens one machine cycle after the overflow and lasts for one more
+const TIMA_RELOAD_TICKS: u8 = 4;
+
+// https://gbdev.io/pandocs/#timer-and-divider-registers
+// https://hacktix.github.io/GBEDG/timers/
 impl Timer {
 
     pub fn new() -> Timer {
         Timer {
-            div: 0,
+            system_counter: 0,
             tima: 0,
             tma: 0,
-            tac: 256,
-            enabled: true,
-            divider_counter: 0,
-            timer_counter: 0,
+            tac: 0,
+            reload_delay: 0,
+            reload_window: 0,
             interrupt_flags: 0,
         }
     }
 
     pub fn reset(&mut self, model: GameboyType) {
-        self.div = 0;
+        self.system_counter = 0;
         self.tima = 0;
         self.tma = 0;
-        self.tac = 256;
-        self.enabled = true;
-        self.divider_counter = 0;
-        self.timer_counter = 0;
+        self.tac = 0;
+        self.reload_delay = 0;
+        self.reload_window = 0;
         self.interrupt_flags = 0;
     }
 
     pub fn read_byte(&self, address: u16) -> u8 {
         match address {
-            0xFF04 => self.div,
+            0xFF04 => (self.system_counter >> 8) as u8,
             0xFF05 => self.tima,
             0xFF06 => self.tma,
-            0xFF07 => {
-                (if self.enabled { 0x4 } else { 0 }) |
-                    (match self.tac { 16 => 1, 64 => 2, 256 => 3, _ => 0 })
-            }
+            0xFF07 => self.tac,
             _ => panic!("{:4X}", address),
         }
     }
 
+    // Resetting the counter or changing TAC can drop the selected bit, which the
+    // falling edge detector sees as a regular TIMA increment
     pub fn write_byte(&mut self, address: u16, value: u8) {
+        let input = self.timer_input();
+
         match address {
-            0xFF04 => { self.div = 0; },
-            0xFF05 => { self.tima = value; },
-            0xFF06 => { self.tma = value; },
-            0xFF07 => {
-                self.enabled = value & 0x4 != 0;
-                self.tac = match value & 0x3 { 1 => 16, 2 => 64, 3 => 256, _ => 1024 };
+            0xFF04 => { self.system_counter = 0; },
+            0xFF05 => {
+                // A write before the reload cancels it, one during the reload is overwritten by TMA
+                if self.reload_window == 0 { self.tima = value; }
+                self.reload_delay = 0;
             },
+            0xFF06 => {
+                self.tma = value;
+                if self.reload_window > 0 { self.tima = value; }
+            },
+            0xFF07 => { self.tac = value & 0x07; },
             _ => panic!("{:4X}", address),
         };
+
+        if input && !self.timer_input() {
+            self.increment_tima();
+        }
     }
 
-    // https://www.coranac.com/tonc/text/timers.htm#sec-intro
-    // https://hacktix.github.io/GBEDG/timers/
     pub fn execute_ticks(&mut self, ticks: u32) {
-        self.divider_counter += ticks;
-
-        if self.enabled {
-            self.timer_counter += ticks;
+        for _ in 0 .. ticks {
+            self.execute_tick();
         }
-
-        self.execute_tick();
     }
 
     pub fn execute_tick(&mut self) {
-        while self.divider_counter >= 256 {
-            self.div = self.div.wrapping_add(1);
-            self.divider_counter -= 256;
+        if self.reload_window > 0 {
+            self.reload_window -= 1;
         }
 
-        if self.enabled {
-            while self.timer_counter >= self.tac {
-                self.tima = self.tima.wrapping_add(1);
-                if self.tima == 0 {
-                    self.tima = self.tma;
-                    self.interrupt_flags |= 0x04;
-                }
-                self.timer_counter -= self.tac;
+        if self.reload_delay > 0 {
+            self.reload_delay -= 1;
+            if self.reload_delay == 0 {
+                self.tima = self.tma;
+                self.interrupt_flags |= 0x04;
+                self.reload_window = TIMA_RELOAD_TICKS;
             }
         }
+
+        let input = self.timer_input();
+        self.system_counter = self.system_counter.wrapping_add(1);
+
+        if input && !self.timer_input() {
+            self.increment_tima();
+        }
+    }
+
+    // https://gbdev.io/pandocs/#timer-obscure-behaviour
+    // TIMA counts the falling edges of one counter bit ANDed with the enable flag
+    fn timer_input(&self) -> bool {
+        if self.tac & 0x04 == 0 { return false; }
+
+        let bit = match self.tac & 0x03 {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            _ => 7, // 16384 Hz
+        };
+
+        return self.system_counter & (1 << bit) != 0;
+    }
+
+    fn increment_tima(&mut self) {
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0 {
+            self.reload_delay = TIMA_RELOAD_TICKS;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_is_upper_byte_of_system_counter() {
+        let mut timer = Timer::new();
+
+        timer.execute_ticks(255);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+
+        timer.execute_ticks(1);
+        assert_eq!(timer.read_byte(0xFF04), 1);
+
+        timer.execute_ticks(256 * 255);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+    }
+
+    #[test]
+    fn div_write_resets_whole_counter() {
+        let mut timer = Timer::new();
+        timer.execute_ticks(0x1FF);
+
+        timer.write_byte(0xFF04, 0x12);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+
+        // The low bits were cleared too, so the next increment is a full period away
+        timer.execute_ticks(255);
+        assert_eq!(timer.read_byte(0xFF04), 0);
+        timer.execute_ticks(1);
+        assert_eq!(timer.read_byte(0xFF04), 1);
+    }
+
+    #[test]
+    fn tima_counts_at_selected_frequency() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut timer = Timer::new();
+            timer.write_byte(0xFF07, tac);
+
+            timer.execute_ticks(period - 1);
+            assert_eq!(timer.read_byte(0xFF05), 0, "tac {:02X}", tac);
+            timer.execute_ticks(1);
+            assert_eq!(timer.read_byte(0xFF05), 1, "tac {:02X}", tac);
+            timer.execute_ticks(period * 3);
+            assert_eq!(timer.read_byte(0xFF05), 4, "tac {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn tima_stops_when_disabled() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x01);
+
+        timer.execute_ticks(1024);
+        assert_eq!(timer.read_byte(0xFF05), 0);
+    }
+
+    #[test]
+    fn div_write_with_selected_bit_high_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+
+        // Bit 3 is set halfway through a period
+        timer.execute_ticks(8);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+
+        // With the bit low the write has no effect
+        timer.execute_ticks(4);
+        timer.write_byte(0xFF04, 0);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+    }
+
+    #[test]
+    fn disabling_timer_with_selected_bit_high_increments_tima() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF07, 0x05);
+        timer.execute_ticks(8);
+
+        timer.write_byte(0xFF07, 0x01);
+        assert_eq!(timer.read_byte(0xFF05), 1);
+    }
+
+    #[test]
+    fn tima_overflow_reloads_tma_and_requests_interrupt() {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF06, 0xF0);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF07, 0x05);
+
+        timer.execute_ticks(16);
+        assert_eq!(timer.read_byte(0xFF05), 0x00);
+
+        timer.execute_ticks(4);
+        assert_eq!(timer.read_byte(0xFF05), 0xF0);
+        assert_eq!(timer.interrupt_flags, 0x04);
+    }
+
+    fn overflow_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write_byte(0xFF06, 0x80);
+        timer.write_byte(0xFF05, 0xFF);
+        timer.write_byte(0xFF07, 0x05);
+        timer.execute_ticks(16);
+        return timer;
+    }
+
+    #[test]
+    fn tima_reads_zero_for_one_machine_cycle_after_overflow() {
+        let mut timer = overflow_timer();
+
+        for _ in 0 .. 3 {
+            assert_eq!(timer.read_byte(0xFF05), 0x00);
+            assert_eq!(timer.interrupt_flags, 0);
+            timer.execute_tick();
+        }
+        assert_eq!(timer.read_byte(0xFF05), 0x00);
+
+        timer.execute_tick();
+        assert_eq!(timer.read_byte(0xFF05), 0x80);
+        assert_eq!(timer.interrupt_flags, 0x04);
+    }
+
+    #[test]
+    fn tima_write_before_reload_cancels_it() {
+        let mut timer = overflow_timer();
+        timer.execute_ticks(2);
+
+        timer.write_byte(0xFF05, 0x42);
+        timer.execute_ticks(4);
+
+        assert_eq!(timer.read_byte(0xFF05), 0x42);
+        assert_eq!(timer.interrupt_flags, 0);
+    }
+
+    #[test]
+    fn tima_write_during_reload_is_ignored() {
+        let mut timer = overflow_timer();
+        timer.execute_ticks(5);
+
+        timer.write_byte(0xFF05, 0x42);
+        assert_eq!(timer.read_byte(0xFF05), 0x80);
+        assert_eq!(timer.interrupt_flags, 0x04);
+
+        // Once the reload cycle is over writes land again
+        timer.execute_ticks(3);
+        timer.write_byte(0xFF05, 0x42);
+        assert_eq!(timer.read_byte(0xFF05), 0x42);
+    }
+
+    #[test]
+    fn tma_write_during_reload_is_copied_to_tima() {
+        let mut timer = overflow_timer();
+        timer.execute_ticks(5);
+
+        timer.write_byte(0xFF06, 0x33);
+        assert_eq!(timer.read_byte(0xFF05), 0x33);
+
+        timer.execute_ticks(3);
+        timer.write_byte(0xFF06, 0x44);
+        assert_eq!(timer.read_byte(0xFF05), 0x33);
     }
 }
@@ -0,0 +1,80 @@
+use crate::console::Console;
+use crate::cartridge::LoadError;
+use crate::joypad::Button;
+use wasm_bindgen::prelude::*;
+
+// Browser facing wrapper, a page only has to load a rom and then call run_frame and draw
+// frame_buffer from requestAnimationFrame. See js/web_console.js.
+#[wasm_bindgen]
+pub struct WebConsole {
+    console: Console,
+}
+
+#[wasm_bindgen]
+impl WebConsole {
+
+    pub fn new() -> Self {
+        return WebConsole {
+            console: Console::new(),
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        self.console.load_from_bytes(rom.to_vec())?;
+        self.console.reset();
+        return Ok(());
+    }
+
+    pub fn run_frame(&mut self) {
+        self.console.execute_frame();
+    }
+
+    // The last completed frame as 160x144 RGBA pixels, ready for ImageData
+    pub fn frame_buffer(&self) -> Vec<u8> {
+        return self.console.frame_rgba().to_vec();
+    }
+
+    // Browsers only allow an AudioContext to start after a user gesture, call this from one
+    #[cfg(target_arch = "wasm32")]
+    pub fn enable_audio(&mut self) -> Result<(), JsValue> {
+        let sink = crate::audio::WebAudioSink::new()?;
+        self.console.set_sample_rate(sink.sample_rate());
+        self.console.set_audio_sink(Box::new(sink));
+        return Ok(());
+    }
+
+    pub fn press_button(&mut self, button: Button) {
+        self.console.press_button(button);
+    }
+
+    pub fn release_button(&mut self, button: Button) {
+        self.console.release_button(button);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::{SCREEN_W, SCREEN_H};
+
+    #[test]
+    fn run_frame_produces_rgba_frame() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100 .. 0x102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+
+        let mut web_console = WebConsole::new();
+        web_console.load_rom(&rom).unwrap();
+        web_console.run_frame();
+        web_console.run_frame();
+
+        let frame = web_console.frame_buffer();
+        assert_eq!(frame.len(), SCREEN_W * SCREEN_H * 4);
+        assert!(frame.chunks_exact(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn load_rom_rejects_truncated_rom() {
+        let mut web_console = WebConsole::new();
+        assert_eq!(web_console.load_rom(&[0; 0x20]), Err(LoadError::HeaderTooShort(0x20)));
+    }
+}
@@ -2,6 +2,7 @@ use wasm_bindgen_test::{wasm_bindgen_test_configure, wasm_bindgen_test};
 use futures::prelude::*;
 use wasm_bindgen::JsValue;
 use wasm_bindgen_futures::JsFuture;
+use rust_webpack_template::console::Console;
 
 wasm_bindgen_test_configure!(run_in_browser);
 
@@ -20,6 +21,15 @@ fn web_test() {
 }
 
 
+// Anything that is not an array of bytes is refused instead of panicking
+#[wasm_bindgen_test]
+fn load_refuses_garbage() {
+    let mut console = Console::new();
+    assert!(console.load(&JsValue::from_str("not a rom")).is_err());
+    assert!(console.load(&JsValue::from(42)).is_err());
+}
+
+
 // This runs a unit test in the browser, and in addition it supports asynchronous Future APIs.
 #[wasm_bindgen_test(async)]
 fn async_test() -> impl Future<Item = (), Error = JsValue> {